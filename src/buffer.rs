@@ -6,17 +6,26 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/.
  */
 
+extern crate uuid;
+
 use std::cmp;
 use std::error;
 use std::fmt;
+use std::fs;
 use std::fs::File;
 use std::io;
 use std::io::{Seek, Read, Write};
 use std::mem;
 use std::path::{Path, PathBuf};
 use std::ptr;
+use std::rc::Rc;
 use std::result;
 
+use highlight::Group;
+use options::{Options, Scope};
+use syntax::{Grammar, Highlighting};
+use undo::{Change, UndoTree};
+
 use self::PageTreeNode::*;
 
 #[cfg(not(test))]
@@ -27,6 +36,9 @@ const PAGE_SIZE: usize = 16;
 // a rather soft limit where it's good time to split a page in two
 const MAX_PAGE_SIZE: usize = (PAGE_SIZE as f64 * 1.5) as usize;
 
+// symlinks followed in a row before giving up, as the chain may be a loop
+const MAX_SYMLINKS: usize = 40;
+
 /*
  * File contents when stored in memory is paginated for quick modification.
  * Pages are indexed by a balanced binary tree reading left to right. That is,
@@ -152,6 +164,20 @@ impl PageTree {
       map(|offset| offset + if go_left { 0 } else { other.length() })
   }
 
+  // Counts the newlines found before |offset|, which is the same as the line
+  // the offset is on.
+  fn newlines_before_offset(&self, offset: usize) -> usize {
+    let (go_left, new_offset) = self.decide_branch_by_offset(offset);
+    let branch = if go_left { &self.left } else { &self.right };
+    branch.as_ref().map(|boxed_node|
+        match **boxed_node {
+          Tree(ref tree) => tree.newlines_before_offset(new_offset),
+          Leaf(ref page) => page.newline_offsets.iter().
+                              take_while(|&&ofs| ofs < new_offset).count(),
+        }).
+      unwrap_or(0) + if go_left { 0 } else { self.left.newlines() }
+  }
+
   fn get_char_by_line_column(&self, line: usize, column: usize)
       -> Option<char> {
     self.line_column_to_offset(line, column).and_then(
@@ -770,7 +796,10 @@ pub enum Error {
 
 impl fmt::Display for Error {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "{:?}", *self)
+    match *self {
+      Error::IoError(ref err) => write!(f, "{}", err),
+      _                       => write!(f, "{:?}", *self),
+    }
   }
 }
 
@@ -787,53 +816,111 @@ impl error::Error for Error {
 
 pub type Result<T> = result::Result<T, Error>;
 
+/*
+ * An edit performed on the buffer by undo or redo. The location of an edit is
+ * given as it was in the buffer right before that edit was performed.
+ */
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub enum Edit {
+  Insert(String, usize, usize),  // string, line, column
+  Delete(usize, usize, usize, usize),  // start line/column, end line/column
+}
+
 /*
  * The buffer is used to open, modify and write files back to disk.
+ * Modifications are recorded in the buffer's undo history. Recorded changes
+ * make up a single undo step until committed. Each buffer has its own values of
+ * the options local to buffers, such as tabstop. Given a grammar the buffer
+ * keeps its lines highlighted as they are modified.
  */
 pub struct Buffer {
   path: Option<PathBuf>,
   tree: PageTree,
+  history: UndoTree,
+  saved_state: usize,  // the state of the history as last written to path
+  options: Options,
+  highlighting: Option<Highlighting>,
 }
 
 impl Buffer {
   #[cfg(test)]
   pub fn new() -> Buffer {
-    let mut buffer = Buffer { path: None, tree: PageTree::new() };
-    buffer.insert_at_offset("\n".to_string(), 0);
+    let mut buffer = Buffer::with_tree(None, PageTree::new());
+    buffer.apply_insert("\n".to_string(), 0);
     return buffer;
   }
 
   pub fn open(path: &Path) -> Result<Buffer> {
     PageStream::new(path).
     and_then(PageTree::build).
-    and_then(|tree| Ok(Buffer { path: Some(path.to_path_buf()), tree: tree })).
+    and_then(|tree| Ok(Buffer::with_tree(Some(path.to_path_buf()), tree))).
     map(|mut buffer| { buffer.ensure_ends_with_newline(); buffer }).
     map_err(|io_err| Error::IoError(io_err))
   }
 
+  fn with_tree(path: Option<PathBuf>, tree: PageTree) -> Buffer {
+    let history = UndoTree::new();
+    let saved_state = history.state();
+    Buffer {
+      path: path, tree: tree, history: history, saved_state: saved_state,
+      options: Options::new().local(Scope::Buffer),
+      highlighting: None,
+    }
+  }
+
   fn ensure_ends_with_newline(&mut self) {
     let ends_with_newline = self.tree.length > 0 &&
       self.tree.get_char_by_offset(self.tree.length - 1).map(|c| c == '\n').
       expect("Found no last character in buffer of non-zero length.");
     if !ends_with_newline {
       let offset = self.tree.length;
-      self.insert_at_offset("\n".to_string(), offset);
+      self.apply_insert("\n".to_string(), offset);
     }
   }
 
-  pub fn write(&self) -> Result<()> {
+  pub fn write(&mut self) -> Result<()> {
     self.path.as_ref().
-    map_or(Err(Error::NoPath), |path| self.write_to(path))
-  }
-
+    map_or(Err(Error::NoPath), |path| self.write_to(path)).
+    map(|_| {
+      self.history.commit();
+      self.saved_state = self.history.state(); })
+  }
+
+  // Writing is done to a temporary file in the same directory as the target,
+  // which is then renamed over the target, and the directory synced if it can
+  // be so that the rename survives a crash. This way a failed write never
+  // leaves a partially written file behind. Symlinks are written through, even
+  // those whose target doesn't exist yet, and the permissions of an existing
+  // target are kept.
   pub fn write_to(&self, path: &Path) -> Result<()> {
-    File::create(path).
-    and_then(|mut file|
-      self.tree.iter().
-      map(|page| file.write_all(page.data.as_bytes())).
-      fold(Ok(()),
-        |ok, err| if ok.is_ok() && err.is_err() { err } else { ok })).
-    map_err(|io_err| Error::IoError(io_err))
+    let target = fs::canonicalize(path).unwrap_or(follow_symlinks(path));
+    let dir = match target.parent() {
+      Some(dir) if dir != Path::new("") => dir.to_path_buf(),
+      _                                 => PathBuf::from("."),
+    };
+    let file_name = try!(target.file_name().ok_or(Error::IoError(
+      io::Error::new(io::ErrorKind::InvalidInput, "Path lacks a file name."))));
+    let temp_path = dir.join(format!(".{}.{}.tmp", file_name.to_string_lossy(),
+                                     uuid::Uuid::new_v4()));
+
+    let result = File::create(&temp_path).
+      and_then(|mut file|
+        self.tree.iter().
+        map(|page| file.write_all(page.data.as_bytes())).
+        fold(Ok(()),
+          |ok, err| if ok.is_ok() && err.is_err() { err } else { ok }).
+        and_then(|_| file.sync_all())).
+      and_then(|_| match fs::metadata(&target) {
+        Ok(metadata) => fs::set_permissions(&temp_path, metadata.permissions()),
+        Err(_)       => Ok(()),  // a new file has no permissions to keep
+      }).
+      and_then(|_| fs::rename(&temp_path, &target));
+    if result.is_err() { fs::remove_file(&temp_path).ok(); }
+    try!(result.map_err(|io_err| Error::IoError(io_err)));
+    // the file is written once renamed, so failing to sync the directory,
+    // which some systems don't allow, is no reason to report otherwise
+    File::open(&dir).and_then(|dir| dir.sync_all()).ok();
+    Ok(())
   }
 
   #[cfg(not(test))]
@@ -841,6 +928,34 @@ impl Buffer {
     self.path.as_ref().map(|path| path.as_path()).ok_or(Error::NoPath)
   }
 
+  pub fn options(&self) -> &Options {
+    &self.options
+  }
+
+  pub fn options_mut(&mut self) -> &mut Options {
+    &mut self.options
+  }
+
+  // Highlights the buffer by |grammar| from now on, or no longer if none.
+  pub fn set_grammar(&mut self, grammar: Option<Rc<Grammar>>) {
+    self.highlighting = grammar.map(|grammar| {
+      let mut highlighting = Highlighting::new(grammar);
+      highlighting.update(LineIterator::new(&self.tree));
+      highlighting });
+  }
+
+  // the highlight group of each character on a line, none if not highlighted
+  pub fn highlights_on_line(&self, line: usize) -> &[Option<Group>] {
+    self.highlighting.as_ref().
+    map(|highlighting| highlighting.groups_on_line(line)).unwrap_or(&[])
+  }
+
+  // Whether the buffer has changed since it was last written to its path. Undo
+  // and redo back to the written state makes the buffer unmodified again.
+  pub fn is_modified(&self) -> bool {
+    self.history.has_pending() || self.history.state() != self.saved_state
+  }
+
   pub fn insert_at_line_column(&mut self, string: String, line: usize,
                                column: usize) -> Result<()> {
     self.tree.line_column_to_offset(line, column).
@@ -848,7 +963,14 @@ impl Buffer {
     ok_or(Error::BadLocation)
   }
 
-  pub fn insert_at_offset(&mut self, string: String, mut offset: usize) {
+  pub fn insert_at_offset(&mut self, string: String, offset: usize) {
+    self.history.record(Change::Insert(offset, string.clone()));
+    self.apply_insert(string, offset);
+  }
+
+  fn apply_insert(&mut self, string: String, mut offset: usize) {
+    let line = self.tree.newlines_before_offset(offset);
+    let added = string.matches('\n').count();
     if string.len() > PAGE_SIZE {
       for chunk in StringChunkerator::new(string, PAGE_SIZE) {
         let chunk_length = chunk.chars().count();
@@ -859,20 +981,98 @@ impl Buffer {
     else {
       self.tree.insert_string_at_offset(string, offset);
     }
+    self.highlight_edit(line, 0, added);
   }
 
   pub fn delete_range(&mut self, start_line: usize, start_column: usize,
                       end_line: usize, end_column: usize) -> Result<()> {
+    self.range_offsets(start_line, start_column, end_line, end_column).
+    map(|(start, end)| {
+      let deleted = CharIterator::new(&self.tree, start, end).collect();
+      self.history.record(Change::Delete(start, deleted));
+      self.apply_delete(start, end) }).
+    ok_or(Error::BadLocation)
+  }
+
+  // copies the text between two locations, the end being exclusive
+  pub fn get_range(&self, start_line: usize, start_column: usize,
+                   end_line: usize, end_column: usize) -> Result<String> {
+    self.range_offsets(start_line, start_column, end_line, end_column).
+    map(|(start, end)| CharIterator::new(&self.tree, start, end).collect()).
+    ok_or(Error::BadLocation)
+  }
+
+  // finds the offsets of a non-empty range, the end being exclusive
+  fn range_offsets(&self, start_line: usize, start_column: usize,
+                   end_line: usize, end_column: usize)
+      -> Option<(usize, usize)> {
     self.tree.line_column_to_offset(start_line, start_column).
     and_then(|start|
       self.tree.line_column_to_offset(end_line, end_column).
       and_then(|end| if end < self.tree.length { Some(end) } else { None }).
       map(|end| (start, end))).
     and_then(|(start, end)|
-      if start < end { Some((start, end)) } else { None }).
-    map(|(start, mut end)|
-      while start < end { end -= self.tree.delete_range(start, end); } ).
-    ok_or(Error::BadLocation)
+      if start < end { Some((start, end)) } else { None })
+  }
+
+  fn apply_delete(&mut self, start: usize, mut end: usize) {
+    let line = self.tree.newlines_before_offset(start);
+    let removed = self.tree.newlines_before_offset(end) - line;
+    while start < end { end -= self.tree.delete_range(start, end); }
+    self.highlight_edit(line, removed, 0);
+  }
+
+  // Highlights the lines of an edit at |line| replacing |removed| newlines by
+  // |added| ones, and those following until the highlighting is unchanged.
+  fn highlight_edit(&mut self, line: usize, removed: usize, added: usize) {
+    if let Some(ref mut highlighting) = self.highlighting {
+      highlighting.edit(line, removed, added);
+      highlighting.update(LineIterator::new(&self.tree));
+    }
+  }
+
+  // Makes the changes done since the last commit a single undo step.
+  pub fn commit_changes(&mut self) {
+    self.history.commit();
+  }
+
+  // whether there are changes done since the last commit
+  pub fn has_pending_changes(&self) -> bool {
+    self.history.has_pending()
+  }
+
+  // Reverts the last undo step, returning the edits it took to do so.
+  pub fn undo(&mut self) -> Option<Vec<Edit>> {
+    self.history.undo().map(|changes| self.apply_changes(changes))
+  }
+
+  // Performs the last reverted undo step again, returning the edits it took.
+  pub fn redo(&mut self) -> Option<Vec<Edit>> {
+    self.history.redo().map(|changes| self.apply_changes(changes))
+  }
+
+  fn apply_changes(&mut self, changes: Vec<Change>) -> Vec<Edit> {
+    changes.into_iter().map(|change| match change {
+      Change::Insert(offset, string) => {
+        let (line, column) = self.offset_to_line_column(offset);
+        self.apply_insert(string.clone(), offset);
+        Edit::Insert(string, line, column)
+      }
+      Change::Delete(offset, string) => {
+        let end = offset + string.chars().count();
+        let (start_line, start_column) = self.offset_to_line_column(offset);
+        let (end_line, end_column) = self.offset_to_line_column(end);
+        self.apply_delete(offset, end);
+        Edit::Delete(start_line, start_column, end_line, end_column)
+      }
+    }).collect()
+  }
+
+  fn offset_to_line_column(&self, offset: usize) -> (usize, usize) {
+    let line = self.tree.newlines_before_offset(offset);
+    let line_start = self.tree.offset_of_line_start(line).
+      expect("Found no start of the line containing an offset.");
+    (line, offset - line_start)
   }
 
   pub fn get_char_by_line_column(&self, line: usize, column: usize)
@@ -895,10 +1095,32 @@ impl Buffer {
   }
 }
 
+// The path a chain of symlinks starting at |path| ends at, whether or not
+// anything exists there.
+fn follow_symlinks(path: &Path) -> PathBuf {
+  let mut path = path.to_path_buf();
+  for _ in 0..MAX_SYMLINKS {
+    let target = match fs::symlink_metadata(&path) {
+      Ok(ref metadata) if metadata.file_type().is_symlink() =>
+        fs::read_link(&path),
+      _                                                     => break,
+    };
+    match target {
+      Ok(target) => {
+        let dir = path.parent().map(Path::to_path_buf).
+          unwrap_or(PathBuf::new());
+        path = dir.join(target);
+      }
+      Err(_)     => break,
+    }
+  }
+  path
+}
+
 #[cfg(test)]
 mod test {
   use std::fs::File;
-  use std::path::Path;
+  use std::path::{Path, PathBuf};
 
   use super::*;
 
@@ -1183,4 +1405,140 @@ mod test {
     assert!(buffer.delete_range(0, 0, 4, 0).is_err());
     assert!(buffer.delete_range(2, 0, 0, 0).is_err());
   }
+
+  #[test]
+  fn get_range() {
+    let mut buffer = Buffer::new();
+    buffer.insert_at_offset("first\nsecond".to_string(), 0);
+    assert_eq!(buffer.get_range(0, 2, 1, 3).unwrap(), "rst\nsec");
+    assert_eq!(buffer.get_range(1, 0, 1, 6).unwrap(), "second");
+    assert!(buffer.get_range(1, 3, 1, 3).is_err());
+    // the last newline is not part of any range
+    assert!(buffer.get_range(1, 0, 1, 7).is_err());
+  }
+
+  fn contents(buffer: &Buffer) -> String {
+    buffer.tree.iter().map(|page| page.data.as_str()).collect()
+  }
+
+  #[test]
+  fn undo_redo() {
+    let mut buffer = Buffer::new();
+    buffer.insert_at_offset("abc".to_string(), 0);
+    assert!(buffer.has_pending_changes());
+    buffer.commit_changes();
+    assert!(!buffer.has_pending_changes());
+    buffer.insert_at_offset("\ndef".to_string(), 3);
+    buffer.delete_range(0, 1, 0, 2).unwrap();
+    buffer.commit_changes();
+    assert_eq!(contents(&buffer), "ac\ndef\n");
+    assert_eq!(buffer.undo(), Some(vec!(Edit::Insert("b".to_string(), 0, 1),
+                                        Edit::Delete(0, 3, 1, 3))));
+    assert_eq!(contents(&buffer), "abc\n");
+    assert_eq!(buffer.undo(), Some(vec!(Edit::Delete(0, 0, 0, 3))));
+    assert_eq!(contents(&buffer), "\n");
+    assert_eq!(buffer.undo(), None);
+    assert_eq!(buffer.redo(),
+               Some(vec!(Edit::Insert("abc".to_string(), 0, 0))));
+    assert_eq!(buffer.redo().map(|edits| edits.len()), Some(2));
+    assert_eq!(contents(&buffer), "ac\ndef\n");
+    assert_eq!(buffer.redo(), None);
+  }
+
+  #[test]
+  fn undo_spanning_pages() {
+    let path = Path::new("tests/buffer/delete_big_range.txt");
+    let mut buffer = Buffer::open(&path).unwrap();
+    let original = contents(&buffer);
+    buffer.delete_range(0, 227, 6, 91).unwrap();
+    buffer.insert_at_offset("boop\nboop".to_string(), 100);
+    buffer.undo().unwrap();
+    assert_eq!(contents(&buffer), original);
+    assert!(is_balanced(&buffer.tree));
+  }
+
+  #[test]
+  fn write_to_bad_path() {
+    let buffer = Buffer::new();
+    assert!(buffer.write_to(&Path::new("tests/buffer/nowhere/file")).is_err());
+    assert!(buffer.write_to(&Path::new("/")).is_err());
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn write_keeps_symlinks_and_permissions() {
+    use std::fs;
+    use std::io::Read;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    let target = Path::new("tests/buffer/write_target-result.txt");
+    let link = Path::new("tests/buffer/write_link-result.txt");
+    File::create(&target).unwrap();
+    fs::set_permissions(&target, fs::Permissions::from_mode(0o640)).unwrap();
+    fs::remove_file(&link).ok();
+    symlink("write_target-result.txt", &link).unwrap();
+
+    let mut buffer = Buffer::new();
+    buffer.insert_at_offset("written".to_string(), 0);
+    buffer.write_to(&link).unwrap();
+
+    assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+    let permissions = fs::metadata(&target).unwrap().permissions();
+    assert_eq!(permissions.mode() & 0o777, 0o640);
+    let mut content = String::new();
+    File::open(&target).unwrap().read_to_string(&mut content).unwrap();
+    assert_eq!(content, "written\n");
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn write_through_dangling_symlink() {
+    use std::fs;
+    use std::io::Read;
+    use std::os::unix::fs::symlink;
+    let target = Path::new("tests/buffer/dangling_target-result.txt");
+    let link = Path::new("tests/buffer/dangling_link-result.txt");
+    fs::remove_file(&target).ok();
+    fs::remove_file(&link).ok();
+    symlink("dangling_target-result.txt", &link).unwrap();
+
+    let mut buffer = Buffer::new();
+    buffer.insert_at_offset("created".to_string(), 0);
+    buffer.write_to(&link).unwrap();
+
+    assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+    let mut content = String::new();
+    File::open(&target).unwrap().read_to_string(&mut content).unwrap();
+    assert_eq!(content, "created\n");
+  }
+
+  #[test]
+  fn modified() {
+    let path = Path::new("tests/buffer/lacking_newline.txt");
+    let mut buffer = Buffer::open(&path).unwrap();
+    assert!(!buffer.is_modified());
+    buffer.insert_at_offset("a".to_string(), 0);
+    assert!(buffer.is_modified());
+    buffer.path = Some(PathBuf::from("tests/buffer/modified-result.txt"));
+    buffer.write().unwrap();
+    assert!(!buffer.is_modified());
+    buffer.delete_range(0, 0, 0, 1).unwrap();
+    assert!(buffer.is_modified());
+    buffer.undo();
+    assert!(!buffer.is_modified());
+    buffer.undo();
+    assert!(buffer.is_modified());
+    buffer.redo();
+    assert!(!buffer.is_modified());
+  }
+
+  #[test]
+  fn newlines_before_offset() {
+    let path = Path::new("tests/buffer/line_column_offset.txt");
+    let buffer = Buffer::open(&path).unwrap();
+    let tests = [(0, 0), (15, 0), (16, 1), (44, 1), (51, 5), (53, 7), (62, 8)];
+    for &(offset, expected_line) in tests.iter() {
+      assert_eq!(buffer.tree.newlines_before_offset(offset), expected_line);
+      assert_eq!(buffer.offset_to_line_column(offset).0, expected_line);
+    }
+  }
 }
@@ -13,10 +13,13 @@ use std::cmp;
 use self::unicode_width::UnicodeWidthChar as CharWidth;
 
 use buffer::Buffer;
+use options::Opt;
 
 /*
  * LineUp/Down: move caret a line up or down while trying to preserve the
  *   screen space column
+ * DisplayLineUp/Down: like LineUp/Down but moving by the rows lines are
+ *   displayed in when wrapped
  * CharNext/Prev: move forward/backward on a line
  * Char*Flat: like CharNext/Prev but treating the file as "flat" (disregarding
  *   line breaks)
@@ -25,6 +28,13 @@ use buffer::Buffer;
  * Set: just set a position
  * WeakSet: like Set but preserving a saved column
  * Clamp: clamps first to a valid line then to a valid column on that line
+ * Line: move to the start of a line, or of the last line if beyond it
+ * Word*: move to the start of the next/previous word or the end of the next
+ *   one, when true treating any run of non-blank characters as a word (WORD)
+ * Paragraph*: move to the next/previous empty line
+ * Sentence*: move to the start of the next/previous sentence
+ * SearchChar: search the line for a character, when true repeating an earlier
+ *   search
  */
 #[derive(Clone, Copy, PartialEq)]
 #[cfg_attr(test, derive(Debug))]
@@ -32,6 +42,8 @@ use buffer::Buffer;
 pub enum Adjustment {
   LineUp,
   LineDown,
+  DisplayLineUp(Wrapping),
+  DisplayLineDown(Wrapping),
   CharNext,
   CharNextFlat,
   CharNextAppending,
@@ -42,8 +54,91 @@ pub enum Adjustment {
   Clamp,
   FirstLine,
   LastLine,
+  Line(usize),
   StartOfLine,
   EndOfLine,
+  WordNext(bool),
+  WordPrev(bool),
+  WordEnd(bool),
+  ParagraphNext,
+  ParagraphPrev,
+  SentenceNext,
+  SentencePrev,
+  SearchChar(CharSearch, char, bool),
+}
+
+/*
+ * How lines are wrapped when displayed, rows being |width| screen cells wide.
+ * With linebreak, rows are broken after blanks rather than at any character.
+ */
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct Wrapping {
+  pub width: usize,
+  pub linebreak: bool,
+}
+
+/*
+ * The ways of searching a line for a character. Find moves onto the character
+ * while till stops just before it. Repeating a till search skips the character
+ * if it's right next to the caret, or it wouldn't get anywhere.
+ */
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum CharSearch {
+  Find,
+  FindBackward,
+  Till,
+  TillBackward,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl CharSearch {
+  pub fn reversed(&self) -> CharSearch {
+    match *self {
+      CharSearch::Find         => CharSearch::FindBackward,
+      CharSearch::FindBackward => CharSearch::Find,
+      CharSearch::Till         => CharSearch::TillBackward,
+      CharSearch::TillBackward => CharSearch::Till,
+    }
+  }
+}
+
+/*
+ * How an adjustment spans the text it moves over when used as a motion for an
+ * operator. Exclusive motions leave out the character moved to, inclusive ones
+ * include it and linewise motions span whole lines.
+ */
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum MotionKind {
+  Exclusive,
+  Inclusive,
+  Linewise,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl Adjustment {
+  pub fn motion_kind(&self) -> MotionKind {
+    match *self {
+      Adjustment::LineUp | Adjustment::LineDown |
+      Adjustment::FirstLine | Adjustment::LastLine |
+      Adjustment::Line(_)                            => MotionKind::Linewise,
+      Adjustment::EndOfLine | Adjustment::WordEnd(_) => MotionKind::Inclusive,
+      Adjustment::SearchChar(CharSearch::Find, _, _) |
+      Adjustment::SearchChar(CharSearch::Till, _, _) => MotionKind::Inclusive,
+      _                                              => MotionKind::Exclusive,
+    }
+  }
+
+  // the adjustment to make for each repetition following this one
+  pub fn repeated(&self) -> Adjustment {
+    match *self {
+      Adjustment::SearchChar(search, c, _) =>
+        Adjustment::SearchChar(search, c, true),
+      adjustment                           => adjustment,
+    }
+  }
 }
 
 /*
@@ -114,6 +209,10 @@ impl Caret {
         if line == max_line { (line, column, self.saved_column) }
         else { self.vertical_caret_movement(line, line + 1, buffer) }
       }
+      Adjustment::DisplayLineUp(wrapping)   =>
+        self.display_line_movement(false, wrapping, buffer),
+      Adjustment::DisplayLineDown(wrapping) =>
+        self.display_line_movement(true, wrapping, buffer),
       Adjustment::Set(line, column)     => (line, column, None),
       Adjustment::WeakSet(line, column) => (line, column, self.saved_column),
       Adjustment::Clamp                 => {
@@ -122,11 +221,41 @@ impl Caret {
       }
       Adjustment::FirstLine             => (0, 0, None),
       Adjustment::LastLine              => (buffer.num_lines() - 1, 0, None),
+      Adjustment::Line(line)            =>
+        (clamp(line, buffer.num_lines() as isize - 1), 0, None),
       Adjustment::StartOfLine           => (self.line, 0, None),
       Adjustment::EndOfLine             =>
         buffer.line_length(self.line).map(|line_len|
           (self.line, clamped_column(self.line, line_len, buffer), None)).
         unwrap_or((self.line, self.column, None)),
+      Adjustment::WordNext(big)         => {
+        let (line, column) = word_next(line, column, big, buffer);
+        (line, column, None)
+      }
+      Adjustment::WordPrev(big)         => {
+        let (line, column) = word_prev(line, column, big, buffer);
+        (line, column, None)
+      }
+      Adjustment::WordEnd(big)          => {
+        let (line, column) = word_end(line, column, big, buffer);
+        (line, column, None)
+      }
+      Adjustment::ParagraphNext         => {
+        let (line, column) = paragraph_next(line, buffer);
+        (line, column, None)
+      }
+      Adjustment::ParagraphPrev         =>
+        (paragraph_prev(line, buffer), 0, None),
+      Adjustment::SentenceNext          => {
+        let (line, column) = sentence_next(line, column, buffer);
+        (line, column, None)
+      }
+      Adjustment::SentencePrev          => {
+        let (line, column) = sentence_prev(line, column, buffer);
+        (line, column, None)
+      }
+      Adjustment::SearchChar(search, c, repeated) =>
+        (line, search_char(line, column, search, c, repeated, buffer), None),
     };
     if line != new_line || column != new_column {
       self.line = new_line;
@@ -135,6 +264,51 @@ impl Caret {
     }
   }
 
+  // adjusts the caret as |adjustment| does, repeated |count| times
+  pub fn adjust_repeatedly(&mut self, adjustment: Adjustment, count: usize,
+                           buffer: &Buffer) {
+    for i in 0..count { self.adjust_repetition(adjustment, i, buffer); }
+  }
+
+  // Where |adjustment|, repeated |count| times, takes the caret when it's the
+  // motion of an operator, or None where the motion fails.
+  pub fn motion_target(&self, adjustment: Adjustment, count: usize,
+                       buffer: &Buffer) -> Option<Caret> {
+    let (mut target, mut last_start) = (*self, *self);
+    for i in 0..count {
+      last_start = target;
+      target.adjust_repetition(adjustment, i, buffer);
+    }
+    match adjustment {
+      // moving up or down fails without a line to move to
+      Adjustment::LineUp | Adjustment::LineDown if target.line == self.line =>
+        None,
+      // the last word moved over ending its line is where moving over words
+      // stops, rather than at the start of the next line
+      Adjustment::WordNext(_) if target.line > last_start.line            => {
+        let line_len = buffer.line_length(last_start.line).unwrap_or(0);
+        target.adjust(Adjustment::Set(last_start.line, line_len), buffer);
+        Some(target)
+      }
+      _                                                                    =>
+        Some(target),
+    }
+  }
+
+  // helper function to adjust_repeatedly, making repetition |i| of
+  // |adjustment|, counting from 0, a count taking $ down to a following line
+  fn adjust_repetition(&mut self, adjustment: Adjustment, i: usize,
+                       buffer: &Buffer) {
+    match adjustment {
+      _ if i == 0           => self.adjust(adjustment, buffer),
+      Adjustment::EndOfLine => {
+        self.adjust(Adjustment::LineDown, buffer);
+        self.adjust(Adjustment::EndOfLine, buffer);
+      }
+      _                     => self.adjust(adjustment.repeated(), buffer),
+    }
+  }
+
   // helper function to adjust, restricts the caret column to valid
   // character positions in screen space
   fn vertical_caret_movement(&self, from_line: usize, to_line: usize,
@@ -162,22 +336,384 @@ impl Caret {
                        else { Some(desired_column) };
     return (to_line, buffer_column, saved_column);
   }
+
+  // helper function to adjust, like vertical_caret_movement but moving to the
+  // display row above or below, the screen column kept being that within the
+  // row
+  fn display_line_movement(&self, down: bool, wrapping: Wrapping,
+                           buffer: &Buffer) -> (usize, usize, Option<usize>) {
+    let rows = display_rows(self.line, buffer, wrapping);
+    let row = rows.iter().rposition(|&start| start <= self.column).unwrap_or(0);
+    let max_line = cmp::max(0, buffer.num_lines() as isize - 1) as usize;
+    // find the start of the row to move to, and the start of the one after it
+    let (to_line, start, next_start) =
+      if down && row + 1 < rows.len() {
+        (self.line, rows[row + 1], rows.get(row + 2).cloned())
+      }
+      else if down && self.line < max_line {
+        let to_rows = display_rows(self.line + 1, buffer, wrapping);
+        (self.line + 1, 0, to_rows.get(1).cloned())
+      }
+      else if !down && row > 0 { (self.line, rows[row - 1], Some(rows[row])) }
+      else if !down && self.line > 0 {
+        let to_rows = display_rows(self.line - 1, buffer, wrapping);
+        (self.line - 1, *to_rows.last().unwrap(), None)
+      }
+      else { return (self.line, self.column, self.saved_column) };
+    // find where we want to be on the row in screen space
+    let row_screen_column = |line, start|
+      buffer_to_screen_column(line, start, buffer);
+    let current_screen_column =
+      buffer_to_screen_column(self.line, self.column, buffer) -
+      row_screen_column(self.line, rows[row]);
+    let desired_column = self.saved_column.
+      map(|saved_column| cmp::max(saved_column, current_screen_column)).
+      unwrap_or(current_screen_column);
+    // go back to buffer space, staying on the row
+    let end = next_start.unwrap_or(buffer.line_length(to_line).unwrap_or(0));
+    let max_column = cmp::max(start as isize, end as isize - 1) as usize;
+    let to_start = row_screen_column(to_line, start);
+    let buffer_column = cmp::min(max_column,
+      screen_to_buffer_column(to_line, to_start + desired_column, buffer).
+      unwrap_or(start));
+    // determine whether to save the desired column
+    let final_screen_column =
+      buffer_to_screen_column(to_line, buffer_column, buffer) - to_start;
+    let saved_column = if final_screen_column >= desired_column { None }
+                       else { Some(desired_column) };
+    return (to_line, buffer_column, saved_column);
+  }
+}
+
+/*
+ * The shapes a selection may take. Characterwise it covers everything between
+ * its ends, linewise it covers whole lines and blockwise it covers the
+ * rectangle of screen columns spanned by its ends.
+ */
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum SelectionKind {
+  Char,
+  Line,
+  Block,
+}
+
+/*
+ * A selection spans the buffer between an anchor and a caret, both included.
+ */
+#[derive(Clone, Copy)]
+pub struct Selection {
+  pub kind: SelectionKind,
+  pub anchor: Caret,
+  pub caret: Caret,
+}
+
+impl Selection {
+  // the first and last line touched by the selection
+  pub fn lines(&self) -> (usize, usize) {
+    (cmp::min(self.anchor.line, self.caret.line),
+     cmp::max(self.anchor.line, self.caret.line))
+  }
+
+  // the end of the selection coming first in the buffer, then the other one
+  pub fn ordered_ends(&self) -> (Caret, Caret) {
+    let anchor = (self.anchor.line, self.anchor.column);
+    let caret = (self.caret.line, self.caret.column);
+    if anchor <= caret { (self.anchor, self.caret) }
+    else               { (self.caret, self.anchor) }
+  }
+
+  // Range of buffer columns selected on |line|, the end being exclusive. A
+  // range reaching one beyond the length of the line includes its newline.
+  pub fn columns_on_line(&self, line: usize, buffer: &Buffer)
+      -> Option<(usize, usize)> {
+    let (first_line, last_line) = self.lines();
+    if line < first_line || line > last_line { return None }
+    let line_len = buffer.line_length(line).unwrap_or(0);
+    match self.kind {
+      SelectionKind::Char  => {
+        let (start, end) = self.ordered_ends();
+        let start_col = if line == start.line { start.column } else { 0 };
+        let end_col = if line == end.line { end.column + 1 }
+                      else                { line_len + 1 };
+        Some((start_col, cmp::min(end_col, line_len + 1)))
+      }
+      SelectionKind::Line  => Some((0, line_len + 1)),
+      SelectionKind::Block => {
+        // characters partially inside the block are selected as well
+        let (left, right) = self.block_columns(buffer);
+        let tab_stop = buffer.options().number(Opt::TabStop);
+        buffer.line_iter().from(line).next().and_then(|chars| {
+          let mut range: Option<(usize, usize)> = None;
+          let mut screen_col = 0;
+          for (column, c) in chars.take(line_len).enumerate() {
+            let end_screen_col =
+              screen_col + char_width(c, screen_col, tab_stop);
+            if end_screen_col > left && screen_col <= right {
+              range = Some((range.map(|(start, _)| start).unwrap_or(column),
+                            column + 1));
+            }
+            screen_col = end_screen_col;
+          }
+          range })
+      }
+    }
+  }
+
+  // the first and last screen column of a block selection
+  fn block_columns(&self, buffer: &Buffer) -> (usize, usize) {
+    let span = |caret: Caret| {
+      let start = buffer_to_screen_column(caret.line, caret.column, buffer);
+      let tab_stop = buffer.options().number(Opt::TabStop);
+      let width = buffer.get_char_by_line_column(caret.line, caret.column).
+        map(|c| char_width(c, start, tab_stop)).unwrap_or(1);
+      (start, start + cmp::max(width, 1) - 1)
+    };
+    let (anchor_start, anchor_end) = span(self.anchor);
+    let (caret_start, caret_end) = span(self.caret);
+    (cmp::min(anchor_start, caret_start), cmp::max(anchor_end, caret_end))
+  }
+}
+
+/*
+ * Words are made up of characters of the same class, blanks separating them.
+ * Treating the text as WORDs there are only blanks and non-blanks.
+ */
+#[derive(Clone, Copy, PartialEq)]
+enum CharClass {
+  Blank,
+  Punctuation,
+  Word,
+}
+
+fn char_class(c: char, big: bool) -> CharClass {
+  if c.is_whitespace()                             { CharClass::Blank }
+  else if big || c.is_alphanumeric() || c == '_'   { CharClass::Word }
+  else                                             { CharClass::Punctuation }
+}
+
+// Iterates the characters after a position along with their line and column,
+// newlines included.
+fn chars_after<'l>(line: usize, column: usize, buffer: &'l Buffer)
+    -> Box<Iterator<Item=(usize, usize, char)> + 'l> {
+  Box::new(buffer.line_iter().from(line).enumerate().
+    flat_map(move |(offset, chars)| chars.enumerate().
+      map(move |(col, c)| (line + offset, col, c))).
+    skip_while(move |&(l, col, _)| l == line && col <= column))
+}
+
+// Iterates the characters before a position backwards along with their line
+// and column, newlines included.
+fn chars_before<'l>(line: usize, column: usize, buffer: &'l Buffer)
+    -> Box<Iterator<Item=(usize, usize, char)> + 'l> {
+  Box::new((0..line + 1).rev().
+    flat_map(move |l| {
+      let chars: Vec<char> = buffer.line_iter().from(l).next().
+        map(|chars| chars.collect()).unwrap_or(Vec::new());
+      chars.into_iter().enumerate().rev().map(move |(col, c)| (l, col, c)) }).
+    skip_while(move |&(l, col, _)| l == line && col >= column))
+}
+
+fn is_empty_line(line: usize, buffer: &Buffer) -> bool {
+  buffer.line_length(line) == Some(0)
+}
+
+// Finds the start of the next word, an empty line counting as one. Lacking a
+// next word the end of the buffer is found, just beyond the last character.
+fn word_next(line: usize, column: usize, big: bool, buffer: &Buffer)
+    -> (usize, usize) {
+  let mut prev_class = buffer.get_char_by_line_column(line, column).
+    map(|c| char_class(c, big)).unwrap_or(CharClass::Blank);
+  let mut last = (line, column);
+  for (l, col, c) in chars_after(line, column, buffer) {
+    let class = char_class(c, big);
+    if col == 0 && c == '\n' { return (l, col) }
+    if class != CharClass::Blank && class != prev_class { return (l, col) }
+    prev_class = class;
+    last = (l, col);
+  }
+  last
+}
+
+// finds the start of the previous word, an empty line counting as one
+fn word_prev(line: usize, column: usize, big: bool, buffer: &Buffer)
+    -> (usize, usize) {
+  let mut word_class = None;
+  let mut last = (line, column);
+  for (l, col, c) in chars_before(line, column, buffer) {
+    let class = char_class(c, big);
+    match word_class {
+      Some(word_class) if class != word_class => return last,
+      Some(_)                                 => {}
+      None if class != CharClass::Blank       => word_class = Some(class),
+      None if col == 0 && c == '\n'           => return (l, col),
+      None                                    => {}
+    }
+    last = (l, col);
+  }
+  last
+}
+
+// finds the end of the next word
+fn word_end(line: usize, column: usize, big: bool, buffer: &Buffer)
+    -> (usize, usize) {
+  let mut word_class = None;
+  let mut last = (line, column);
+  for (l, col, c) in chars_after(line, column, buffer) {
+    let class = char_class(c, big);
+    match word_class {
+      Some(word_class) if class != word_class => return last,
+      Some(_)                                 => {}
+      None if class != CharClass::Blank       => word_class = Some(class),
+      None                                    => {}
+    }
+    last = (l, col);
+  }
+  last
+}
+
+// Finds the empty line ending the paragraph at or after |line|. Lacking one
+// the end of the buffer is found, just beyond the last character.
+fn paragraph_next(line: usize, buffer: &Buffer) -> (usize, usize) {
+  let last_line = cmp::max(1, buffer.num_lines()) - 1;
+  let mut line = line;
+  while line < last_line && is_empty_line(line, buffer) { line += 1; }
+  while line < last_line && !is_empty_line(line, buffer) { line += 1; }
+  (line, buffer.line_length(line).unwrap_or(0))
+}
+
+// finds the empty line preceding the paragraph at or before |line|
+fn paragraph_prev(line: usize, buffer: &Buffer) -> usize {
+  let mut line = line;
+  while line > 0 && is_empty_line(line, buffer) { line -= 1; }
+  while line > 0 && !is_empty_line(line, buffer) { line -= 1; }
+  line
+}
+
+// Finds the start of the next sentence. A sentence ends with a '.', '!' or '?',
+// possibly followed by closing parentheses or quotes, and then a blank. Empty
+// lines are boundaries on their own. Lacking a next sentence the end of the
+// buffer is found, just beyond the last character.
+fn sentence_next(line: usize, column: usize, buffer: &Buffer)
+    -> (usize, usize) {
+  #[derive(PartialEq)]
+  enum State { Within, Ending, Ended }
+  let ends = |c| c == '.' || c == '!' || c == '?';
+  let closes = |c| c == ')' || c == ']' || c == '"' || c == '\'';
+  let mut prev_empty = is_empty_line(line, buffer);
+  let mut state = if prev_empty { State::Ended } else { State::Within };
+  let mut last = (line, column);
+  for (l, col, c) in chars_after(line, column, buffer) {
+    let empty = col == 0 && c == '\n';
+    if empty && !prev_empty { return (l, col) }
+    if prev_empty { state = State::Ended; }
+    if state == State::Ended && !c.is_whitespace() { return (l, col) }
+    let blank = c.is_whitespace();
+    state =
+      if ends(c)                                  { State::Ending }
+      else if state == State::Ending && closes(c) { State::Ending }
+      else if state != State::Within && blank     { State::Ended }
+      else                                        { State::Within };
+    prev_empty = empty;
+    last = (l, col);
+  }
+  last
+}
+
+// Finds the start of the sentence at, or if already there before, a position.
+// Sentences are scanned for from the start of the previous paragraph.
+fn sentence_prev(line: usize, column: usize, buffer: &Buffer)
+    -> (usize, usize) {
+  let mut start = line;
+  while start > 0 && !is_empty_line(start - 1, buffer) { start -= 1; }
+  while start > 0 && is_empty_line(start - 1, buffer) { start -= 1; }
+  while start > 0 && !is_empty_line(start - 1, buffer) { start -= 1; }
+  let mut position = (start, 0);
+  if position >= (line, column) { return (line, column) }
+  loop {
+    let next = sentence_next(position.0, position.1, buffer);
+    if next >= (line, column) || next == position { return position }
+    position = next;
+  }
+}
+
+// Searches a line for a character, finding the column to move to. Failing to
+// find it the column stays the same.
+fn search_char(line: usize, column: usize, search: CharSearch, c: char,
+               repeated: bool, buffer: &Buffer) -> usize {
+  let chars: Vec<char> = buffer.line_iter().from(line).next().
+    map(|chars| chars.filter(|&c| c != '\n').collect()).unwrap_or(Vec::new());
+  let skip = if repeated { 1 } else { 0 };
+  let found = match search {
+    CharSearch::Find         =>
+      (column + 1..chars.len()).find(|&col| chars[col] == c),
+    CharSearch::FindBackward =>
+      (0..column).rev().find(|&col| chars[col] == c),
+    CharSearch::Till         =>
+      (column + 1 + skip..chars.len()).find(|&col| chars[col] == c).
+      map(|col| col - 1),
+    CharSearch::TillBackward =>
+      (0..column.saturating_sub(skip)).rev().find(|&col| chars[col] == c).
+      map(|col| col + 1),
+  };
+  found.unwrap_or(column)
+}
+
+// the number of screen cells taken by |c| when starting at |screen_column|, a
+// tab reaching up to the next tab stop
+pub fn char_width(c: char, screen_column: usize, tab_stop: usize) -> usize {
+  if c == '\t' { tab_stop - screen_column % tab_stop }
+  else        { CharWidth::width(c).unwrap_or(0) }
+}
+
+// The buffer columns at which the rows |line| is displayed in start when
+// wrapped, the first being 0. A character not fitting on a row starts the next
+// one, or with linebreak the characters following the last blank on the row do.
+pub fn display_rows(line: usize, buffer: &Buffer, wrapping: Wrapping)
+    -> Vec<usize> {
+  let tab_stop = buffer.options().number(Opt::TabStop);
+  let line_len = buffer.line_length(line).unwrap_or(0);
+  let mut rows = vec!(0);
+  let mut row_screen_column = 0;
+  // the buffer and screen column following the last blank of the row
+  let mut last_break: Option<(usize, usize)> = None;
+  buffer.line_iter().from(line).next().map(|chars| {
+    let mut screen_column = 0;
+    for (column, c) in chars.take(line_len).enumerate() {
+      let width = char_width(c, screen_column, tab_stop);
+      while screen_column + width > row_screen_column + wrapping.width &&
+            column > *rows.last().unwrap() {
+        let (start, start_screen_column) = match last_break.take() {
+          Some(blank) if wrapping.linebreak => blank,
+          _                                 => (column, screen_column),
+        };
+        rows.push(start);
+        row_screen_column = start_screen_column;
+      }
+      screen_column += width;
+      if c == ' ' || c == '\t' {
+        last_break = Some((column + 1, screen_column));
+      }
+    } });
+  rows
 }
 
 // sums up the widths of the characters before the given buffer column
 pub fn buffer_to_screen_column(line: usize, column: usize, buffer: &Buffer)
     -> usize {
+  let tab_stop = buffer.options().number(Opt::TabStop);
   buffer.line_iter().from(line).next().map(|chars|
-    chars.take(column).map(|c| CharWidth::width(c).unwrap_or(0)).sum()).
+    chars.take(column).fold(0, |sum, c| sum + char_width(c, sum, tab_stop))).
   unwrap_or(0)
 }
 
 // scans a line, counting characters up to the given screen column
 pub fn screen_to_buffer_column(row: usize, screen_column: usize,
                                buffer: &Buffer) -> Option<usize> {
+  let tab_stop = buffer.options().number(Opt::TabStop);
   buffer.line_iter().from(row).next().map(|chars|
     chars.filter(|&c| c != '\n').scan(0, |sum, c| {
-      *sum += CharWidth::width(c).unwrap_or(0);
+      *sum += char_width(c, *sum, tab_stop);
       Some(*sum) }).
     take_while(|&sum| sum <= screen_column).count())
 }
@@ -187,6 +723,7 @@ mod test {
   use std::path::Path;
 
   use buffer::Buffer;
+  use options::{Opt, Value};
 
   use super::*;
 
@@ -253,6 +790,11 @@ mod test {
     caret.adjust(Adjustment::CharNextAppending, &buffer);
     assert_eq!(caret.line, 14); assert_eq!(caret.column, 35);
     assert!(caret.saved_column.is_none());
+    // move to a given line, or the last one if beyond it
+    caret.adjust(Adjustment::Line(2), &buffer);
+    assert_eq!(caret.line, 2); assert_eq!(caret.column, 0);
+    caret.adjust(Adjustment::Line(100), &buffer);
+    assert_eq!(caret.line, 14); assert_eq!(caret.column, 0);
   }
 
   #[test]
@@ -287,6 +829,64 @@ mod test {
     assert!(caret.saved_column.is_none());
   }
 
+  #[test]
+  fn tabs() {
+    let mut buffer = Buffer::new();
+    buffer.insert_at_line_column(
+      "\tab\tc\nabcdefghijk\n\t\tx".to_string(), 0, 0).unwrap();
+    assert_eq!(buffer_to_screen_column(2, 2, &buffer), 16);
+    buffer.options_mut().set(Opt::TabStop, Value::Number(4));
+    assert_eq!(buffer_to_screen_column(0, 1, &buffer), 4);
+    assert_eq!(buffer_to_screen_column(0, 3, &buffer), 6);
+    assert_eq!(buffer_to_screen_column(0, 4, &buffer), 8);
+    assert_eq!(screen_to_buffer_column(0, 5, &buffer), Some(2));
+    assert_eq!(screen_to_buffer_column(0, 7, &buffer), Some(3));
+    // the screen column is kept when moving across tabs
+    let mut caret = Caret::new();
+    caret.adjust(Adjustment::Set(1, 9), &buffer);
+    caret.adjust(Adjustment::LineUp, &buffer);
+    assert_eq!(caret.line, 0); assert_eq!(caret.column, 4);
+    assert_eq!(caret.saved_column, Some(9));
+    caret.adjust(Adjustment::LineDown, &buffer);
+    assert_eq!(caret.line, 1); assert_eq!(caret.column, 9);
+    assert!(caret.saved_column.is_none());
+    caret.adjust(Adjustment::Set(1, 6), &buffer);
+    caret.adjust(Adjustment::LineDown, &buffer);
+    assert_eq!(caret.line, 2); assert_eq!(caret.column, 1);
+    assert_eq!(caret.saved_column, Some(6));
+    caret.adjust(Adjustment::LineUp, &buffer);
+    assert_eq!(caret.line, 1); assert_eq!(caret.column, 6);
+  }
+
+  #[test]
+  fn display_lines() {
+    let mut buffer = Buffer::new();
+    buffer.insert_at_line_column(
+      "aaaa bbbb cccc\nx\ndddddddddd".to_string(), 0, 0).unwrap();
+    let wrapping = Wrapping { width: 6, linebreak: false };
+    assert_eq!(display_rows(0, &buffer, wrapping), vec!(0, 6, 12));
+    assert_eq!(display_rows(1, &buffer, wrapping), vec!(0));
+    let linebreak = Wrapping { linebreak: true, .. wrapping };
+    assert_eq!(display_rows(0, &buffer, linebreak), vec!(0, 5, 10));
+    let mut caret = Caret::new();
+    caret.adjust(Adjustment::Set(0, 8), &buffer);
+    caret.adjust(Adjustment::DisplayLineDown(wrapping), &buffer);
+    assert_eq!(caret.line, 0); assert_eq!(caret.column, 13);
+    assert_eq!(caret.saved_column, Some(2));
+    caret.adjust(Adjustment::DisplayLineDown(wrapping), &buffer);
+    assert_eq!(caret.line, 1); assert_eq!(caret.column, 0);
+    caret.adjust(Adjustment::DisplayLineDown(wrapping), &buffer);
+    assert_eq!(caret.line, 2); assert_eq!(caret.column, 2);
+    assert!(caret.saved_column.is_none());
+    caret.adjust(Adjustment::Set(2, 3), &buffer);
+    caret.adjust(Adjustment::DisplayLineUp(wrapping), &buffer);
+    caret.adjust(Adjustment::DisplayLineUp(wrapping), &buffer);
+    assert_eq!(caret.line, 0); assert_eq!(caret.column, 13);
+    caret.adjust(Adjustment::DisplayLineUp(wrapping), &buffer);
+    assert_eq!(caret.line, 0); assert_eq!(caret.column, 9);
+    assert!(caret.saved_column.is_none());
+  }
+
   #[test]
   fn adjust_flat() {
     let buffer =
@@ -319,4 +919,166 @@ mod test {
     assert_eq!(caret.line, 14); assert_eq!(caret.column, 35);
     assert!(caret.saved_column.is_none());
   }
+
+  #[test]
+  fn selection() {
+    let buffer =
+      Buffer::open(&Path::new("tests/caret/selection.txt")).unwrap();
+    let caret_at = |line, column| {
+      let mut caret = Caret::new();
+      caret.adjust(Adjustment::Set(line, column), &buffer);
+      caret
+    };
+    // characterwise, with the anchor after the caret
+    let mut selection = Selection {
+      kind: SelectionKind::Char, anchor: caret_at(1, 2), caret: caret_at(0, 6)
+    };
+    assert_eq!(selection.lines(), (0, 1));
+    assert_eq!(selection.columns_on_line(0, &buffer), Some((6, 11)));
+    assert_eq!(selection.columns_on_line(1, &buffer), Some((0, 3)));
+    assert_eq!(selection.columns_on_line(2, &buffer), None);
+    // linewise
+    selection.kind = SelectionKind::Line;
+    assert_eq!(selection.columns_on_line(0, &buffer), Some((0, 11)));
+    assert_eq!(selection.columns_on_line(1, &buffer), Some((0, 7)));
+    // blockwise, partially covering a double width character
+    selection = Selection {
+      kind: SelectionKind::Block, anchor: caret_at(0, 1), caret: caret_at(3, 1)
+    };
+    assert_eq!(selection.lines(), (0, 3));
+    assert_eq!(selection.columns_on_line(0, &buffer), Some((1, 3)));
+    assert_eq!(selection.columns_on_line(1, &buffer), Some((1, 3)));
+    assert_eq!(selection.columns_on_line(2, &buffer), None);
+    assert_eq!(selection.columns_on_line(3, &buffer), Some((1, 2)));
+    selection.caret = caret_at(3, 0);
+    assert_eq!(selection.columns_on_line(2, &buffer), Some((0, 1)));
+    assert_eq!(selection.columns_on_line(3, &buffer), Some((0, 2)));
+    selection.anchor = caret_at(0, 3);
+    assert_eq!(selection.columns_on_line(3, &buffer), Some((0, 3)));
+  }
+
+  #[test]
+  fn word_motions() {
+    let buffer = Buffer::open(&Path::new("tests/caret/motions.txt")).unwrap();
+    let moved = |adjustment, line, column| {
+      let mut caret = Caret::new();
+      caret.adjust(Adjustment::Set(line, column), &buffer);
+      caret.adjust(adjustment, &buffer);
+      (caret.line, caret.column)
+    };
+    // words of punctuation, across lines and onto an empty line
+    assert_eq!(moved(Adjustment::WordNext(false), 0, 0), (0, 3));
+    assert_eq!(moved(Adjustment::WordNext(false), 0, 3), (0, 4));
+    assert_eq!(moved(Adjustment::WordNext(false), 0, 4), (0, 9));
+    assert_eq!(moved(Adjustment::WordNext(false), 0, 9), (1, 2));
+    assert_eq!(moved(Adjustment::WordNext(false), 1, 8), (1, 10));
+    assert_eq!(moved(Adjustment::WordNext(false), 1, 10), (2, 0));
+    assert_eq!(moved(Adjustment::WordNext(false), 2, 0), (3, 0));
+    assert_eq!(moved(Adjustment::WordNext(true), 0, 0), (0, 9));
+    assert_eq!(moved(Adjustment::WordNext(true), 1, 2), (1, 10));
+    // off the end of the buffer
+    assert_eq!(moved(Adjustment::WordNext(false), 6, 1), (6, 4));
+    // backwards
+    assert_eq!(moved(Adjustment::WordPrev(false), 1, 2), (0, 9));
+    assert_eq!(moved(Adjustment::WordPrev(false), 0, 4), (0, 3));
+    assert_eq!(moved(Adjustment::WordPrev(false), 0, 3), (0, 0));
+    assert_eq!(moved(Adjustment::WordPrev(false), 0, 0), (0, 0));
+    assert_eq!(moved(Adjustment::WordPrev(false), 3, 0), (2, 0));
+    assert_eq!(moved(Adjustment::WordPrev(false), 2, 0), (1, 10));
+    assert_eq!(moved(Adjustment::WordPrev(true), 1, 10), (1, 2));
+    assert_eq!(moved(Adjustment::WordPrev(true), 0, 9), (0, 0));
+    // ends of words
+    assert_eq!(moved(Adjustment::WordEnd(false), 0, 0), (0, 2));
+    assert_eq!(moved(Adjustment::WordEnd(false), 0, 2), (0, 3));
+    assert_eq!(moved(Adjustment::WordEnd(false), 0, 3), (0, 6));
+    assert_eq!(moved(Adjustment::WordEnd(true), 0, 0), (0, 6));
+    assert_eq!(moved(Adjustment::WordEnd(true), 0, 6), (0, 15));
+    assert_eq!(moved(Adjustment::WordEnd(true), 1, 14), (3, 4));
+  }
+
+  #[test]
+  fn paragraph_and_sentence_motions() {
+    let buffer = Buffer::open(&Path::new("tests/caret/motions.txt")).unwrap();
+    let moved = |adjustment, line, column| {
+      let mut caret = Caret::new();
+      caret.adjust(Adjustment::Set(line, column), &buffer);
+      caret.adjust(adjustment, &buffer);
+      (caret.line, caret.column)
+    };
+    assert_eq!(moved(Adjustment::ParagraphNext, 0, 5), (2, 0));
+    assert_eq!(moved(Adjustment::ParagraphNext, 2, 0), (5, 0));
+    assert_eq!(moved(Adjustment::ParagraphNext, 5, 0), (6, 4));
+    assert_eq!(moved(Adjustment::ParagraphPrev, 6, 2), (5, 0));
+    assert_eq!(moved(Adjustment::ParagraphPrev, 5, 0), (2, 0));
+    assert_eq!(moved(Adjustment::ParagraphPrev, 2, 0), (0, 0));
+    // sentences end at blanks following punctuation, or at empty lines
+    assert_eq!(moved(Adjustment::SentenceNext, 1, 10), (2, 0));
+    assert_eq!(moved(Adjustment::SentenceNext, 2, 0), (3, 0));
+    assert_eq!(moved(Adjustment::SentenceNext, 3, 0), (3, 16));
+    assert_eq!(moved(Adjustment::SentenceNext, 3, 16), (3, 29));
+    assert_eq!(moved(Adjustment::SentenceNext, 3, 29), (4, 13));
+    assert_eq!(moved(Adjustment::SentenceNext, 4, 13), (5, 0));
+    assert_eq!(moved(Adjustment::SentenceNext, 5, 0), (6, 0));
+    assert_eq!(moved(Adjustment::SentencePrev, 4, 15), (4, 13));
+    assert_eq!(moved(Adjustment::SentencePrev, 4, 13), (3, 29));
+    assert_eq!(moved(Adjustment::SentencePrev, 3, 0), (2, 0));
+    assert_eq!(moved(Adjustment::SentencePrev, 2, 0), (0, 0));
+    assert_eq!(moved(Adjustment::SentencePrev, 0, 0), (0, 0));
+  }
+
+  #[test]
+  fn repeated_motions() {
+    let buffer = Buffer::open(&Path::new("tests/caret/motions.txt")).unwrap();
+    let target = |adjustment, count, line| {
+      let mut caret = Caret::new();
+      caret.adjust(Adjustment::Set(line, 0), &buffer);
+      caret.motion_target(adjustment, count, &buffer).
+        map(|target| (target.line, target.column))
+    };
+    // a count takes $ to the end of a following line
+    assert_eq!(target(Adjustment::EndOfLine, 1, 0), Some((0, 15)));
+    assert_eq!(target(Adjustment::EndOfLine, 2, 0), Some((1, 14)));
+    let mut caret = Caret::new();
+    caret.adjust_repeatedly(Adjustment::EndOfLine, 2, &buffer);
+    assert_eq!((caret.line, caret.column), (1, 14));
+    // moving up or down fails only if there's no line to move to
+    assert_eq!(target(Adjustment::LineDown, 1, 5), Some((6, 0)));
+    assert_eq!(target(Adjustment::LineDown, 3, 5), Some((6, 0)));
+    assert_eq!(target(Adjustment::LineDown, 1, 6), None);
+    assert_eq!(target(Adjustment::LineUp, 1, 0), None);
+    assert_eq!(target(Adjustment::LineUp, 2, 1), Some((0, 0)));
+    // moving over words stops at the end of a line only when the last word
+    // moved over ends it
+    assert_eq!(target(Adjustment::WordNext(false), 1, 0), Some((0, 3)));
+    assert_eq!(target(Adjustment::WordNext(false), 4, 0), Some((0, 16)));
+    assert_eq!(target(Adjustment::WordNext(false), 5, 0), Some((1, 3)));
+    assert_eq!(target(Adjustment::WordNext(true), 2, 0), Some((0, 16)));
+    assert_eq!(target(Adjustment::WordNext(true), 3, 0), Some((1, 10)));
+  }
+
+  #[test]
+  fn char_search() {
+    let buffer = Buffer::open(&Path::new("tests/caret/motions.txt")).unwrap();
+    let searched = |search, c, repeated, column| {
+      let mut caret = Caret::new();
+      caret.adjust(Adjustment::Set(0, column), &buffer);
+      caret.adjust(Adjustment::SearchChar(search, c, repeated), &buffer);
+      caret.column
+    };
+    assert_eq!(searched(CharSearch::Find, 'a', false, 0), 5);
+    assert_eq!(searched(CharSearch::Find, 'a', false, 5), 10);
+    assert_eq!(searched(CharSearch::Find, 'z', false, 12), 12);
+    assert_eq!(searched(CharSearch::FindBackward, 'o', false, 9), 2);
+    assert_eq!(searched(CharSearch::FindBackward, 'q', false, 9), 9);
+    assert_eq!(searched(CharSearch::Till, 'b', false, 0), 3);
+    // till doesn't get past a character right next to the caret unless repeated
+    assert_eq!(searched(CharSearch::Till, 'b', false, 3), 3);
+    assert_eq!(searched(CharSearch::Till, 'b', true, 3), 8);
+    assert_eq!(searched(CharSearch::TillBackward, 'o', false, 9), 3);
+    assert_eq!(searched(CharSearch::TillBackward, 'o', false, 3), 3);
+    assert_eq!(searched(CharSearch::TillBackward, 'o', true, 3), 2);
+    assert_eq!(CharSearch::Till.reversed(), CharSearch::TillBackward);
+    assert_eq!(Adjustment::SearchChar(CharSearch::Find, 'a', false).repeated(),
+               Adjustment::SearchChar(CharSearch::Find, 'a', true));
+  }
 }
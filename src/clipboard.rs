@@ -0,0 +1,311 @@
+/*
+ * Copyright (c) 2015 Mathias Hällman
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::HashMap;
+#[cfg(not(test))]
+use std::env;
+use std::error;
+use std::fmt;
+use std::io::Write;
+use std::mem;
+use std::process::{Command, Stdio};
+use std::result;
+
+/*
+ * The selections of the desktop, the clipboard being reached through register
+ * + and the primary selection through register *.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(test, derive(Debug))]
+pub enum Selection {
+  Clipboard,
+  Primary,
+}
+
+impl Selection {
+  pub fn from_register(name: char) -> Option<Selection> {
+    match name {
+      '+' => Some(Selection::Clipboard),
+      '*' => Some(Selection::Primary),
+      _   => None,
+    }
+  }
+
+  fn name(&self) -> &'static str {
+    match *self {
+      Selection::Clipboard => "clipboard",
+      Selection::Primary   => "primary",
+    }
+  }
+
+  // the name of the selection in an OSC 52 escape sequence
+  #[cfg(not(test))]
+  pub fn osc52_name(&self) -> char {
+    match *self {
+      Selection::Clipboard => 'c',
+      Selection::Primary   => 'p',
+    }
+  }
+}
+
+/*
+ * A Provider moves text to and from the selections of the desktop.
+ */
+pub trait Provider {
+  fn copy(&mut self, selection: Selection, text: &str) -> Result<()>;
+  fn paste(&mut self, selection: Selection) -> Result<String>;
+
+  // Takes the text copied since last asked which is left to write to the
+  // terminal, along with the selection it was copied to.
+  fn take_terminal_copies(&mut self) -> Vec<(Selection, String)> {
+    Vec::new()
+  }
+}
+
+/*
+ * CommandProvider runs external commands, such as xclip or pbcopy, for each
+ * selection. The copy command reads the text from its standard input while the
+ * paste command writes it to its standard output. Commands are given as a
+ * program followed by its arguments, separated by blanks.
+ */
+pub struct CommandProvider {
+  clipboard: (Vec<String>, Vec<String>),  // the copy and paste commands
+  primary: (Vec<String>, Vec<String>),
+}
+
+impl CommandProvider {
+  pub fn new(clipboard_copy: &str, clipboard_paste: &str, primary_copy: &str,
+             primary_paste: &str) -> CommandProvider {
+    let words = |command: &str|
+      command.split_whitespace().map(|word| word.to_string()).collect();
+    CommandProvider {
+      clipboard: (words(clipboard_copy), words(clipboard_paste)),
+      primary: (words(primary_copy), words(primary_paste)),
+    }
+  }
+
+  // Uses the same copy and paste commands for both selections, %s in them
+  // standing for the name of the selection, clipboard or primary.
+  pub fn for_both(copy: &str, paste: &str) -> CommandProvider {
+    let command = |command: &str, selection: Selection|
+      command.replace("%s", selection.name());
+    CommandProvider::new(&command(copy, Selection::Clipboard),
+                         &command(paste, Selection::Clipboard),
+                         &command(copy, Selection::Primary),
+                         &command(paste, Selection::Primary))
+  }
+
+  fn commands(&self, selection: Selection) -> &(Vec<String>, Vec<String>) {
+    match selection {
+      Selection::Clipboard => &self.clipboard,
+      Selection::Primary   => &self.primary,
+    }
+  }
+}
+
+impl Provider for CommandProvider {
+  fn copy(&mut self, selection: Selection, text: &str) -> Result<()> {
+    let &(ref command, _) = self.commands(selection);
+    let mut child = try!(command_for(command).
+      stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::null()).
+      spawn().map_err(|err| failure(command, err)));
+    try!(child.stdin.take().expect("Child lacked its standard input.").
+      write_all(text.as_bytes()).map_err(|err| failure(command, err)));
+    let status = try!(child.wait().map_err(|err| failure(command, err)));
+    if status.success() { Ok(()) } else { Err(failure(command, status)) }
+  }
+
+  fn paste(&mut self, selection: Selection) -> Result<String> {
+    let &(_, ref command) = self.commands(selection);
+    let output = try!(command_for(command).stdin(Stdio::null()).
+      stderr(Stdio::null()).output().map_err(|err| failure(command, err)));
+    if !output.status.success() {
+      return Err(failure(command, output.status));
+    }
+    String::from_utf8(output.stdout).map_err(|err| failure(command, err))
+  }
+}
+
+fn command_for(words: &[String]) -> Command {
+  let mut command = Command::new(words.first().map(|w| &w[..]).unwrap_or(""));
+  command.args(words.iter().skip(1));
+  command
+}
+
+fn failure<D: fmt::Display>(command: &[String], reason: D) -> Error {
+  Error::CommandFailed(format!("{}: {}", command.join(" "), reason))
+}
+
+/*
+ * Osc52Provider copies by having OSC 52 escape sequences written to the
+ * terminal, which many terminals pass on to the desktop, even over ssh. The
+ * text copied is kept until taken for writing to the screen. Terminals are
+ * seldom willing to tell what's in the selections, so pasting is limited to
+ * what was last copied from here.
+ */
+pub struct Osc52Provider {
+  copied: HashMap<Selection, String>,
+  unwritten: Vec<(Selection, String)>,
+}
+
+impl Osc52Provider {
+  pub fn new() -> Osc52Provider {
+    Osc52Provider { copied: HashMap::new(), unwritten: Vec::new() }
+  }
+}
+
+impl Provider for Osc52Provider {
+  fn copy(&mut self, selection: Selection, text: &str) -> Result<()> {
+    self.unwritten.push((selection, text.to_string()));
+    self.copied.insert(selection, text.to_string());
+    Ok(())
+  }
+
+  fn paste(&mut self, selection: Selection) -> Result<String> {
+    self.copied.get(&selection).cloned().ok_or(Error::CantPaste)
+  }
+
+  fn take_terminal_copies(&mut self) -> Vec<(Selection, String)> {
+    mem::replace(&mut self.unwritten, Vec::new())
+  }
+}
+
+// The provider running the commands given, in which %s stands for the name of
+// the selection, or if none are given one picked for the desktop in use.
+#[cfg(not(test))]
+pub fn configured(copy: &str, paste: &str) -> Box<Provider> {
+  if copy.is_empty() && paste.is_empty() { detect() }
+  else { Box::new(CommandProvider::for_both(copy, paste)) }
+}
+
+// Picks the commands of the first clipboard tool found for the desktop in use,
+// falling back on OSC 52 escape sequences.
+#[cfg(not(test))]
+pub fn detect() -> Box<Provider> {
+  let wayland = env::var_os("WAYLAND_DISPLAY").is_some();
+  let x11 = env::var_os("DISPLAY").is_some();
+  let tools = [
+    (wayland, "wl-copy", "wl-paste --no-newline",
+     "wl-copy --primary", "wl-paste --no-newline --primary"),
+    (x11, "xclip -i -selection clipboard", "xclip -o -selection clipboard",
+     "xclip -i -selection primary", "xclip -o -selection primary"),
+    (x11, "xsel -i -b", "xsel -o -b", "xsel -i -p", "xsel -o -p"),
+    (true, "pbcopy", "pbpaste", "pbcopy", "pbpaste"),
+  ];
+  let in_path = |program: &str| env::var_os("PATH").map(|paths|
+    env::split_paths(&paths).any(|path| path.join(program).is_file())).
+    unwrap_or(false);
+  tools.iter().
+  find(|&&(usable, copy, _, _, _)|
+    usable && copy.split_whitespace().next().map(&in_path).unwrap_or(false)).
+  map(|&(_, clipboard_copy, clipboard_paste, primary_copy, primary_paste)|
+    Box::new(CommandProvider::new(clipboard_copy, clipboard_paste,
+                                  primary_copy, primary_paste))
+      as Box<Provider>).
+  unwrap_or_else(|| Box::new(Osc52Provider::new()))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+  CommandFailed(String),
+  CantPaste,
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Error::CommandFailed(ref reason) =>
+        write!(f, "Clipboard command failed: {}", reason),
+      Error::CantPaste                 =>
+        write!(f, "{}", error::Error::description(self)),
+    }
+  }
+}
+
+impl error::Error for Error {
+  fn description(&self) -> &str {
+    match *self {
+      Error::CommandFailed(_) => "Clipboard command failed",
+      Error::CantPaste        => "Clipboard can't be pasted from the terminal",
+    }
+  }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn fake_provider() -> CommandProvider {
+    CommandProvider::new(
+      "sh tests/clipboard/fake-clipboard.sh copy clipboard",
+      "sh tests/clipboard/fake-clipboard.sh paste clipboard",
+      "sh tests/clipboard/fake-clipboard.sh copy primary",
+      "sh tests/clipboard/fake-clipboard.sh paste primary")
+  }
+
+  #[test]
+  fn copy_and_paste() {
+    let mut provider = fake_provider();
+    provider.copy(Selection::Clipboard, "some\ntext äö\n").unwrap();
+    provider.copy(Selection::Primary, "other text").unwrap();
+    assert_eq!(provider.paste(Selection::Clipboard),
+               Ok("some\ntext äö\n".to_string()));
+    assert_eq!(provider.paste(Selection::Primary),
+               Ok("other text".to_string()));
+  }
+
+  #[test]
+  fn same_commands_for_both() {
+    let mut provider = CommandProvider::for_both(
+      "sh tests/clipboard/fake-clipboard.sh copy both-%s",
+      "sh tests/clipboard/fake-clipboard.sh paste both-%s");
+    provider.copy(Selection::Clipboard, "copied").unwrap();
+    provider.copy(Selection::Primary, "selected").unwrap();
+    assert_eq!(provider.paste(Selection::Clipboard),
+               Ok("copied".to_string()));
+    assert_eq!(provider.paste(Selection::Primary),
+               Ok("selected".to_string()));
+  }
+
+  #[test]
+  fn failing_commands() {
+    let mut provider = CommandProvider::new(
+      "sh tests/clipboard/fake-clipboard.sh fail", "", "", "");
+    assert!(provider.copy(Selection::Clipboard, "text").is_err());
+    assert!(provider.paste(Selection::Clipboard).is_err());
+    assert!(provider.paste(Selection::Primary).is_err());
+  }
+
+  #[test]
+  fn osc52() {
+    let mut provider = Osc52Provider::new();
+    assert_eq!(provider.paste(Selection::Clipboard), Err(Error::CantPaste));
+    provider.copy(Selection::Clipboard, "first").unwrap();
+    provider.copy(Selection::Primary, "selected").unwrap();
+    provider.copy(Selection::Clipboard, "copied").unwrap();
+    // every copy is written to the terminal, while the last one is pasted
+    assert_eq!(provider.take_terminal_copies(),
+               vec!((Selection::Clipboard, "first".to_string()),
+                    (Selection::Primary, "selected".to_string()),
+                    (Selection::Clipboard, "copied".to_string())));
+    assert_eq!(provider.take_terminal_copies(), vec!());
+    assert_eq!(provider.paste(Selection::Clipboard),
+               Ok("copied".to_string()));
+    // commands have nothing to write to the terminal
+    assert_eq!(fake_provider().take_terminal_copies(), vec!());
+  }
+
+  #[test]
+  fn registers() {
+    assert_eq!(Selection::from_register('+'), Some(Selection::Clipboard));
+    assert_eq!(Selection::from_register('*'), Some(Selection::Primary));
+    assert_eq!(Selection::from_register('a'), None);
+  }
+}
@@ -0,0 +1,285 @@
+/*
+ * Copyright (c) 2015 Mathias Hällman
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+#[cfg(not(test))]
+extern crate unicode_width;
+
+use std::collections::HashMap;
+
+#[cfg(not(test))]
+use highlight::{Group, Highlights};
+#[cfg(not(test))]
+use screen;
+#[cfg(not(test))]
+use screen::Screen;
+
+#[cfg(not(test))]
+use self::unicode_width::UnicodeWidthChar as CharWidth;
+
+/*
+ * CmdLine is the single row at the bottom of the screen where commands are
+ * typed following a prompt. While not prompting it is used to show messages.
+ * Entered lines are remembered in a history which can be browsed while
+ * prompting, each kind of prompt having a history of its own.
+ */
+#[cfg_attr(test, allow(dead_code))]
+pub struct CmdLine {
+  prompt: Option<char>,
+  text: Vec<char>,
+  caret: usize,  // position in text, may be one beyond its end
+  message: Option<String>,
+  histories: HashMap<char, Vec<String>>,
+  history_index: usize,  // equals the history length unless browsing it
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl CmdLine {
+  pub fn new() -> CmdLine {
+    CmdLine {
+      prompt: None,
+      text: Vec::new(),
+      caret: 0,
+      message: None,
+      histories: HashMap::new(),
+      history_index: 0,
+    }
+  }
+
+  pub fn is_prompting(&self) -> bool {
+    self.prompt.is_some()
+  }
+
+  pub fn prompt(&self) -> Option<char> {
+    self.prompt
+  }
+
+  pub fn start_prompt(&mut self, prompt: char) {
+    self.prompt = Some(prompt);
+    self.message = None;
+    self.set_text(String::new());
+    self.history_index = self.history().len();
+  }
+
+  // Ends prompting and returns the entered text, which is also added to the
+  // history of the prompt.
+  pub fn finish_prompt(&mut self) -> String {
+    let text = self.text();
+    if let Some(prompt) = self.prompt {
+      let history = self.histories.entry(prompt).or_insert(Vec::new());
+      if !text.is_empty() && history.last() != Some(&text) {
+        history.push(text.clone());
+      }
+    }
+    self.cancel_prompt();
+    text
+  }
+
+  pub fn cancel_prompt(&mut self) {
+    self.prompt = None;
+    self.set_text(String::new());
+  }
+
+  pub fn set_message(&mut self, message: String) {
+    self.message = Some(message);
+  }
+
+  pub fn text(&self) -> String {
+    self.text.iter().cloned().collect()
+  }
+
+  fn set_text(&mut self, text: String) {
+    self.text = text.chars().collect();
+    self.caret = self.text.len();
+  }
+
+  pub fn insert(&mut self, string: &str) {
+    for character in string.chars() {
+      self.text.insert(self.caret, character);
+      self.caret += 1;
+    }
+  }
+
+  // returns false if there was nothing before the caret to delete
+  pub fn backspace(&mut self) -> bool {
+    if self.caret == 0 { return false }
+    self.caret -= 1;
+    self.text.remove(self.caret);
+    true
+  }
+
+  pub fn delete(&mut self) {
+    if self.caret < self.text.len() { self.text.remove(self.caret); }
+  }
+
+  pub fn caret_left(&mut self) {
+    if self.caret > 0 { self.caret -= 1; }
+  }
+
+  pub fn caret_right(&mut self) {
+    if self.caret < self.text.len() { self.caret += 1; }
+  }
+
+  pub fn caret_start(&mut self) {
+    self.caret = 0;
+  }
+
+  pub fn caret_end(&mut self) {
+    self.caret = self.text.len();
+  }
+
+  pub fn history_previous(&mut self) {
+    if self.history_index > 0 {
+      self.history_index -= 1;
+      let text = self.history()[self.history_index].clone();
+      self.set_text(text);
+    }
+  }
+
+  pub fn history_next(&mut self) {
+    if self.history_index < self.history().len() {
+      self.history_index += 1;
+      let text = self.history().get(self.history_index).cloned().
+        unwrap_or(String::new());
+      self.set_text(text);
+    }
+  }
+
+  // the history of the current prompt
+  fn history(&self) -> &[String] {
+    self.prompt.and_then(|prompt| self.histories.get(&prompt)).
+    map(|history| &history[..]).unwrap_or(&[])
+  }
+
+  // The column of the caret, counting the prompt, along with the columns the
+  // text is scrolled by to keep the caret within |cols| columns. None if not
+  // prompting.
+  #[cfg(not(test))]
+  fn caret_and_scroll(&self, cols: usize) -> Option<(usize, usize)> {
+    let width = |c: &char| CharWidth::width(*c).unwrap_or(0);
+    self.prompt.map(|prompt| {
+      let col = self.text[..self.caret].iter().map(&width).
+        fold(width(&prompt), |a, b| a + b);
+      (col, (col + 1).saturating_sub(cols))
+    })
+  }
+
+  // the column the caret is drawn in when |cols| wide, if prompting
+  #[cfg(not(test))]
+  pub fn caret_column(&self, cols: usize) -> Option<usize> {
+    self.caret_and_scroll(cols).map(|(col, scroll)| col - scroll)
+  }
+
+  #[cfg(not(test))]
+  pub fn draw(&self, rect: screen::Rect, highlights: &Highlights,
+              screen: &mut Screen) {
+    let style = highlights.style(Group::MsgArea);
+    let caret_style = highlights.style_over(Group::Cursor, style);
+    let screen::Rect(position, screen::Size(rows, cols)) = rect;
+    if rows == 0 || cols == 0 { return }
+    let cols = cols as usize;
+
+    // gather what to draw along with where the caret is, if anywhere
+    let (chars, caret): (Vec<char>, Option<usize>) = match self.prompt {
+      Some(prompt) => {
+        let mut chars = vec!(prompt);
+        chars.extend(self.text.iter().cloned());
+        (chars, Some(self.caret + 1))
+      }
+      None         => (self.message.as_ref().map(|message|
+                        message.chars().collect()).unwrap_or(Vec::new()),
+                       None),
+    };
+
+    // scroll horizontally so that the caret is kept visible
+    let width = |c: &char| CharWidth::width(*c).unwrap_or(0);
+    let caret_col = self.caret_and_scroll(cols).map(|(col, _)| col);
+    let scroll = self.caret_and_scroll(cols).map(|(_, scroll)| scroll).
+      unwrap_or(0);
+
+    let mut col = 0;
+    for (i, character) in chars.iter().enumerate() {
+      let char_width = width(character);
+      if col + char_width > scroll + cols { break }
+      if col >= scroll {
+        screen.put(position + screen::Cell(0, (col - scroll) as u16),
+                   *character,
+                   if caret == Some(i) { caret_style } else { style });
+      }
+      col += char_width;
+    }
+    // blank out the rest of the row, the caret may be found at its start
+    let caret_cell = caret_col.map(|caret_col| caret_col - scroll);
+    for col in col.saturating_sub(scroll)..cols {
+      screen.put(position + screen::Cell(0, col as u16), ' ',
+                 if caret_cell == Some(col) { caret_style } else { style });
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn editing() {
+    let mut cmdline = CmdLine::new();
+    cmdline.start_prompt(':');
+    cmdline.insert("wq");
+    cmdline.caret_start();
+    cmdline.insert("%");
+    cmdline.caret_end();
+    cmdline.caret_left();
+    assert!(cmdline.backspace());
+    cmdline.caret_right();
+    cmdline.insert("a");
+    assert_eq!(cmdline.text(), "%qa");
+    cmdline.caret_start();
+    cmdline.delete();
+    assert!(!cmdline.backspace());
+    assert_eq!(cmdline.finish_prompt(), "qa");
+    assert!(!cmdline.is_prompting());
+  }
+
+  #[test]
+  fn history() {
+    let mut cmdline = CmdLine::new();
+    for text in ["first", "second", "second", ""].iter() {
+      cmdline.start_prompt(':');
+      cmdline.insert(text);
+      cmdline.finish_prompt();
+    }
+    cmdline.start_prompt(':');
+    cmdline.insert("typed");
+    cmdline.history_next();
+    assert_eq!(cmdline.text(), "typed");
+    cmdline.history_previous();
+    assert_eq!(cmdline.text(), "second");
+    cmdline.history_previous();
+    cmdline.history_previous();
+    assert_eq!(cmdline.text(), "first");
+    cmdline.history_next();
+    assert_eq!(cmdline.text(), "second");
+    cmdline.history_next();
+    assert_eq!(cmdline.text(), "");
+    cmdline.cancel_prompt();
+    cmdline.start_prompt(':');
+    cmdline.history_previous();
+    assert_eq!(cmdline.text(), "second");
+    cmdline.cancel_prompt();
+    // other prompts keep histories of their own
+    cmdline.start_prompt('/');
+    assert_eq!(cmdline.prompt(), Some('/'));
+    cmdline.history_previous();
+    assert_eq!(cmdline.text(), "");
+    cmdline.insert("pattern");
+    cmdline.finish_prompt();
+    cmdline.start_prompt(':');
+    cmdline.history_previous();
+    assert_eq!(cmdline.text(), "second");
+  }
+}
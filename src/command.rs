@@ -22,14 +22,18 @@ use self::futures::sync::{mpsc, oneshot};
 use self::vec_map::VecMap;
 
 use caret;
+use ex;
 use frame;
-use keymap::Key;
+use keymap::{Key, MOD_NONE};
 
 #[cfg(not(test))]
 const TIMEOUT: u64 = 3000;
 #[cfg(test)]
 const TIMEOUT: u64 = 100;
 
+// how many times keys may be mapped to keys mapped again, like maxmapdepth
+const MAX_MAP_DEPTH: usize = 1000;
+
 /*
  * CmdThread is the interface for instructing the command thread. It is returned
  * when the thread is started and it will tear down the thread when dropped.
@@ -39,11 +43,19 @@ const TIMEOUT: u64 = 100;
  * to wait for more keys before possibly producing a command, however, it may
  * only do so for a set time. After a timeout, the keys arrived so far will be
  * drained from the buffer by forcing keychains to only match keys to complete
- * commands.
+ * commands. The time waited may be changed, or the timeout done away with such
+ * that keychains wait for as long as it takes.
  * Between each command produced and sent to the client, the client must ack the
  * arrival of the command before the command thread can proceed to process
  * further keys. This allows the client to for instance set a new mode to be
  * used for the keys ahead.
+ * The keys processed may be recorded, and keys may be pushed to be processed
+ * ahead of those arrived, such as when replaying a recording. Pushed keys are
+ * processed under the same ack protocol as arriving keys, and are never
+ * recorded themselves.
+ * Keys matching a mapping of a mode are replaced by the keys they map to, which
+ * aren't recorded either. These are mapped again unless the mapping says not
+ * to, up to a depth beyond which everything left to process is dropped.
  */
 pub struct CmdThread {
   kill_tx: Option<oneshot::Sender<()>>,
@@ -60,6 +72,27 @@ impl CmdThread {
     self.send(Msg::AckCmd);
   }
 
+  pub fn start_recording(&self) {
+    self.send(Msg::StartRecording);
+  }
+
+  // Stops recording and returns the keys recorded, leaving out the keys of the
+  // last command sent, which is taken to be the one stopping the recording.
+  pub fn stop_recording(&self) -> Vec<Key> {
+    let (keys_tx, keys_rx) = oneshot::channel();
+    self.send(Msg::StopRecording(keys_tx));
+    keys_rx.wait().expect("Command thread died.")
+  }
+
+  pub fn push_keys(&self, keys: Vec<Key>) {
+    self.send(Msg::PushKeys(keys));
+  }
+
+  // sets the milliseconds to wait for more keys, or never to time out if None
+  pub fn set_timeout(&self, timeout: Option<u64>) {
+    self.send(Msg::SetTimeout(timeout));
+  }
+
   fn send(&self, msg: Msg) {
     self.msg_tx.send(msg).ok().expect("command thread died");
   }
@@ -79,6 +112,10 @@ impl Drop for CmdThread {
 enum Msg {
   SetMode(Mode, usize),
   AckCmd,
+  StartRecording,
+  StopRecording(oneshot::Sender<Vec<Key>>),
+  PushKeys(Vec<Key>),
+  SetTimeout(Option<u64>),
 }
 
 // start the command thread
@@ -91,6 +128,18 @@ pub fn start(key_rx: mpsc::UnboundedReceiver<Key>,
   CmdThread { kill_tx: Some(kill_tx), died_rx: Some(died_rx), msg_tx: msg_tx }
 }
 
+/*
+ * Where the keys to process came from, keys from a mapping being mapped again
+ * if true.
+ */
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+enum Origin {
+  Typed,
+  Pushed,
+  Mapped(bool),
+}
+
 /*
  * Events to the command loop.
  */
@@ -115,21 +164,34 @@ fn cmd_thread(kill_rx: oneshot::Receiver<()>, died_tx: oneshot::Sender<()>,
   let mut back_seq: usize = 0;
   let mut front_seq: usize = 0;
 
+  // where each of the unprocessed keys came from, and how many mappings deep
+  // the keys last mapped were
+  let mut origins = VecDeque::new();
+  let mut map_depth: usize = 0;
+
+  // keys processed while recording, along with how many of them made up the
+  // last command sent
+  let mut recording: Option<Vec<Key>> = None;
+  let mut last_cmd_recorded: usize = 0;
+
   // modes used to make commands out of a stream of keys, a higher index/key
   // implies higher priority
   let mut modes = VecMap::new();
 
+  // the milliseconds to wait for more keys before draining, if ever
+  let mut timeout_len: Option<u64> = Some(TIMEOUT);
+
   // setup the command loop
   let mut core = tokio_core::reactor::Core::new().unwrap();
 
   // setup a channel through which timeouts may be requested
   let timeout_core_handle = core.handle();
   let (timeout_tx, timeout_rx) = mpsc::unbounded();
-  let request_timeout = |seq| {
+  let request_timeout = |seq, millis| {
       let tx = timeout_tx.clone();
       let timeout =
         tokio_timer::wheel().tick_duration(Duration::from_millis(10)).build().
-        sleep(Duration::from_millis(TIMEOUT)).then(move |_| {
+        sleep(Duration::from_millis(millis)).then(move |_| {
             tx.send(seq).expect("Oneshot channel died.");
             Ok(())
           });
@@ -146,27 +208,51 @@ fn cmd_thread(kill_rx: oneshot::Receiver<()>, died_tx: oneshot::Sender<()>,
     let mut drain = false;
 
     match event {
-      Event::Key(key)     => { keys.push_back(key); back_seq += 1; }
+      Event::Key(key)     => {
+        keys.push_back(key);
+        origins.push_back(Origin::Typed);
+        back_seq += 1;
+      }
       Event::CmdMsg(msg)  =>
         match msg {
-          Msg::SetMode(mode, num) => { modes.insert(num, mode); }
-          Msg::AckCmd             => { cmd_acknowledged = true; }
+          Msg::SetMode(mode, num)       => { modes.insert(num, mode); }
+          Msg::AckCmd                   => { cmd_acknowledged = true; }
+          Msg::StartRecording           => {
+            recording = Some(Vec::new());
+            last_cmd_recorded = 0;
+          }
+          Msg::StopRecording(keys_tx)   => {
+            let mut recorded = recording.take().unwrap_or(Vec::new());
+            let len = recorded.len();
+            recorded.truncate(len - last_cmd_recorded.min(len));
+            keys_tx.complete(recorded);
+          }
+          Msg::PushKeys(pushed)         => {
+            back_seq += pushed.len();
+            for key in pushed.into_iter().rev() {
+              keys.push_front(key);
+              origins.push_front(Origin::Pushed);
+            }
+          }
+          Msg::SetTimeout(timeout)      => { timeout_len = timeout; }
         },
       Event::Timeout(seq) => drain = seq == back_seq,
       Event::Kill         => return Err(()),
     }
 
-    // work through the arrived keys
-    while keys.len() > 0 {
+    // work through the arrived keys, keys being neither matched nor dropped
+    // until the last command sent is acknowledged as the mode may change
+    while keys.len() > 0 && cmd_acknowledged {
       assert!(back_seq >= front_seq);
       // determine amount of keys to consider
       let num_keys = back_seq - front_seq;
+      let remap = origins[0] != Origin::Mapped(false);
       // match keys with modes in priority order
       let mut match_result = MatchResult::None;
       for (_, mode) in modes.iter().rev() {
-        // first match by keychain
+        // first match by mappings and keychain
         match_result =
-          mode.keychain.match_keys(&mut keys.iter().take(num_keys), drain);
+          mode.match_keys(keys.iter().take(num_keys), drain, remap);
         // use the mode's fallback if the keychain didn't match anything
         if match_result == MatchResult::None {
           (mode.fallback)(keys[0]).map(|cmd|
@@ -178,17 +264,48 @@ fn cmd_thread(kill_rx: oneshot::Receiver<()>, died_tx: oneshot::Sender<()>,
 
       // act on the match result
       match match_result {
-        MatchResult::None               => { keys.pop_front(); front_seq += 1; }
+        MatchResult::None               => {
+          pop_key(&mut keys, &mut origins, &mut recording);
+          front_seq += 1;
+        }
         MatchResult::Partial(num)       => {
           assert_eq!(front_seq + num, back_seq);
-          request_timeout(back_seq);
+          timeout_len.map(|millis| request_timeout(back_seq, millis));
           break;
         }
         MatchResult::Complete(cmd, num) => {
-          if !cmd_acknowledged { break; } else {
-            for _ in 0..num { keys.pop_front(); front_seq += 1; }
-            cmd_tx.send(cmd).expect("Command channel died.");
+          let mapped = match origins[0] { Origin::Mapped(_) => true,
+                                          _                 => false };
+          last_cmd_recorded = 0;
+          let mut matched = Vec::new();
+          for _ in 0..num {
+            let (key, recorded) =
+              pop_key(&mut keys, &mut origins, &mut recording);
+            if recorded { last_cmd_recorded += 1; }
+            matched.push(key);
+            front_seq += 1;
+          }
+          let expanded = match expand_mapping(&cmd, &matched) {
+            Some(expanded) => expanded,
+            None           => {
+              cmd_tx.send(cmd).expect("Command channel died.");
+              cmd_acknowledged = false;
+              continue;
+            }
+          };
+          map_depth = if mapped { map_depth + 1 } else { 1 };
+          if map_depth > MAX_MAP_DEPTH {
+            front_seq = back_seq;
+            keys.clear();
+            origins.clear();
+            cmd_tx.send(Cmd::RecursiveMapping).expect("Command channel died.");
             cmd_acknowledged = false;
+            continue;
+          }
+          back_seq += expanded.len();
+          for (key, origin) in expanded.into_iter().rev() {
+            keys.push_front(key);
+            origins.push_front(origin);
           }
         }
       }
@@ -202,21 +319,109 @@ fn cmd_thread(kill_rx: oneshot::Receiver<()>, died_tx: oneshot::Sender<()>,
   died_tx.complete(());
 }
 
+// Pops the key at the front, recording it if it was typed. Returns the key and
+// whether it was recorded.
+fn pop_key(keys: &mut VecDeque<Key>, origins: &mut VecDeque<Origin>,
+           recording: &mut Option<Vec<Key>>) -> (Key, bool) {
+  let key = keys.pop_front().expect("Popped a key from an empty queue.");
+  let origin = origins.pop_front().expect("Popped a key without an origin.");
+  if origin != Origin::Typed { return (key, false); }
+  (key, recording.as_mut().map(|recorded| recorded.push(key)).is_some())
+}
+
+// The keys replacing those |matched| by a mapping, or None if |cmd| isn't one.
+// A count typed in front of the mapping goes in front of the keys, and like in
+// Vim, keys mapped to keys starting with them aren't mapped again at first.
+fn expand_mapping(cmd: &Cmd, matched: &[Key]) -> Option<Vec<(Key, Origin)>> {
+  let (count, to, remap) = match *cmd {
+    Cmd::Keys(ref to, remap)     => (None, to, remap),
+    Cmd::Counted(count, ref cmd) => match **cmd {
+      Cmd::Keys(ref to, remap) => (Some(count), to, remap),
+      _                        => return None,
+    },
+    _                            => return None,
+  };
+  let digit = |c| Key::Unicode{codepoint: c, mods: MOD_NONE};
+  let mut expanded: Vec<(Key, Origin)> =
+    count.map(|count| count.to_string()).unwrap_or(String::new()).chars().
+    map(|c| (digit(c), Origin::Mapped(false))).collect();
+  let starts_with_mapped = to.starts_with(&matched[expanded.len()..]);
+  expanded.extend(to.iter().enumerate().map(|(i, key)|
+    (*key, Origin::Mapped(remap && !(i == 0 && starts_with_mapped)))));
+  Some(expanded)
+}
+
 /*
  * A Mode is what the command thread use to form commands out of keys. It
  * consist of a keychain and a fallback command contructor for when the keychain
- * doesn't match on a string of keys.
+ * doesn't match on a string of keys. Mappings, binding keys to Cmd::Keys, are
+ * kept apart from the keychain, which they take precedence over. A mode may
+ * accept a count in front of the keys matched by its keychain, the command is
+ * then wrapped up along with the count.
  */
 #[derive(Clone)]
 pub struct Mode {
   pub keychain: Keychain,
-  pub fallback: fn(Key) -> Option<Cmd>
+  pub mappings: Keychain,
+  pub fallback: fn(Key) -> Option<Cmd>,
+  pub accepts_count: bool,
 }
 
 impl Mode {
   pub fn new() -> Mode {
     fn fallback(_: Key) -> Option<Cmd> { None }
-    Mode { keychain: Keychain::new(), fallback: fallback }
+    Mode {
+      keychain: Keychain::new(), mappings: Keychain::new(), fallback: fallback,
+      accepts_count: false
+    }
+  }
+
+  // Matches keys against the mappings, if they may be mapped, and then the
+  // keychain after reading off a leading count, if the mode accepts one. A
+  // count never starts with a zero, leaving it free to be bound to a command of
+  // its own.
+  fn match_keys<'l, It>(&self, keys: It, force: bool, remap: bool)
+      -> MatchResult where It: Iterator<Item=&'l Key> + Clone {
+    let mut keys = keys.peekable();
+    let mut count: Option<usize> = None;
+    let mut num_digits = 0;
+    while self.accepts_count {
+      let digit = match keys.peek() {
+        Some(&&Key::Unicode{codepoint, mods}) if mods == MOD_NONE =>
+          codepoint.to_digit(10),
+        _                                                        => None,
+      };
+      match digit {
+        Some(digit) if digit > 0 || count.is_some() => {
+          count = Some(count.unwrap_or(0).saturating_mul(10).
+                       saturating_add(digit as usize));
+          num_digits += 1;
+          keys.next();
+        }
+        _                                           => break,
+      }
+    }
+    let mapped =
+      if remap { self.mappings.match_keys(&mut keys.clone(), force) }
+      else     { MatchResult::None };
+    let matched = match mapped {
+      MatchResult::None => self.keychain.match_keys(&mut keys, force),
+      mapped            => mapped,
+    };
+    match matched {
+      MatchResult::None                => MatchResult::None,
+      // a lone count is dropped when forced
+      MatchResult::Partial(_) if force => MatchResult::None,
+      MatchResult::Partial(num)        =>
+        MatchResult::Partial(num_digits + num),
+      MatchResult::Complete(cmd, num)  => {
+        let cmd = match count {
+          Some(count) => Cmd::Counted(count, Box::new(cmd)),
+          None        => cmd,
+        };
+        MatchResult::Complete(cmd, num_digits + num)
+      }
+    }
   }
 }
 
@@ -278,6 +483,61 @@ impl Keychain {
     *self = new_self;
   }
 
+  // Removes the command bound to |keys|, leaving longer strings of keys
+  // starting with them bound. Returns whether there was a command to remove.
+  pub fn unbind(&mut self, keys: &[Key]) -> bool {
+    let (removed, new_self) = match mem::replace(self, Keychain::new()) {
+      Keychain::Node(mut map, opt_cmd) => {
+        match keys.split_first() {
+          None                  =>
+            (opt_cmd.is_some(), Keychain::Node(map, None)),
+          Some((key, ref keys)) => {
+            let mut removed = false;
+            map.remove(&key).map(|mut subchain| {
+              removed = subchain.unbind(keys);
+              if !subchain.is_empty() { map.insert(*key, subchain); } });
+            // a node left without keys following it is but a command
+            match opt_cmd {
+              Some(cmd) if map.is_empty() => (removed, Keychain::Cmd(cmd)),
+              opt_cmd                     =>
+                (removed, Keychain::Node(map, opt_cmd)),
+            }
+          }
+        }
+      }
+      Keychain::Cmd(old_cmd)           =>
+        if keys.is_empty() { (true, Keychain::new()) }
+        else               { (false, Keychain::Cmd(old_cmd)) },
+    };
+    *self = new_self;
+    removed
+  }
+
+  // Every string of keys bound, along with the command it's bound to.
+  pub fn bindings(&self) -> Vec<(Vec<Key>, &Cmd)> {
+    match *self {
+      Keychain::Cmd(ref cmd)               => vec!((Vec::new(), cmd)),
+      Keychain::Node(ref map, ref opt_cmd) => {
+        let mut bindings: Vec<(Vec<Key>, &Cmd)> =
+          opt_cmd.iter().map(|cmd| (Vec::new(), cmd)).collect();
+        for (key, chain) in map.iter() {
+          bindings.extend(chain.bindings().into_iter().map(|(keys, cmd)| {
+            let mut keys = keys;
+            keys.insert(0, *key);
+            (keys, cmd) }));
+        }
+        bindings
+      }
+    }
+  }
+
+  fn is_empty(&self) -> bool {
+    match *self {
+      Keychain::Node(ref map, None) => map.is_empty(),
+      _                             => false,
+    }
+  }
+
   fn match_keys<'l, It>(&self, keys: &mut It, force: bool) -> MatchResult
       where It: Iterator<Item=&'l Key> {
     let res_from_opt = |opt: Option<Cmd>|
@@ -321,9 +581,45 @@ pub enum Cmd {
   GrowWindow(frame::Orientation),
   ShrinkWindow(frame::Orientation),
   CloseWindow,
-  QuitWindow,
-  Quit,
+  QuitWindow(bool),  // whether to quit even if there are unsaved changes
+  WriteQuitWindow(Option<PathBuf>, bool),  // whether to overwrite a file
+  Quit(bool),  // whether to quit even if there are unsaved changes
+  WriteAll,
+  WriteQuitAll,
+  EnterCmdLineMode,
+  EnterSearchMode(bool),  // whether to search backward
+  Map(ex::MapMode, Vec<Key>, Vec<Key>, bool),  // whether to map the keys again
+  Unmap(ex::MapMode, Vec<Key>),
+  ListMappings(ex::MapMode, Vec<Key>),  // those of keys starting with these
+  ShowRegisters(String),  // the names of the registers, or empty for all
+  Set(Vec<ex::Setting>, bool),  // whether to set local values only
+  Highlight(String, Vec<ex::Setting>),  // the group, or clear, and its keys
+  Colorscheme(String),
+  Keys(Vec<Key>, bool),  // the keys of a mapping and whether to map them again
+  RecursiveMapping,  // keys were mapped too many times over
+  CmdLine(CmdLineCmd),
   WinCmd(WinCmd),
+  Counted(usize, Box<Cmd>),  // a command preceded by a count
+}
+
+/*
+ * Commands for editing the command line.
+ */
+#[derive(Clone, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+#[cfg_attr(test, allow(dead_code))]
+pub enum CmdLineCmd {
+  Insert(String),
+  Backspace,
+  Delete,
+  CaretLeft,
+  CaretRight,
+  CaretStart,
+  CaretEnd,
+  HistoryPrevious,
+  HistoryNext,
+  Execute,
+  Cancel,
 }
 
 /*
@@ -334,6 +630,7 @@ pub enum Cmd {
 #[cfg_attr(test, allow(dead_code))]  // the tests don't make use of all commands
 pub enum WinCmd {
   MoveCaret(caret::Adjustment),
+  MoveCaretByDisplayLine(bool),  // whether to move down
   EnterNormalMode,
   EnterReplaceMode(bool),
   EnterInsertMode,
@@ -342,8 +639,21 @@ pub enum WinCmd {
   EnterInsertModeAppendEndOfLine,
   EnterInsertModeNextLine,
   EnterInsertModePreviousLine,
-  OpenBuffer(PathBuf),
-  SaveBuffer,
+  EnterVisualMode(caret::SelectionKind),
+  SwapSelectionEnds,
+  OperateOnSelection(Operator),
+  PendOperator(Operator),
+  Operate(Operator, caret::Adjustment),
+  OperateOnLines(Operator),
+  PendCharSearch(caret::CharSearch),
+  SearchChar(Option<char>),  // none if the search was cancelled
+  RepeatCharSearch(bool),    // whether to search in the opposite direction
+  SearchNext(bool),          // whether to search in the opposite direction
+  PendRegister(RegisterUse),
+  SelectRegister(Option<char>),  // none if naming the register was cancelled
+  Put(bool),  // whether to put before the caret
+  OpenBuffer(PathBuf, bool),  // whether to discard changes made to it
+  SaveBuffer(Option<PathBuf>, bool),  // whether to overwrite another file
   Replace(String),
   ReplaceLine(String),
   Insert(String),
@@ -351,13 +661,120 @@ pub enum WinCmd {
   Backspace,
   DeleteOnLine,
   BackspaceOnLine,
-  DeleteLine,
-  DeleteRestOfLine,
-  ChangeRestOfLine,
+  DeleteLines(ex::Range),
+  Substitute(ex::Range, ex::Substitution),
+  ConfirmSubstitution(Confirmation),
   PageUp,
   PageDown,
   HalfPageUp,
   HalfPageDown,
+  GotoLine(ex::Address),
+  Undo,
+  Redo,
+  RepeatChange,
+}
+
+impl WinCmd {
+  // Whether the command may be part of a change repeated by RepeatChange.
+  // Commands from the command line aren't, nor are undo and redo.
+  pub fn is_repeatable(&self) -> bool {
+    match *self {
+      WinCmd::OpenBuffer(..) | WinCmd::SaveBuffer(..) | WinCmd::DeleteLines(_) |
+      WinCmd::Substitute(..) | WinCmd::ConfirmSubstitution(_) |
+      WinCmd::GotoLine(_) | WinCmd::Undo | WinCmd::Redo |
+      WinCmd::RepeatChange => false,
+      _                    => true,
+    }
+  }
+}
+
+/*
+ * Changes keeps track of the window commands given since the window was last
+ * idle, along with their counts, and of those that made the last change, to be
+ * given again by RepeatChange.
+ */
+pub struct Changes {
+  pending: Vec<(WinCmd, Option<usize>)>,
+  last: Vec<(WinCmd, Option<usize>)>,
+  repeating: bool,
+}
+
+impl Changes {
+  pub fn new() -> Changes {
+    Changes { pending: Vec::new(), last: Vec::new(), repeating: false }
+  }
+
+  // Notes a command given, unless it repeats the last change itself.
+  pub fn push(&mut self, cmd: &WinCmd, count: Option<usize>) {
+    if *cmd == WinCmd::RepeatChange { self.repeating = true; }
+    else { self.pending.push((cmd.clone(), count)); }
+  }
+
+  // Ends the commands given since the window was last idle, which make the
+  // last change if they changed the buffer and may all be repeated.
+  pub fn finish(&mut self, changed: bool) {
+    let pending = mem::replace(&mut self.pending, Vec::new());
+    if changed && !self.repeating &&
+       pending.iter().all(|&(ref cmd, _)| cmd.is_repeatable()) {
+      self.last = pending;
+    }
+    self.repeating = false;
+  }
+
+  // The commands of the last change, to be given again. A count replaces the
+  // one the change was made with, and is kept for later repeats.
+  pub fn repeat(&mut self, count: Option<usize>)
+      -> Vec<(WinCmd, Option<usize>)> {
+    if count.is_some() {
+      for (i, &mut (_, ref mut cmd_count)) in self.last.iter_mut().enumerate() {
+        *cmd_count = if i == 0 { count } else { None };
+      }
+    }
+    self.last.clone()
+  }
+}
+
+/*
+ * Operators act on a range of a buffer.
+ */
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+#[cfg_attr(test, allow(dead_code))]
+pub enum Operator {
+  Delete,
+  Change,
+  Yank,
+  Indent,
+  Unindent,
+}
+
+/*
+ * What the register named after a PendRegister is used for: by the command
+ * following it, to record keys into, or to execute the keys it holds.
+ */
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+#[cfg_attr(test, allow(dead_code))]
+pub enum RegisterUse {
+  Name,
+  Record,
+  Execute,
+}
+
+/*
+ * The answers to whether a match of a substitution should be replaced. Besides
+ * yes and no the user may replace all the remaining matches, replace only this
+ * last one, or quit without replacing any more.
+ */
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+#[cfg_attr(test, allow(dead_code))]
+pub enum Confirmation {
+  Yes,
+  No,
+  All,
+  Last,
+  Quit,
 }
 
 #[cfg(test)]
@@ -371,8 +788,9 @@ mod test {
   use self::futures::sync::mpsc;
   use self::futures::{Future, Stream};
 
+  use caret::Adjustment;
   use frame;
-  use keymap::{Key, MOD_NONE};
+  use keymap::{self, Key, parse_notation};
 
   use super::*;
 
@@ -421,115 +839,61 @@ mod test {
 
   fn mode_0() -> Mode {
     let mut mode = Mode::new();
-    mode.keychain.bind(&[Key::Unicode{codepoint: 'a', mods: MOD_NONE}],
-      Cmd::Quit);
-    mode.keychain.bind(&[Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'a', mods: MOD_NONE}],
-      Cmd::ResetLayout);
-    mode.keychain.bind(&[Key::Unicode{codepoint: 'c', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'a', mods: MOD_NONE}],
-      Cmd::CloseWindow);
-    mode.keychain.bind(&[Key::Unicode{codepoint: 'd', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'd', mods: MOD_NONE}],
-      Cmd::CloseWindow);
+    mode.keychain.bind(&parse_notation("a"), Cmd::Quit(false));
+    mode.keychain.bind(&parse_notation("ba"), Cmd::ResetLayout);
+    mode.keychain.bind(&parse_notation("cba"), Cmd::CloseWindow);
+    mode.keychain.bind(&parse_notation("dbad"), Cmd::CloseWindow);
     return mode;
   }
 
   fn mode_1() -> Mode {
     let mut mode = Mode::new();
-    mode.keychain.bind(&[Key::Unicode{codepoint: 'a', mods: MOD_NONE}],
+    mode.keychain.bind(&parse_notation("a"),
       Cmd::MoveFocus(frame::Direction::Left));
-    mode.keychain.bind(&[Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'a', mods: MOD_NONE}],
+    mode.keychain.bind(&parse_notation("ba"),
       Cmd::MoveFocus(frame::Direction::Right));
-    mode.keychain.bind(&[Key::Unicode{codepoint: 'c', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'a', mods: MOD_NONE}],
+    mode.keychain.bind(&parse_notation("cbabaa"),
       Cmd::MoveFocus(frame::Direction::Up));
     return mode;
   }
 
   fn mode_2() -> Mode {
     let mut mode = Mode::new();
-    mode.keychain.bind(&[Key::Unicode{codepoint: 'a', mods: MOD_NONE}],
-      Cmd::Quit);
-    mode.keychain.bind(&[Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'a', mods: MOD_NONE}],
-      Cmd::ResetLayout);
-    mode.keychain.bind(&[Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'b', mods: MOD_NONE}],
-      Cmd::CloseWindow);
+    mode.keychain.bind(&parse_notation("a"), Cmd::Quit(false));
+    mode.keychain.bind(&parse_notation("ba"), Cmd::ResetLayout);
+    mode.keychain.bind(&parse_notation("bab"), Cmd::CloseWindow);
     return mode;
   }
 
   fn mode_3() -> Mode {
     let mut mode = Mode::new();
-    mode.keychain.bind(&[Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'b', mods: MOD_NONE}],
+    mode.keychain.bind(&parse_notation("bab"),
       Cmd::MoveFocus(frame::Direction::Right));
-    mode.keychain.bind(&[Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'c', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'b', mods: MOD_NONE}],
+    mode.keychain.bind(&parse_notation("babcabab"),
       Cmd::MoveFocus(frame::Direction::Up));
     return mode;
   }
 
   fn mode_4() -> Mode {
     let mut mode = Mode::new();
-    mode.keychain.bind(&[Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'a', mods: MOD_NONE}],
-      Cmd::Quit);
-    mode.keychain.bind(&[Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'b', mods: MOD_NONE}],
-      Cmd::ResetLayout);
-    mode.keychain.bind(&[Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-                         Key::Unicode{codepoint: 'b', mods: MOD_NONE}],
-      Cmd::CloseWindow);
+    mode.keychain.bind(&parse_notation("ba"), Cmd::Quit(false));
+    mode.keychain.bind(&parse_notation("bab"), Cmd::ResetLayout);
+    mode.keychain.bind(&parse_notation("babab"), Cmd::CloseWindow);
     return mode;
   }
 
   #[test]
   fn single_mode() {
-    let inputs = vec!(vec!(
-      // Quit
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      // Nothing
-      Key::Unicode{codepoint: 'x', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'y', mods: MOD_NONE},
-      // ResetLayout
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      // Nothing
-      Key::Unicode{codepoint: 'z', mods: MOD_NONE},
-      // CloseWindow
-      Key::Unicode{codepoint: 'c', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      // Nothing (partial CloseWindow)
-      Key::Unicode{codepoint: 'c', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      // ResetLayout
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE}));
+    let inputs = vec!(parse_notation(concat!(
+      "a",    // Quit
+      "xy",   // Nothing
+      "ba",   // ResetLayout
+      "z",    // Nothing
+      "cba",  // CloseWindow
+      "cb",   // Nothing (partial CloseWindow)
+      "ba"))); // ResetLayout
     let outputs = vec!(
-      Cmd::Quit,
+      Cmd::Quit(false),
       Cmd::ResetLayout,
       Cmd::CloseWindow,
       Cmd::ResetLayout);
@@ -541,27 +905,15 @@ mod test {
 
   #[test]
   fn multiple_modes() {
-    let inputs = vec!(vec!(
-      // MoveFocus(Left)
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      // Nothing
-      Key::Unicode{codepoint: 'x', mods: MOD_NONE},
-      // MoveFocus(Left)
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
+    let inputs = vec!(parse_notation(concat!(
+      "a",       // MoveFocus(Left)
+      "x",       // Nothing
+      "a",       // MoveFocus(Left)
       // mistype cbabaa, get CloseWindow from mode0 (cba), MoveFocus(Right)
       // overriding from mode1 (ba), leaving c in the buffer
-      Key::Unicode{codepoint: 'c', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'c', mods: MOD_NONE},
+      "cbabac",
       // MoveFocus(Up) by properly typing cbabaa (c left from above)
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE}));
+      "babaa")));
     let outputs = vec!(
       Cmd::MoveFocus(frame::Direction::Left),
       Cmd::MoveFocus(frame::Direction::Left),
@@ -575,27 +927,32 @@ mod test {
     run_test(inputs, outputs, setup, callback);
   }
 
+  #[test]
+  fn no_timeout() {
+    let inputs = vec!(
+      parse_notation("ba"),  // No timeout: Nothing
+      parse_notation("b"));  // CloseWindow
+    let outputs = vec!(
+      Cmd::CloseWindow);
+    let setup = |cmd_thread: &CmdThread| {
+      cmd_thread.set_mode(mode_2(), 0);
+      cmd_thread.set_timeout(None); };
+    let callback = |_: Cmd, _: &CmdThread| {};
+    run_test(inputs, outputs, setup, callback);
+  }
+
   #[test]
   fn single_mode_timeout() {
     let inputs = vec!(
-      vec!(
-        // CloseWindow
-        Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-        Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-        Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-        // Timeout: ResetLayout
-        Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-        Key::Unicode{codepoint: 'a', mods: MOD_NONE}),
-      vec!(
-        // Timeout: Nothing
-        Key::Unicode{codepoint: 'b', mods: MOD_NONE}),
-      vec!(
-        // Quit
-        Key::Unicode{codepoint: 'a', mods: MOD_NONE}));
+      parse_notation(concat!(
+        "bab",  // CloseWindow
+        "ba")), // Timeout: ResetLayout
+      parse_notation("b"),  // Timeout: Nothing
+      parse_notation("a")); // Quit
     let outputs = vec!(
       Cmd::CloseWindow,
       Cmd::ResetLayout,
-      Cmd::Quit);
+      Cmd::Quit(false));
     let setup = |cmd_thread: &CmdThread| {
       cmd_thread.set_mode(mode_2(), 0); };
     let callback = |_: Cmd, _: &CmdThread| {};
@@ -604,19 +961,12 @@ mod test {
 
   #[test]
   fn multiple_mode_timeout() {
-    let inputs = vec!(vec!(
-      // Timeout: MoveFocus(Right) (bab override on mode1), throw c away,
-      // Quit (mode0), ResetLayout (mode0)
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'c', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE}));
+    // Timeout: MoveFocus(Right) (bab override on mode1), throw c away,
+    // Quit (mode0), ResetLayout (mode0)
+    let inputs = vec!(parse_notation("babcaba"));
     let outputs = vec!(
       Cmd::MoveFocus(frame::Direction::Right),
-      Cmd::Quit,
+      Cmd::Quit(false),
       Cmd::ResetLayout);
     let setup = |cmd_thread: &CmdThread| {
       cmd_thread.set_mode(mode_2(), 0);
@@ -627,11 +977,9 @@ mod test {
 
   #[test]
   fn change_mode() {
-    let inputs = vec!(vec!(
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE}));
+    let inputs = vec!(parse_notation("aa"));
     let outputs = vec!(
-      Cmd::Quit,
+      Cmd::Quit(false),
       Cmd::MoveFocus(frame::Direction::Left));
     let setup = |cmd_thread: &CmdThread| {
       cmd_thread.set_mode(mode_0(), 0); };
@@ -643,103 +991,297 @@ mod test {
   #[test]
   fn mode_fallback_command_constructor() {
     let inputs = vec!(
-      vec!(
-        // ResetLayout, normal match
-        Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-        Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-        // Fallback, plain miss
-        Key::Unicode{codepoint: 'x', mods: MOD_NONE},
+      parse_notation(concat!(
+        "ba",     // ResetLayout, normal match
+        "x",      // Fallback, plain miss
         // mistype dbad, triggering first miss then match on ba, then miss again
-        Key::Unicode{codepoint: 'd', mods: MOD_NONE},
-        Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-        Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-        Key::Unicode{codepoint: 'x', mods: MOD_NONE}),
-      vec!(
-        // timeout on partial dbad, triggering first miss then match on ba
-        Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-        Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-        Key::Unicode{codepoint: 'a', mods: MOD_NONE}));
+        "dbax")),
+      // timeout on partial dbad, triggering first miss then match on ba
+      parse_notation("bba"));
     let outputs = vec!(
       Cmd::ResetLayout,
-      Cmd::Quit,
-      Cmd::Quit,
+      Cmd::Quit(false),
+      Cmd::Quit(false),
       Cmd::ResetLayout,
-      Cmd::Quit,
-      Cmd::Quit,
+      Cmd::Quit(false),
+      Cmd::Quit(false),
       Cmd::ResetLayout);
     let setup = |cmd_thread: &CmdThread| {
       let mut mode = mode_0();
-      fn fallback(_: Key) -> Option<Cmd> { Some(Cmd::Quit) }
+      fn fallback(_: Key) -> Option<Cmd> { Some(Cmd::Quit(false)) }
       mode.fallback = fallback;
       cmd_thread.set_mode(mode, 0); };
     let callback = |_: Cmd, _: &CmdThread| {};
     run_test(inputs, outputs, setup, callback);
   }
 
+  #[test]
+  fn record_and_push_keys() {
+    // recording starts on r and stops on q, after which the recorded keys are
+    // pushed twice, ahead of the r which arrived after q
+    let inputs = vec!(parse_notation("rbaxaqr"));
+    let outputs = vec!(
+      Cmd::CloseWindow,
+      Cmd::ResetLayout,
+      Cmd::Quit(false),
+      Cmd::Quit(true),
+      Cmd::ResetLayout,
+      Cmd::Quit(false),
+      Cmd::ResetLayout,
+      Cmd::Quit(false),
+      Cmd::CloseWindow);
+    let setup = |cmd_thread: &CmdThread| {
+      let mut mode = mode_0();
+      mode.keychain.bind(&parse_notation("r"), Cmd::CloseWindow);
+      mode.keychain.bind(&parse_notation("q"), Cmd::Quit(true));
+      cmd_thread.set_mode(mode, 0); };
+    let callback = |cmd: Cmd, cmd_thread: &CmdThread| match cmd {
+      Cmd::CloseWindow => cmd_thread.start_recording(),
+      Cmd::Quit(true)  => {
+        // keys not making up a command are recorded too, unlike the q
+        let recorded = cmd_thread.stop_recording();
+        assert_eq!(recorded, parse_notation("baxa"));
+        cmd_thread.push_keys(recorded.iter().chain(recorded.iter()).
+                             cloned().collect());
+      }
+      _                => (),
+    };
+    run_test(inputs, outputs, setup, callback);
+  }
+
+  #[test]
+  fn mappings() {
+    // x is mapped to keys which are mapped no further, y to keys starting with
+    // y which isn't mapped again, while r maps to itself without end
+    let inputs = vec!(parse_notation("xy"), parse_notation("r"),
+                      parse_notation("a"));
+    let outputs = vec!(
+      Cmd::Quit(false),
+      Cmd::ResetLayout,
+      Cmd::ResetLayout,
+      Cmd::RecursiveMapping,
+      Cmd::Quit(false));
+    let setup = |cmd_thread: &CmdThread| {
+      let mut mode = mode_0();
+      for &(keys, to) in [("x", "aba"), ("y", "yba"), ("r", "qr")].iter() {
+        mode.mappings.bind(&parse_notation(keys),
+                           Cmd::Keys(parse_notation(to), true));
+      }
+      cmd_thread.set_mode(mode, 0); };
+    let callback = |_: Cmd, _: &CmdThread| {};
+    run_test(inputs, outputs, setup, callback);
+  }
+
+  #[test]
+  fn mapping_matching() {
+    let mut mode = mode_0();
+    mode.accepts_count = true;
+    let to = Cmd::Keys(parse_notation("ba"), true);
+    mode.mappings.bind(&parse_notation("a"), to.clone());
+    mode.mappings.bind(&parse_notation("xy"), to.clone());
+    let match_keys = |notation, force, remap|
+      mode.match_keys(parse_notation(notation).iter(), force, remap);
+
+    // mappings go before the keychain, unless the keys aren't to be mapped
+    assert_eq!(match_keys("a", false, true),
+               MatchResult::Complete(to.clone(), 1));
+    assert_eq!(match_keys("a", false, false),
+               MatchResult::Complete(Cmd::Quit(false), 1));
+    assert_eq!(match_keys("3a", false, true),
+               MatchResult::Complete(Cmd::Counted(3, Box::new(to.clone())), 2));
+    assert_eq!(match_keys("ba", false, true),
+               MatchResult::Complete(Cmd::ResetLayout, 2));
+
+    // a partial mapping waits for more keys, unless forced
+    assert_eq!(match_keys("x", false, true), MatchResult::Partial(1));
+    assert_eq!(match_keys("x", true, true), MatchResult::None);
+  }
+
+  #[test]
+  fn mapping_expansion() {
+    let expanded =
+      |cmd, matched| expand_mapping(&cmd, &parse_notation(matched));
+    let keys = |notation, origins: Vec<Origin>|
+      Some(parse_notation(notation).into_iter().zip(origins).collect());
+    let (remapped, kept) = (Origin::Mapped(true), Origin::Mapped(false));
+    assert_eq!(expanded(Cmd::Keys(parse_notation("dw"), true), "x"),
+               keys("dw", vec!(remapped, remapped)));
+    assert_eq!(expanded(Cmd::Keys(parse_notation("dw"), false), "x"),
+               keys("dw", vec!(kept, kept)));
+    // keys mapped to keys starting with them aren't mapped again at first
+    assert_eq!(expanded(Cmd::Keys(parse_notation("nzz"), true), "n"),
+               keys("nzz", vec!(kept, remapped, remapped)));
+    // a count goes in front
+    let counted = Cmd::Counted(12, Box::new(Cmd::Keys(parse_notation("nzz"),
+                                                      true)));
+    assert_eq!(expanded(counted, "12n"),
+               keys("12nzz", vec!(kept, kept, kept, remapped, remapped)));
+    assert_eq!(expanded(Cmd::Quit(false), "a"), None);
+  }
+
+  #[test]
+  fn count_matching() {
+    let mut mode = Mode::new();
+    mode.keychain.bind(&parse_notation("a"), Cmd::Quit(false));
+    mode.keychain.bind(&parse_notation("0"), Cmd::ResetLayout);
+    let match_keys = |mode: &Mode, notation, force|
+      mode.match_keys(parse_notation(notation).iter(), force, true);
+
+    // counts are only read by modes accepting them
+    assert_eq!(match_keys(&mode, "2a", false), MatchResult::None);
+    mode.accepts_count = true;
+    assert_eq!(match_keys(&mode, "a", false),
+               MatchResult::Complete(Cmd::Quit(false), 1));
+    assert_eq!(match_keys(&mode, "2a", false),
+               MatchResult::Complete(
+                 Cmd::Counted(2, Box::new(Cmd::Quit(false))), 2));
+    assert_eq!(match_keys(&mode, "105a", false),
+               MatchResult::Complete(
+                 Cmd::Counted(105, Box::new(Cmd::Quit(false))), 4));
+
+    // a lone count waits for more keys, unless forced
+    assert_eq!(match_keys(&mode, "10", false), MatchResult::Partial(2));
+    assert_eq!(match_keys(&mode, "10", true), MatchResult::None);
+
+    // a leading zero is not a count
+    assert_eq!(match_keys(&mode, "0", false),
+               MatchResult::Complete(Cmd::ResetLayout, 1));
+
+    // modified digits are not counts either
+    assert_eq!(match_keys(&mode, "<C-2>a", false), MatchResult::None);
+  }
+
   #[test]
   fn keychain_matching() {
     let mode = mode_4();
 
-    let match_test = |keys: Vec<Key>, regular, forced| {
+    let match_test = |notation, regular, forced| {
+      let keys = parse_notation(notation);
       assert_eq!(mode.keychain.match_keys(&mut keys.iter(), false), regular);
       assert_eq!(mode.keychain.match_keys(&mut keys.iter(), true), forced); };
 
     // no matches
-    let keys = vec!(Key::Unicode{codepoint: 'a', mods: MOD_NONE});
-    match_test(keys, MatchResult::None, MatchResult::None);
-    let keys = vec!(
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE});
-    match_test(keys, MatchResult::None, MatchResult::None);
+    match_test("a", MatchResult::None, MatchResult::None);
+    match_test("ab", MatchResult::None, MatchResult::None);
 
     // force a no match from a patial match
-    let keys = vec!(Key::Unicode{codepoint: 'b', mods: MOD_NONE});
-    match_test(keys, MatchResult::Partial(1), MatchResult::None);
+    match_test("b", MatchResult::Partial(1), MatchResult::None);
 
     // force a shorter complete match from a prefix of a longer command, the
     // shorter complete match must be the longest available
-    let keys = vec!(
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE});
-    match_test(keys, MatchResult::Partial(2),
-      MatchResult::Complete(Cmd::Quit, 2));
-    let keys = vec!(
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE});
-    match_test(keys, MatchResult::Partial(3),
+    match_test("ba", MatchResult::Partial(2),
+      MatchResult::Complete(Cmd::Quit(false), 2));
+    match_test("bab", MatchResult::Partial(3),
       MatchResult::Complete(Cmd::ResetLayout, 3));
-    let keys = vec!(
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE});
-    match_test(keys, MatchResult::Partial(4),
+    match_test("baba", MatchResult::Partial(4),
       MatchResult::Complete(Cmd::ResetLayout, 3));
 
     // mistype a longer command to trigger a complete match of a shorter command
     // that prefix the longer one, the shorter complete match must be the
     // longest available
-    let keys = vec!(
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'x', mods: MOD_NONE});
-    match_test(keys, MatchResult::Complete(Cmd::Quit, 2),
-      MatchResult::Complete(Cmd::Quit, 2));
-    let keys = vec!(
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'x', mods: MOD_NONE});
-    match_test(keys, MatchResult::Complete(Cmd::ResetLayout, 3),
+    match_test("bax", MatchResult::Complete(Cmd::Quit(false), 2),
+      MatchResult::Complete(Cmd::Quit(false), 2));
+    match_test("babx", MatchResult::Complete(Cmd::ResetLayout, 3),
       MatchResult::Complete(Cmd::ResetLayout, 3));
-    let keys = vec!(
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'b', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'a', mods: MOD_NONE},
-      Key::Unicode{codepoint: 'x', mods: MOD_NONE});
-    match_test(keys, MatchResult::Complete(Cmd::ResetLayout, 3),
+    match_test("babax", MatchResult::Complete(Cmd::ResetLayout, 3),
       MatchResult::Complete(Cmd::ResetLayout, 3));
+
+    // special and modified keys are told apart from characters
+    let mut mode = mode_4();
+    mode.keychain.bind(&parse_notation("<C-w>v<Esc>"), Cmd::CloseWindow);
+    assert_eq!(mode.keychain.match_keys(
+                 &mut parse_notation("<C-w>v<Esc>").iter(), false),
+               MatchResult::Complete(Cmd::CloseWindow, 3));
+    assert_eq!(mode.keychain.match_keys(
+                 &mut parse_notation("wv<Esc>").iter(), false),
+               MatchResult::None);
+  }
+
+  #[test]
+  fn keychain_unbinding() {
+    let mut mode = mode_4();
+    let match_keys = |mode: &Mode, notation, force|
+      mode.keychain.match_keys(&mut parse_notation(notation).iter(), force);
+
+    // longer commands are kept when a shorter one is unbound
+    assert!(mode.keychain.unbind(&parse_notation("bab")));
+    assert!(!mode.keychain.unbind(&parse_notation("bab")));
+    assert!(!mode.keychain.unbind(&parse_notation("b")));
+    assert_eq!(match_keys(&mode, "bab", true),
+               MatchResult::Complete(Cmd::Quit(false), 2));
+    assert_eq!(match_keys(&mode, "babab", false),
+               MatchResult::Complete(Cmd::CloseWindow, 5));
+
+    // keys leading only to an unbound command are left unbound too
+    assert!(mode.keychain.unbind(&parse_notation("babab")));
+    assert_eq!(match_keys(&mode, "ba", false),
+               MatchResult::Complete(Cmd::Quit(false), 2));
+    assert!(mode.keychain.unbind(&parse_notation("ba")));
+    assert_eq!(match_keys(&mode, "b", false), MatchResult::None);
+    assert!(mode.keychain.is_empty());
+  }
+
+  #[test]
+  fn keychain_bindings() {
+    let mode = mode_0();
+    let mut bindings: Vec<(String, Cmd)> = mode.keychain.bindings().iter().
+      map(|&(ref keys, cmd)| (keymap::to_notation(keys), cmd.clone())).
+      collect();
+    bindings.sort_by(|&(ref a, _), &(ref b, _)| a.cmp(b));
+    assert_eq!(bindings, vec!(("a".to_string(), Cmd::Quit(false)),
+                              ("ba".to_string(), Cmd::ResetLayout),
+                              ("cba".to_string(), Cmd::CloseWindow),
+                              ("dbad".to_string(), Cmd::CloseWindow)));
+  }
+
+  #[test]
+  fn repeat_changes() {
+    let delete_words = WinCmd::Operate(Operator::Delete,
+                                       Adjustment::WordNext(false));
+    let mut changes = Changes::new();
+    assert_eq!(changes.repeat(None), vec!());
+
+    // an operator along with its motion
+    changes.push(&delete_words, Some(2));
+    changes.finish(true);
+    assert_eq!(changes.repeat(None), vec!((delete_words.clone(), Some(2))));
+
+    // commands leaving the buffer unchanged, those which can't be repeated, and
+    // a repeat itself don't make a change of their own
+    changes.push(&WinCmd::MoveCaret(Adjustment::LineDown), None);
+    changes.finish(false);
+    changes.push(&WinCmd::Undo, None);
+    changes.finish(true);
+    changes.push(&WinCmd::RepeatChange, None);
+    changes.finish(true);
+    assert_eq!(changes.repeat(None), vec!((delete_words.clone(), Some(2))));
+
+    // an insert is made up of all commands until returning to normal mode
+    changes.push(&WinCmd::EnterInsertModeAppend, None);
+    changes.push(&WinCmd::Insert("ab".to_string()), None);
+    changes.push(&WinCmd::Backspace, None);
+    changes.push(&WinCmd::EnterNormalMode, None);
+    changes.finish(true);
+    let insert = vec!((WinCmd::EnterInsertModeAppend, None),
+                      (WinCmd::Insert("ab".to_string()), None),
+                      (WinCmd::Backspace, None),
+                      (WinCmd::EnterNormalMode, None));
+    assert_eq!(changes.repeat(None), insert);
+
+    // a new count replaces the one the change was made with, for later repeats
+    changes.push(&WinCmd::EnterInsertModeAppend, Some(3));
+    changes.push(&WinCmd::Insert("c".to_string()), None);
+    changes.push(&WinCmd::EnterNormalMode, None);
+    changes.finish(true);
+    assert_eq!(changes.repeat(None)[0], (WinCmd::EnterInsertModeAppend,
+                                         Some(3)));
+    let counted = vec!((WinCmd::EnterInsertModeAppend, Some(2)),
+                       (WinCmd::Insert("c".to_string()), None),
+                       (WinCmd::EnterNormalMode, None));
+    assert_eq!(changes.repeat(Some(2)), counted);
+    assert_eq!(changes.repeat(None), counted);
+    changes.push(&delete_words, None);
+    changes.finish(true);
+    assert_eq!(changes.repeat(Some(4)), vec!((delete_words.clone(), Some(4))));
   }
 }
@@ -0,0 +1,690 @@
+/*
+ * Copyright (c) 2015 Mathias Hällman
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::mem;
+use std::path::PathBuf;
+use std::result;
+
+use command::{Cmd, WinCmd};
+use frame;
+use keymap;
+
+/*
+ * An address refers to a line of a buffer, either by its number or relative to
+ * the current or the last line. Each kind of address carries an offset which
+ * is added to the line referred to. Line numbers are one-indexed, just the way
+ * they are typed.
+ */
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum Address {
+  Number(usize, isize),
+  Current(isize),
+  Last(isize),
+}
+
+impl Address {
+  // Resolves the address to a zero-indexed line given the zero-indexed current
+  // line and the number of lines in the buffer.
+  pub fn resolve(&self, current: usize, num_lines: usize) -> Result<usize> {
+    let line = self.unchecked_line(current, num_lines);
+    if line >= 0 && line < num_lines as isize { Ok(line as usize) }
+    else                                      { Err(Error::InvalidRange) }
+  }
+
+  // Like resolve, but clamps the line to the buffer rather than failing.
+  pub fn resolve_clamped(&self, current: usize, num_lines: usize) -> usize {
+    let line = self.unchecked_line(current, num_lines);
+    cmp::max(cmp::min(line, num_lines as isize - 1), 0) as usize
+  }
+
+  fn unchecked_line(&self, current: usize, num_lines: usize) -> isize {
+    match *self {
+      Address::Number(number, offset) =>
+        cmp::max(number as isize, 1) - 1 + offset,
+      Address::Current(offset)        => current as isize + offset,
+      Address::Last(offset)           => num_lines as isize - 1 + offset,
+    }
+  }
+
+  fn with_offset(self, offset: isize) -> Address {
+    match self {
+      Address::Number(number, _) => Address::Number(number, offset),
+      Address::Current(_)        => Address::Current(offset),
+      Address::Last(_)           => Address::Last(offset),
+    }
+  }
+}
+
+/*
+ * A range of lines, from one address through another.
+ */
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct Range(pub Address, pub Address);
+
+impl Range {
+  pub fn current_line() -> Range {
+    Range(Address::Current(0), Address::Current(0))
+  }
+
+  // Resolves the range to the zero-indexed first and last line it covers.
+  pub fn resolve(&self, current: usize, num_lines: usize)
+      -> Result<(usize, usize)> {
+    let Range(ref start, ref end) = *self;
+    let start = try!(start.resolve(current, num_lines));
+    let end = try!(end.resolve(current, num_lines));
+    if start <= end { Ok((start, end)) } else { Err(Error::BackwardsRange) }
+  }
+}
+
+/*
+ * A substitution replaces the matches of a pattern on each line of a range.
+ * Unless global, only the first match on each line is replaced. When asked to
+ * confirm, each match is replaced only once the user agrees to it.
+ */
+#[derive(Clone, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct Substitution {
+  pub pattern: String,
+  pub replacement: String,
+  pub global: bool,
+  pub confirm: bool,
+  pub ignore_case: bool,
+}
+
+/*
+ * The modes a mapping is made in. Mappings made without naming a mode are made
+ * in both normal and visual mode.
+ */
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum MapMode {
+  NormalVisual,
+  Normal,
+  Insert,
+}
+
+/*
+ * An option being set, either by its name alone, which turns it on or off if
+ * prefixed by no, or by giving it a value. An option may also be queried for
+ * its value by following its name by a question mark.
+ */
+#[derive(Clone, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum Setting {
+  Name(String),
+  Value(String, String),
+  Query(String),
+}
+
+/*
+ * The various errors that may result from parsing a command line.
+ */
+#[derive(Debug, PartialEq)]
+pub enum Error {
+  UnknownCommand(String),
+  TrailingCharacters(String),
+  ArgumentRequired,
+  NoRangeAllowed,
+  NoBangAllowed,
+  InvalidRange,
+  BackwardsRange,
+  InvalidDelimiter,
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Error::UnknownCommand(ref name)      =>
+        write!(f, "Not an editor command: {}", name),
+      Error::TrailingCharacters(ref chars) =>
+        write!(f, "Trailing characters: {}", chars),
+      _                                    =>
+        write!(f, "{}", error::Error::description(self)),
+    }
+  }
+}
+
+impl error::Error for Error {
+  fn description(&self) -> &str {
+    match *self {
+      Error::UnknownCommand(_)     => "Not an editor command",
+      Error::TrailingCharacters(_) => "Trailing characters",
+      Error::ArgumentRequired      => "Argument required",
+      Error::NoRangeAllowed        => "No range allowed",
+      Error::NoBangAllowed         => "No ! allowed",
+      Error::InvalidRange          => "Invalid range",
+      Error::BackwardsRange        => "Backwards range given",
+      Error::InvalidDelimiter      =>
+        "Regular expressions can't be delimited by letters",
+    }
+  }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+  Close,
+  Colorscheme,
+  Delete,
+  Edit,
+  Highlight,
+  Map(MapMode),
+  Noremap(MapMode),  // mapping to keys which aren't mapped again
+  Quit,
+  QuitAll,
+  Registers,
+  Set,
+  SetLocal,
+  Split,
+  Substitute,
+  Unmap(MapMode),
+  VerticalSplit,
+  Write,
+  WriteAll,
+  WriteQuit,
+  WriteQuitAll,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Argument {
+  Never,
+  Optional,
+  Required,
+}
+
+/*
+ * Definitions of the commands known to the parser. A command may be abbreviated
+ * down to a minimum length. Where abbreviations of two commands collide, the
+ * command listed first wins.
+ */
+struct Definition {
+  name: &'static str,
+  min_length: usize,
+  kind: Kind,
+  range: bool,
+  bang: bool,
+  argument: Argument,
+}
+
+const DEFINITIONS: &'static [Definition] = &[
+  Definition { name: "close", min_length: 3, kind: Kind::Close,
+               range: false, bang: false, argument: Argument::Never },
+  Definition { name: "colorscheme", min_length: 4, kind: Kind::Colorscheme,
+               range: false, bang: false, argument: Argument::Required },
+  Definition { name: "delete", min_length: 1, kind: Kind::Delete,
+               range: true, bang: false, argument: Argument::Never },
+  Definition { name: "edit", min_length: 1, kind: Kind::Edit,
+               range: false, bang: true, argument: Argument::Required },
+  Definition { name: "display", min_length: 2, kind: Kind::Registers,
+               range: false, bang: false, argument: Argument::Optional },
+  Definition { name: "highlight", min_length: 2, kind: Kind::Highlight,
+               range: false, bang: false, argument: Argument::Required },
+  Definition { name: "imap", min_length: 2, kind: Kind::Map(MapMode::Insert),
+               range: false, bang: false, argument: Argument::Optional },
+  Definition { name: "inoremap", min_length: 3,
+               kind: Kind::Noremap(MapMode::Insert),
+               range: false, bang: false, argument: Argument::Optional },
+  Definition { name: "iunmap", min_length: 2,
+               kind: Kind::Unmap(MapMode::Insert),
+               range: false, bang: false, argument: Argument::Required },
+  Definition { name: "map", min_length: 3,
+               kind: Kind::Map(MapMode::NormalVisual),
+               range: false, bang: false, argument: Argument::Optional },
+  Definition { name: "nmap", min_length: 2, kind: Kind::Map(MapMode::Normal),
+               range: false, bang: false, argument: Argument::Optional },
+  Definition { name: "nnoremap", min_length: 2,
+               kind: Kind::Noremap(MapMode::Normal),
+               range: false, bang: false, argument: Argument::Optional },
+  Definition { name: "noremap", min_length: 2,
+               kind: Kind::Noremap(MapMode::NormalVisual),
+               range: false, bang: false, argument: Argument::Optional },
+  Definition { name: "nunmap", min_length: 3,
+               kind: Kind::Unmap(MapMode::Normal),
+               range: false, bang: false, argument: Argument::Required },
+  Definition { name: "quit", min_length: 1, kind: Kind::Quit,
+               range: false, bang: true, argument: Argument::Never },
+  Definition { name: "qall", min_length: 2, kind: Kind::QuitAll,
+               range: false, bang: true, argument: Argument::Never },
+  Definition { name: "quitall", min_length: 5, kind: Kind::QuitAll,
+               range: false, bang: true, argument: Argument::Never },
+  Definition { name: "registers", min_length: 3, kind: Kind::Registers,
+               range: false, bang: false, argument: Argument::Optional },
+  Definition { name: "set", min_length: 2, kind: Kind::Set,
+               range: false, bang: false, argument: Argument::Required },
+  Definition { name: "setlocal", min_length: 4, kind: Kind::SetLocal,
+               range: false, bang: false, argument: Argument::Required },
+  Definition { name: "split", min_length: 2, kind: Kind::Split,
+               range: false, bang: false, argument: Argument::Never },
+  Definition { name: "substitute", min_length: 1, kind: Kind::Substitute,
+               range: true, bang: false, argument: Argument::Required },
+  Definition { name: "unmap", min_length: 3,
+               kind: Kind::Unmap(MapMode::NormalVisual),
+               range: false, bang: false, argument: Argument::Required },
+  Definition { name: "vsplit", min_length: 2, kind: Kind::VerticalSplit,
+               range: false, bang: false, argument: Argument::Never },
+  Definition { name: "write", min_length: 1, kind: Kind::Write,
+               range: false, bang: true, argument: Argument::Optional },
+  Definition { name: "wq", min_length: 2, kind: Kind::WriteQuit,
+               range: false, bang: true, argument: Argument::Optional },
+  Definition { name: "wqall", min_length: 3, kind: Kind::WriteQuitAll,
+               range: false, bang: true, argument: Argument::Never },
+  Definition { name: "wall", min_length: 2, kind: Kind::WriteAll,
+               range: false, bang: true, argument: Argument::Never },
+  Definition { name: "xit", min_length: 1, kind: Kind::WriteQuit,
+               range: false, bang: true, argument: Argument::Optional },
+  Definition { name: "xall", min_length: 2, kind: Kind::WriteQuitAll,
+               range: false, bang: true, argument: Argument::Never },
+];
+
+fn find_definition(name: &str) -> Option<&'static Definition> {
+  DEFINITIONS.iter().find(|def|
+    name.len() >= def.min_length && def.name.starts_with(name))
+}
+
+/*
+ * Scanner keeps track of what's left to parse of a command line.
+ */
+struct Scanner<'l> {
+  rest: &'l str,
+}
+
+impl<'l> Scanner<'l> {
+  fn peek(&self) -> Option<char> {
+    self.rest.chars().next()
+  }
+
+  fn next(&mut self) -> Option<char> {
+    self.peek().map(|c| { self.rest = &self.rest[c.len_utf8()..]; c })
+  }
+
+  fn eat(&mut self, character: char) -> bool {
+    if self.peek() == Some(character) {
+      self.rest = &self.rest[character.len_utf8()..];
+      true
+    } else { false }
+  }
+
+  fn take_while<P>(&mut self, predicate: P) -> &'l str
+      where P: Fn(char) -> bool {
+    let end = self.rest.char_indices().find(|&(_, c)| !predicate(c)).
+      map(|(i, _)| i).unwrap_or(self.rest.len());
+    let taken = &self.rest[..end];
+    self.rest = &self.rest[end..];
+    taken
+  }
+
+  fn number(&mut self) -> Option<usize> {
+    self.take_while(|c| c.is_digit(10)).parse().ok()
+  }
+}
+
+fn parse_address(scanner: &mut Scanner) -> Option<Address> {
+  let base = match scanner.peek() {
+    Some(c) if c.is_digit(10) =>
+      scanner.number().map(|number| Address::Number(number, 0)),
+    Some('.')                 => { scanner.eat('.'); Some(Address::Current(0)) }
+    Some('$')                 => { scanner.eat('$'); Some(Address::Last(0)) }
+    _                         => None,
+  };
+  // a lone offset is relative to the current line
+  let mut offset = None;
+  loop {
+    let sign = if scanner.eat('+')      { 1 }
+               else if scanner.eat('-') { -1 }
+               else                     { break };
+    let amount = scanner.number().unwrap_or(1) as isize;
+    offset = Some(offset.unwrap_or(0) + sign * amount);
+  }
+  match (base, offset) {
+    (Some(base), Some(offset)) => Some(base.with_offset(offset)),
+    (Some(base), None)         => Some(base),
+    (None,       Some(offset)) => Some(Address::Current(offset)),
+    (None,       None)         => None,
+  }
+}
+
+fn parse_range(scanner: &mut Scanner) -> Option<Range> {
+  if scanner.eat('%') {
+    return Some(Range(Address::Number(1, 0), Address::Last(0)));
+  }
+  let start = parse_address(scanner);
+  if scanner.eat(',') {
+    // an omitted address on either side of the comma is the current line
+    let end = parse_address(scanner);
+    Some(Range(start.unwrap_or(Address::Current(0)),
+               end.unwrap_or(Address::Current(0))))
+  }
+  else { start.map(|address| Range(address, address)) }
+}
+
+// Takes what's left up to an unescaped |delimiter|, eating the delimiter too.
+// An escaped delimiter stands for the delimiter itself, while any other escaped
+// character is left escaped.
+fn parse_delimited(scanner: &mut Scanner, delimiter: char) -> String {
+  let mut text = String::new();
+  while let Some(c) = scanner.next() {
+    if c == delimiter { break; }
+    if c == '\\' && !scanner.eat(delimiter) {
+      text.push(c);
+      scanner.next().map(|escaped| text.push(escaped));
+    }
+    else { text.push(if c == '\\' { delimiter } else { c }); }
+  }
+  text
+}
+
+// Parses the argument of a substitution, e.g. /pattern/replacement/flags where
+// the slashes may be any character but a letter, a digit or a backslash.
+fn parse_substitution(argument: &str) -> Result<Substitution> {
+  let mut scanner = Scanner { rest: argument };
+  let delimiter = match scanner.next() {
+    Some(c) if !c.is_alphanumeric() && c != '\\' => c,
+    _                                             =>
+      return Err(Error::InvalidDelimiter),
+  };
+  let pattern = parse_delimited(&mut scanner, delimiter);
+  let replacement = parse_delimited(&mut scanner, delimiter);
+  let flags = scanner.take_while(|c| c == 'g' || c == 'c' || c == 'i');
+  let rest = scanner.rest.trim();
+  if !rest.is_empty() {
+    return Err(Error::TrailingCharacters(rest.to_string()));
+  }
+  Ok(Substitution {
+    pattern: pattern,
+    replacement: replacement,
+    global: flags.contains('g'),
+    confirm: flags.contains('c'),
+    ignore_case: flags.contains('i'),
+  })
+}
+
+// Parses the keys of a mapping and the keys they map to, which are mapped again
+// if |remap|. Without keys to map to, the mappings of the keys are listed.
+fn parse_mapping(mode: MapMode, argument: &str, remap: bool) -> Cmd {
+  let (keys, to) = argument.split_at(
+    argument.find(char::is_whitespace).unwrap_or(argument.len()));
+  if to.is_empty() { Cmd::ListMappings(mode, keymap::parse_notation(keys)) }
+  else {
+    Cmd::Map(mode, keymap::parse_notation(keys),
+             keymap::parse_notation(to.trim_left()), remap)
+  }
+}
+
+// Parses the blank separated settings of :set, e.g. "ts=4 noet sw?". A blank
+// preceded by a backslash is part of the setting, as is a backslash preceded
+// by another.
+fn parse_settings(argument: &str) -> Vec<Setting> {
+  let mut words = Vec::new();
+  let mut word = String::new();
+  let mut chars = argument.chars().peekable();
+  let escapable = |c: char| c == '\\' || c.is_whitespace();
+  while let Some(c) = chars.next() {
+    if c == '\\' && chars.peek().cloned().map(&escapable).unwrap_or(false) {
+      word.push(chars.next().unwrap());
+    }
+    else if !c.is_whitespace() { word.push(c); }
+    else if !word.is_empty() {
+      words.push(mem::replace(&mut word, String::new()));
+    }
+  }
+  if !word.is_empty() { words.push(word); }
+  words.into_iter().map(|word|
+    match word.find('=') {
+      Some(i)                      =>
+        Setting::Value(word[..i].to_string(), word[i + 1..].to_string()),
+      None if word.ends_with('?') =>
+        Setting::Query(word[..word.len() - 1].to_string()),
+      None                         => Setting::Name(word.to_string()),
+    }).collect()
+}
+
+// Parses a command line, as typed after the colon, into a command. A command
+// line holding nothing but a range goes to the last line of that range, while
+// an entirely empty command line results in no command at all.
+pub fn parse(line: &str) -> Result<Option<Cmd>> {
+  let mut scanner = Scanner { rest: line.trim_left_matches(|c: char|
+    c == ':' || c.is_whitespace()) };
+  let range = parse_range(&mut scanner);
+  scanner.take_while(char::is_whitespace);
+  let name = scanner.take_while(char::is_alphabetic);
+  if name.is_empty() {
+    return if !scanner.rest.trim().is_empty() {
+      Err(Error::UnknownCommand(scanner.rest.trim().to_string()))
+    }
+    else {
+      Ok(range.map(|Range(_, end)| Cmd::WinCmd(WinCmd::GotoLine(end))))
+    };
+  }
+  let bang = scanner.eat('!');
+  let argument = scanner.rest.trim();
+  let argument = if argument.is_empty() { None } else { Some(argument) };
+
+  let def = try!(find_definition(name).
+    ok_or(Error::UnknownCommand(name.to_string())));
+  if range.is_some() && !def.range { return Err(Error::NoRangeAllowed); }
+  if bang && !def.bang { return Err(Error::NoBangAllowed); }
+  match (def.argument, argument) {
+    (Argument::Never,    Some(arg)) =>
+      return Err(Error::TrailingCharacters(arg.to_string())),
+    (Argument::Required, None)      => return Err(Error::ArgumentRequired),
+    _                               => (),
+  }
+
+  // the pattern and replacement of a substitution may begin or end with blanks
+  let substitution = if def.kind == Kind::Substitute {
+    Some(try!(parse_substitution(scanner.rest.trim_left())))
+  } else { None };
+
+  let path = argument.map(PathBuf::from);
+  let argument = argument.unwrap_or("");
+  Ok(Some(match def.kind {
+    Kind::Close         => Cmd::CloseWindow,
+    Kind::Colorscheme   => Cmd::Colorscheme(argument.to_string()),
+    Kind::Delete        =>
+      Cmd::WinCmd(WinCmd::DeleteLines(range.unwrap_or(Range::current_line()))),
+    Kind::Edit          =>
+      Cmd::WinCmd(WinCmd::OpenBuffer(path.expect("Edit lacked a path."),
+                                     bang)),
+    Kind::Highlight     => {
+      let (group, settings) = argument.split_at(
+        argument.find(char::is_whitespace).unwrap_or(argument.len()));
+      Cmd::Highlight(group.to_string(), parse_settings(settings))
+    }
+    Kind::Map(mode)     => parse_mapping(mode, argument, true),
+    Kind::Noremap(mode) => parse_mapping(mode, argument, false),
+    Kind::Quit          => Cmd::QuitWindow(bang),
+    Kind::QuitAll       => Cmd::Quit(bang),
+    Kind::Registers     =>
+      Cmd::ShowRegisters(argument.chars().filter(|c| !c.is_whitespace()).
+                         collect()),
+    Kind::Set           => Cmd::Set(parse_settings(argument), false),
+    Kind::SetLocal      => Cmd::Set(parse_settings(argument), true),
+    Kind::Split         => Cmd::SplitWindow(frame::Orientation::Horizontal),
+    Kind::Substitute    =>
+      Cmd::WinCmd(WinCmd::Substitute(range.unwrap_or(Range::current_line()),
+        substitution.expect("Substitute lacked a substitution."))),
+    Kind::Unmap(mode)   => Cmd::Unmap(mode, keymap::parse_notation(argument)),
+    Kind::VerticalSplit => Cmd::SplitWindow(frame::Orientation::Vertical),
+    Kind::Write         => Cmd::WinCmd(WinCmd::SaveBuffer(path, bang)),
+    Kind::WriteAll      => Cmd::WriteAll,
+    Kind::WriteQuit     => Cmd::WriteQuitWindow(path, bang),
+    Kind::WriteQuitAll  => Cmd::WriteQuitAll,
+  }))
+}
+
+#[cfg(test)]
+mod test {
+  use std::path::PathBuf;
+
+  use command::{Cmd, WinCmd};
+  use frame;
+  use keymap;
+
+  use super::*;
+
+  #[test]
+  fn abbreviations() {
+    let tests = [
+      ("q", Cmd::QuitWindow(false)), ("quit", Cmd::QuitWindow(false)),
+      ("qa", Cmd::Quit(false)), ("qall", Cmd::Quit(false)),
+      ("quita", Cmd::Quit(false)), ("q!", Cmd::QuitWindow(true)),
+      ("qa!", Cmd::Quit(true)), ("wa", Cmd::WriteAll),
+      ("wqa", Cmd::WriteQuitAll), ("xa", Cmd::WriteQuitAll),
+      ("clo", Cmd::CloseWindow), ("close", Cmd::CloseWindow),
+      ("sp", Cmd::SplitWindow(frame::Orientation::Horizontal)),
+      ("vs", Cmd::SplitWindow(frame::Orientation::Vertical)),
+      ("w", Cmd::WinCmd(WinCmd::SaveBuffer(None, false))),
+      ("wq", Cmd::WriteQuitWindow(None, false)),
+      ("x", Cmd::WriteQuitWindow(None, false)),
+    ];
+    for &(line, ref cmd) in tests.iter() {
+      assert_eq!(parse(line), Ok(Some(cmd.clone())));
+    }
+    assert_eq!(parse("cl"), Err(Error::UnknownCommand("cl".to_string())));
+    assert_eq!(parse("quits"), Err(Error::UnknownCommand("quits".to_string())));
+  }
+
+  #[test]
+  fn arguments_and_bang() {
+    assert_eq!(parse("w other.txt"), Ok(Some(Cmd::WinCmd(
+      WinCmd::SaveBuffer(Some(PathBuf::from("other.txt")), false)))));
+    assert_eq!(parse("w! other.txt"), Ok(Some(Cmd::WinCmd(
+      WinCmd::SaveBuffer(Some(PathBuf::from("other.txt")), true)))));
+    assert_eq!(parse("  :e!  some file  "), Ok(Some(Cmd::WinCmd(
+      WinCmd::OpenBuffer(PathBuf::from("some file"), true)))));
+    assert_eq!(parse("e file"), Ok(Some(Cmd::WinCmd(
+      WinCmd::OpenBuffer(PathBuf::from("file"), false)))));
+    assert_eq!(parse("wq!"), Ok(Some(Cmd::WriteQuitWindow(None, true))));
+    assert_eq!(parse("e"), Err(Error::ArgumentRequired));
+    assert_eq!(parse("q now"),
+               Err(Error::TrailingCharacters("now".to_string())));
+    assert_eq!(parse("sp!"), Err(Error::NoBangAllowed));
+    assert_eq!(parse("3q"), Err(Error::NoRangeAllowed));
+    assert_eq!(parse("#"), Err(Error::UnknownCommand("#".to_string())));
+    assert_eq!(parse(""), Ok(None));
+  }
+
+  #[test]
+  fn ranges() {
+    let delete = |start, end|
+      Ok(Some(Cmd::WinCmd(WinCmd::DeleteLines(Range(start, end)))));
+    assert_eq!(parse("d"),
+               delete(Address::Current(0), Address::Current(0)));
+    assert_eq!(parse("%d"), delete(Address::Number(1, 0), Address::Last(0)));
+    assert_eq!(parse("3,$-2d"),
+               delete(Address::Number(3, 0), Address::Last(-2)));
+    assert_eq!(parse(".+2,+3 d"),
+               delete(Address::Current(2), Address::Current(3)));
+    assert_eq!(parse(",5-1+3del"),
+               delete(Address::Current(0), Address::Number(5, 2)));
+    assert_eq!(parse("--,."),
+               Ok(Some(Cmd::WinCmd(WinCmd::GotoLine(Address::Current(0))))));
+    assert_eq!(parse("12"),
+               Ok(Some(Cmd::WinCmd(WinCmd::GotoLine(Address::Number(12, 0))))));
+  }
+
+  #[test]
+  fn substitutions() {
+    let substitute = |range, pattern: &str, replacement: &str, global, confirm,
+                      ignore_case| Ok(Some(Cmd::WinCmd(WinCmd::Substitute(range,
+      Substitution { pattern: pattern.to_string(),
+                     replacement: replacement.to_string(), global: global,
+                     confirm: confirm, ignore_case: ignore_case }))));
+    assert_eq!(parse("s/foo/bar/"),
+               substitute(Range::current_line(), "foo", "bar",
+                          false, false, false));
+    assert_eq!(parse("%s/a b/ c /gci"),
+               substitute(Range(Address::Number(1, 0), Address::Last(0)),
+                          "a b", " c ", true, true, true));
+    // the delimiter may be escaped, any other escape is kept as it is
+    assert_eq!(parse("2,3substitute#a\\#\\w#\\1\\\\#g"),
+               substitute(Range(Address::Number(2, 0), Address::Number(3, 0)),
+                          "a#\\w", "\\1\\\\", true, false, false));
+    // trailing delimiters may be left out
+    assert_eq!(parse("s/foo"),
+               substitute(Range::current_line(), "foo", "",
+                          false, false, false));
+    assert_eq!(parse("s/foo/bar"),
+               substitute(Range::current_line(), "foo", "bar",
+                          false, false, false));
+    assert_eq!(parse("s/a/b/gx"),
+               Err(Error::TrailingCharacters("x".to_string())));
+    assert_eq!(parse("s a b "), Err(Error::InvalidDelimiter));
+    assert_eq!(parse("s"), Err(Error::ArgumentRequired));
+    assert_eq!(parse("sp"),
+               Ok(Some(Cmd::SplitWindow(frame::Orientation::Horizontal))));
+  }
+
+  #[test]
+  fn mappings_and_settings() {
+    let keys = keymap::parse_notation;
+    assert_eq!(parse("nmap <C-w>x  :w<CR> dd"), Ok(Some(Cmd::Map(
+      MapMode::Normal, keys("<C-w>x"), keys(":w<CR> dd"), true))));
+    assert_eq!(parse("map Y y$"), Ok(Some(Cmd::Map(
+      MapMode::NormalVisual, keys("Y"), keys("y$"), true))));
+    assert_eq!(parse("im jk <Esc>"), Ok(Some(Cmd::Map(
+      MapMode::Insert, keys("jk"), keys("<Esc>"), true))));
+    assert_eq!(parse("nn n nzz"), Ok(Some(Cmd::Map(
+      MapMode::Normal, keys("n"), keys("nzz"), false))));
+    assert_eq!(parse("no Y y$"), Ok(Some(Cmd::Map(
+      MapMode::NormalVisual, keys("Y"), keys("y$"), false))));
+    assert_eq!(parse("inoremap jk <Esc>"), Ok(Some(Cmd::Map(
+      MapMode::Insert, keys("jk"), keys("<Esc>"), false))));
+    assert_eq!(parse("nmap x"),
+               Ok(Some(Cmd::ListMappings(MapMode::Normal, keys("x")))));
+    assert_eq!(parse("map"),
+               Ok(Some(Cmd::ListMappings(MapMode::NormalVisual, vec!()))));
+    assert_eq!(parse("reg a\"0"),
+               Ok(Some(Cmd::ShowRegisters("a\"0".to_string()))));
+    assert_eq!(parse("di"), Ok(Some(Cmd::ShowRegisters(String::new()))));
+    assert_eq!(parse("unm Y"),
+               Ok(Some(Cmd::Unmap(MapMode::NormalVisual, keys("Y")))));
+    assert_eq!(parse("iunmap jk "),
+               Ok(Some(Cmd::Unmap(MapMode::Insert, keys("jk")))));
+    assert_eq!(parse("set sw=4 noexpandtab ts?"), Ok(Some(Cmd::Set(vec!(
+      Setting::Value("sw".to_string(), "4".to_string()),
+      Setting::Name("noexpandtab".to_string()),
+      Setting::Query("ts".to_string())), false))));
+    assert_eq!(parse("setl so=3"), Ok(Some(Cmd::Set(vec!(
+      Setting::Value("so".to_string(), "3".to_string())), true))));
+    assert_eq!(parse("se"), Err(Error::ArgumentRequired));
+    // blanks and backslashes may be escaped
+    assert_eq!(parse("set cbc=xsel\\ -i\\ -b  a=\\\\\\x"),
+               Ok(Some(Cmd::Set(vec!(
+      Setting::Value("cbc".to_string(), "xsel -i -b".to_string()),
+      Setting::Value("a".to_string(), "\\\\x".to_string())), false))));
+    assert_eq!(parse("hi Comment fg=red  attr=bold"),
+               Ok(Some(Cmd::Highlight("Comment".to_string(), vec!(
+      Setting::Value("fg".to_string(), "red".to_string()),
+      Setting::Value("attr".to_string(), "bold".to_string()))))));
+    assert_eq!(parse("highlight clear"),
+               Ok(Some(Cmd::Highlight("clear".to_string(), vec!()))));
+    assert_eq!(parse("colo dusk "),
+               Ok(Some(Cmd::Colorscheme("dusk".to_string()))));
+    assert_eq!(parse("colorscheme"), Err(Error::ArgumentRequired));
+  }
+
+  #[test]
+  fn resolve_ranges() {
+    let range = Range(Address::Current(-1), Address::Last(0));
+    assert_eq!(range.resolve(3, 10), Ok((2, 9)));
+    assert_eq!(range.resolve(0, 10), Err(Error::InvalidRange));
+    assert_eq!(Range(Address::Number(5, 0), Address::Number(2, 0)).
+               resolve(0, 10), Err(Error::BackwardsRange));
+    assert_eq!(Address::Number(0, 0).resolve(5, 10), Ok(0));
+    assert_eq!(Address::Number(11, 0).resolve(5, 10), Err(Error::InvalidRange));
+    assert_eq!(Address::Number(11, 0).resolve_clamped(5, 10), 9);
+    assert_eq!(Address::Current(-7).resolve_clamped(5, 10), 0);
+  }
+}
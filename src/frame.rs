@@ -14,6 +14,8 @@ use std::error;
 use std::fmt;
 use std::result;
 
+#[cfg(not(test))]
+use highlight::{Group, Highlights};
 use screen;
 #[cfg(not(test))]
 use screen::Screen;
@@ -111,7 +113,7 @@ pub enum Orientation {
 }
 
 impl Orientation {
-  fn opposite(&self) -> Orientation {
+  pub fn opposite(&self) -> Orientation {
     match *self {
       Vertical   => Horizontal,
       Horizontal => Vertical,
@@ -496,17 +498,19 @@ impl Section {
   }
 
   #[cfg(not(test))]
-  fn draw_borders(&self, position: screen::Cell, screen: &mut Screen) {
+  fn draw_borders(&self, position: screen::Cell, highlights: &Highlights,
+                  screen: &mut Screen) {
     self.split.as_ref().map(|ref split| {
-      split.fst.draw_borders(position, screen);
+      split.fst.draw_borders(position, highlights, screen);
       split.snd.draw_borders(
-        snd_position(position, split.fst.size, split.orientation), screen);
+        snd_position(position, split.fst.size, split.orientation), highlights,
+        screen);
 
       for screen_cell in screen::CellIterator::new(
           border_rect(position, split.orientation, split.fst.size)) {
         let border_char = ' ';
-        let border_color = screen::Color::Cyan;
-        screen.put(screen_cell, border_char, border_color, border_color);
+        screen.put(screen_cell, border_char,
+                   highlights.style(Group::VertSplit));
       }
     });
   }
@@ -928,8 +932,8 @@ impl Frame {
   }
 
   #[cfg(not(test))]
-  pub fn draw_borders(&self, screen: &mut Screen) {
-    self.main_section.draw_borders(screen::Cell(0, 0), screen);
+  pub fn draw_borders(&self, highlights: &Highlights, screen: &mut Screen) {
+    self.main_section.draw_borders(screen::Cell(0, 0), highlights, screen);
   }
 }
 
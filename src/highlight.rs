@@ -0,0 +1,489 @@
+/*
+ * Copyright (c) 2015 Mathias Hällman
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::path::{self, Path, PathBuf};
+use std::result;
+
+use ex::Setting;
+use screen::{Attributes, Color, Style};
+use screen::{ATTR_BOLD, ATTR_ITALIC, ATTR_NONE, ATTR_REVERSE,
+             ATTR_STRIKETHROUGH, ATTR_UNDERCURL, ATTR_UNDERLINE};
+
+/*
+ * The groups things on screen are highlighted in, named like those of Vim.
+ * Some are parts of the editor, such as the status line, while the rest are
+ * what syntax highlighting puts text in.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(test, derive(Debug))]
+pub enum Group {
+  Normal,  // the text of the focused window
+  NormalNC,  // the text of other windows
+  Cursor,
+  CursorLine,
+  LineNr,
+  NonText,  // the tildes below the end of a buffer
+  Search,
+  Visual,
+  StatusLine,
+  StatusLineNC,
+  VertSplit,
+  MsgArea,
+  Comment,
+  Constant,
+  String,
+  Number,
+  Identifier,
+  Function,
+  Keyword,
+  Operator,
+  PreProc,
+  Type,
+  Special,
+  Error,
+}
+
+const GROUP_NAMES: &'static [(Group, &'static str)] = &[
+  (Group::Normal,       "Normal"),
+  (Group::NormalNC,     "NormalNC"),
+  (Group::Cursor,       "Cursor"),
+  (Group::CursorLine,   "CursorLine"),
+  (Group::LineNr,       "LineNr"),
+  (Group::NonText,      "NonText"),
+  (Group::Search,       "Search"),
+  (Group::Visual,       "Visual"),
+  (Group::StatusLine,   "StatusLine"),
+  (Group::StatusLineNC, "StatusLineNC"),
+  (Group::VertSplit,    "VertSplit"),
+  (Group::MsgArea,      "MsgArea"),
+  (Group::Comment,      "Comment"),
+  (Group::Constant,     "Constant"),
+  (Group::String,       "String"),
+  (Group::Number,       "Number"),
+  (Group::Identifier,   "Identifier"),
+  (Group::Function,     "Function"),
+  (Group::Keyword,      "Keyword"),
+  (Group::Operator,     "Operator"),
+  (Group::PreProc,      "PreProc"),
+  (Group::Type,         "Type"),
+  (Group::Special,      "Special"),
+  (Group::Error,        "Error"),
+];
+
+impl Group {
+  pub fn name(&self) -> &'static str {
+    GROUP_NAMES.iter().find(|&&(group, _)| group == *self).
+      map(|&(_, name)| name).expect("Group lacked a name.")
+  }
+
+  // the group named |name|, ignoring case like Vim does
+  pub fn from_name(name: &str) -> Option<Group> {
+    let name = name.to_lowercase();
+    GROUP_NAMES.iter().find(|&&(_, group_name)|
+      group_name.to_lowercase() == name).map(|&(group, _)| group)
+  }
+
+  // How the group is highlighted unless a color scheme says otherwise. These
+  // keep the focused window dark on light, and other windows light on dark.
+  fn default_highlight(&self) -> Highlight {
+    use screen::Color::*;
+    let colors = |fg, bg| Highlight { fg: Some(fg), bg: Some(bg),
+                                      attributes: ATTR_NONE };
+    let text = |fg| Highlight { fg: Some(fg), ..Highlight::none() };
+    match *self {
+      Group::Normal       => colors(Black, White),
+      Group::NormalNC     => colors(White, Black),
+      Group::Cursor       =>
+        Highlight { attributes: ATTR_REVERSE, ..Highlight::none() },
+      Group::CursorLine   =>
+        Highlight { attributes: ATTR_UNDERLINE, ..Highlight::none() },
+      Group::LineNr       => colors(Yellow, Black),
+      Group::NonText      => Highlight::none(),
+      Group::Search       => colors(Black, Yellow),
+      Group::Visual       => colors(White, Blue),
+      Group::StatusLine   => colors(Black, White),
+      Group::StatusLineNC => colors(White, BrightBlack),
+      Group::VertSplit    => colors(Cyan, Cyan),
+      Group::MsgArea      => colors(White, Black),
+      Group::Comment      => text(BrightBlue),
+      Group::Constant     => text(BrightMagenta),
+      Group::String       => text(BrightMagenta),
+      Group::Number       => text(BrightMagenta),
+      Group::Identifier   => text(BrightCyan),
+      Group::Function     => text(BrightCyan),
+      Group::Keyword      => text(BrightYellow),
+      Group::Operator     => text(BrightYellow),
+      Group::PreProc      => text(Magenta),
+      Group::Type         => text(Green),
+      Group::Special      => text(Red),
+      Group::Error        => text(BrightRed),
+    }
+  }
+}
+
+/*
+ * A highlight gives a group its colors and attributes. A color left out is
+ * taken from whatever the group is drawn on top of, while the attributes are
+ * added to those of it.
+ */
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct Highlight {
+  pub fg: Option<Color>,
+  pub bg: Option<Color>,
+  pub attributes: Attributes,
+}
+
+impl Highlight {
+  fn none() -> Highlight {
+    Highlight { fg: None, bg: None, attributes: ATTR_NONE }
+  }
+}
+
+/*
+ * One part of a highlight being changed, as given by :highlight.
+ */
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum Key {
+  Fg(Option<Color>),
+  Bg(Option<Color>),
+  Attributes(Attributes),
+}
+
+/*
+ * Highlights holds the highlight of every group, as set by the color scheme.
+ */
+pub struct Highlights {
+  highlights: HashMap<Group, Highlight>,
+}
+
+impl Highlights {
+  // the default highlights of every group
+  pub fn new() -> Highlights {
+    Highlights {
+      highlights: GROUP_NAMES.iter().
+        map(|&(group, _)| (group, group.default_highlight())).collect()
+    }
+  }
+
+  pub fn get(&self, group: Group) -> Highlight {
+    self.highlights.get(&group).cloned().
+      unwrap_or(group.default_highlight())
+  }
+
+  pub fn set(&mut self, group: Group, keys: &[Key]) {
+    let mut highlight = self.get(group);
+    for key in keys {
+      match *key {
+        Key::Fg(color)              => highlight.fg = color,
+        Key::Bg(color)              => highlight.bg = color,
+        Key::Attributes(attributes) => highlight.attributes = attributes,
+      }
+    }
+    self.highlights.insert(group, highlight);
+  }
+
+  // The style of |group|, the colors it leaves out being those of Normal, or
+  // else white on black.
+  pub fn style(&self, group: Group) -> Style {
+    let default = Style::new(Color::White, Color::Black);
+    self.style_over(group, self.style_over(Group::Normal, default))
+  }
+
+  // The style of |group| drawn on top of something in the style of |base|.
+  pub fn style_over(&self, group: Group, base: Style) -> Style {
+    let highlight = self.get(group);
+    Style {
+      fg: highlight.fg.unwrap_or(base.fg),
+      bg: highlight.bg.unwrap_or(base.bg),
+      attributes: base.attributes | highlight.attributes,
+    }
+  }
+
+  // Describes the highlight of |group| the way it would be set, e.g.
+  // Comment fg=blue bg=NONE attr=bold.
+  pub fn show(&self, group: Group) -> String {
+    let highlight = self.get(group);
+    let color = |color: Option<Color>|
+      color.map(show_color).unwrap_or("NONE".to_string());
+    format!("{} fg={} bg={} attr={}", group.name(), color(highlight.fg),
+            color(highlight.bg), show_attributes(highlight.attributes))
+  }
+}
+
+const COLOR_NAMES: &'static [(Color, &'static str)] = &[
+  (Color::Black,         "black"),
+  (Color::Red,           "red"),
+  (Color::Green,         "green"),
+  (Color::Yellow,        "yellow"),
+  (Color::Blue,          "blue"),
+  (Color::Magenta,       "magenta"),
+  (Color::Cyan,          "cyan"),
+  (Color::White,         "white"),
+  (Color::BrightBlack,   "brightblack"),
+  (Color::BrightRed,     "brightred"),
+  (Color::BrightGreen,   "brightgreen"),
+  (Color::BrightYellow,  "brightyellow"),
+  (Color::BrightBlue,    "brightblue"),
+  (Color::BrightMagenta, "brightmagenta"),
+  (Color::BrightCyan,    "brightcyan"),
+  (Color::BrightWhite,   "brightwhite"),
+];
+
+const ATTRIBUTE_NAMES: &'static [(Attributes, &'static str)] = &[
+  (ATTR_BOLD,          "bold"),
+  (ATTR_ITALIC,        "italic"),
+  (ATTR_UNDERLINE,     "underline"),
+  (ATTR_UNDERCURL,     "undercurl"),
+  (ATTR_REVERSE,       "reverse"),
+  (ATTR_STRIKETHROUGH, "strikethrough"),
+];
+
+// Parses a color given by name, by its index among 256 colors, or in RGB as
+// #rrggbb. NONE leaves the color out.
+fn parse_color(value: &str) -> Option<Option<Color>> {
+  let lower = value.to_lowercase();
+  if lower == "none" { return Some(None); }
+  if let Some(&(color, _)) = COLOR_NAMES.iter().find(|&&(_, n)| n == lower) {
+    return Some(Some(color));
+  }
+  if value.starts_with('#') && value.len() == 7 {
+    let component = |i: usize| u8::from_str_radix(&value[i..i + 2], 16).ok();
+    return match (component(1), component(3), component(5)) {
+      (Some(r), Some(g), Some(b)) => Some(Some(Color::Rgb(r, g, b))),
+      _                           => None,
+    };
+  }
+  value.parse().ok().map(|index| Some(Color::Indexed(index)))
+}
+
+fn show_color(color: Color) -> String {
+  match color {
+    Color::Indexed(index)  => index.to_string(),
+    Color::Rgb(r, g, b)    => format!("#{:02x}{:02x}{:02x}", r, g, b),
+    _                      => COLOR_NAMES.iter().find(|&&(c, _)| c == color).
+                              map(|&(_, name)| name.to_string()).
+                              expect("Color lacked a name."),
+  }
+}
+
+// Parses a comma separated list of attributes, or NONE for no attributes.
+fn parse_attributes(value: &str) -> Option<Attributes> {
+  if value.to_lowercase() == "none" { return Some(ATTR_NONE); }
+  value.split(',').fold(Some(ATTR_NONE), |attributes, name| {
+    let name = name.to_lowercase();
+    attributes.and_then(|attributes|
+      ATTRIBUTE_NAMES.iter().find(|&&(_, n)| n == name).
+      map(|&(attribute, _)| attributes | attribute))
+  })
+}
+
+fn show_attributes(attributes: Attributes) -> String {
+  let names: Vec<&str> = ATTRIBUTE_NAMES.iter().
+    filter(|&&(attribute, _)| attributes.contains(attribute)).
+    map(|&(_, name)| name).collect();
+  if names.is_empty() { "NONE".to_string() } else { names.join(",") }
+}
+
+/*
+ * What a :highlight amounts to, either resetting every group to its default,
+ * changing the highlight of a group, or showing it.
+ */
+#[derive(Clone, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum Change {
+  Clear,
+  Set(Group, Vec<Key>),
+  Show(Group),
+}
+
+// Resolves the arguments of :highlight into a change. The group named is set
+// by fg=, bg= and attr= settings, or shown if there are none. Naming clear
+// rather than a group resets all of them.
+pub fn resolve(name: &str, settings: &[Setting]) -> Result<Change> {
+  if name == "clear" && settings.is_empty() { return Ok(Change::Clear); }
+  let group = try!(Group::from_name(name).
+    ok_or(Error::UnknownGroup(name.to_string())));
+  if settings.is_empty() { return Ok(Change::Show(group)); }
+  settings.iter().map(|setting| match *setting {
+    Setting::Value(ref key, ref value) => {
+      let invalid = || Error::InvalidArgument(format!("{}={}", key, value));
+      match &key[..] {
+        "fg"   => parse_color(value).map(Key::Fg).ok_or_else(invalid),
+        "bg"   => parse_color(value).map(Key::Bg).ok_or_else(invalid),
+        "attr" => parse_attributes(value).map(Key::Attributes).
+                  ok_or_else(invalid),
+        _      => Err(invalid()),
+      }
+    }
+    Setting::Name(ref word) | Setting::Query(ref word) =>
+      Err(Error::InvalidArgument(word.to_string())),
+  }).collect::<Result<Vec<Key>>>().map(|keys| Change::Set(group, keys))
+}
+
+// The file of the color scheme |name| in the colors directory of |config_dir|,
+// if there is one. Names leading out of the directory are never found.
+pub fn colorscheme_path(config_dir: &Path, name: &str) -> Option<PathBuf> {
+  if name.is_empty() || name.chars().any(path::is_separator) { return None; }
+  let path = config_dir.join("colors").join(format!("{}.rim", name));
+  if path.is_file() { Some(path) } else { None }
+}
+
+/*
+ * The various errors that may result from resolving a :highlight.
+ */
+#[derive(Debug, PartialEq)]
+pub enum Error {
+  UnknownGroup(String),
+  InvalidArgument(String),
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Error::UnknownGroup(ref argument)    |
+      Error::InvalidArgument(ref argument) =>
+        write!(f, "{}: {}", error::Error::description(self), argument),
+    }
+  }
+}
+
+impl error::Error for Error {
+  fn description(&self) -> &str {
+    match *self {
+      Error::UnknownGroup(_)    => "Unknown highlight group",
+      Error::InvalidArgument(_) => "Invalid argument",
+    }
+  }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[cfg(test)]
+mod test {
+  use std::fs::File;
+  use std::io::{BufRead, BufReader};
+  use std::path::Path;
+
+  use command::Cmd;
+  use ex::{self, Setting};
+  use screen::{Color, Style};
+  use screen::{ATTR_BOLD, ATTR_ITALIC, ATTR_NONE, ATTR_REVERSE,
+               ATTR_UNDERLINE};
+
+  use super::*;
+
+  #[test]
+  fn resolve_highlights() {
+    let value = |name: &str, value: &str|
+      Setting::Value(name.to_string(), value.to_string());
+    assert_eq!(resolve("clear", &[]), Ok(Change::Clear));
+    assert_eq!(resolve("comment", &[]), Ok(Change::Show(Group::Comment)));
+    assert_eq!(resolve("StatusLineNC", &[value("fg", "#1a2B3c"),
+                                         value("bg", "NONE"),
+                                         value("attr", "bold,Italic")]),
+               Ok(Change::Set(Group::StatusLineNC, vec!(
+                 Key::Fg(Some(Color::Rgb(0x1a, 0x2b, 0x3c))), Key::Bg(None),
+                 Key::Attributes(ATTR_BOLD | ATTR_ITALIC)))));
+    assert_eq!(resolve("Visual", &[value("fg", "BrightRed"),
+                                   value("bg", "236"),
+                                   value("attr", "none")]),
+               Ok(Change::Set(Group::Visual, vec!(
+                 Key::Fg(Some(Color::BrightRed)),
+                 Key::Bg(Some(Color::Indexed(236))),
+                 Key::Attributes(ATTR_NONE)))));
+    assert_eq!(resolve("Foo", &[]),
+               Err(Error::UnknownGroup("Foo".to_string())));
+    assert_eq!(resolve("Search", &[value("fg", "purple")]),
+               Err(Error::InvalidArgument("fg=purple".to_string())));
+    assert_eq!(resolve("Search", &[value("bg", "256")]),
+               Err(Error::InvalidArgument("bg=256".to_string())));
+    assert_eq!(resolve("Search", &[value("bg", "#12345g")]),
+               Err(Error::InvalidArgument("bg=#12345g".to_string())));
+    assert_eq!(resolve("Search", &[value("attr", "bold,blink")]),
+               Err(Error::InvalidArgument("attr=bold,blink".to_string())));
+    assert_eq!(resolve("Search", &[value("guifg", "red")]),
+               Err(Error::InvalidArgument("guifg=red".to_string())));
+    assert_eq!(resolve("Search", &[Setting::Name("bold".to_string())]),
+               Err(Error::InvalidArgument("bold".to_string())));
+  }
+
+  #[test]
+  fn styles() {
+    let mut highlights = Highlights::new();
+    assert_eq!(highlights.style(Group::Normal),
+               Style::new(Color::Black, Color::White));
+    // colors left out are those of Normal, or of what is drawn on
+    assert_eq!(highlights.style(Group::Comment),
+               Style::new(Color::BrightBlue, Color::White));
+    let dark = highlights.style(Group::NormalNC);
+    assert_eq!(highlights.style_over(Group::Comment, dark),
+               Style::new(Color::BrightBlue, Color::Black));
+    assert_eq!(highlights.style_over(Group::Cursor, dark),
+               Style { attributes: ATTR_REVERSE, ..dark });
+
+    highlights.set(Group::Normal, &[Key::Fg(None), Key::Bg(None)]);
+    assert_eq!(highlights.style(Group::Normal),
+               Style::new(Color::White, Color::Black));
+    highlights.set(Group::Comment, &[Key::Attributes(ATTR_ITALIC)]);
+    let underlined = highlights.style_over(Group::CursorLine, dark);
+    assert_eq!(highlights.style_over(Group::Comment, underlined), Style {
+      attributes: ATTR_UNDERLINE | ATTR_ITALIC,
+      ..Style::new(Color::BrightBlue, Color::Black)
+    });
+  }
+
+  #[test]
+  fn show_highlights() {
+    let mut highlights = Highlights::new();
+    assert_eq!(highlights.show(Group::Comment),
+               "Comment fg=brightblue bg=NONE attr=NONE");
+    highlights.set(Group::Comment, &[Key::Bg(Some(Color::Rgb(0, 16, 255))),
+                                     Key::Attributes(ATTR_BOLD | ATTR_ITALIC)]);
+    assert_eq!(highlights.show(Group::Comment),
+               "Comment fg=brightblue bg=#0010ff attr=bold,italic");
+    highlights.set(Group::Comment, &[Key::Fg(Some(Color::Indexed(100)))]);
+    assert_eq!(highlights.show(Group::Comment),
+               "Comment fg=100 bg=#0010ff attr=bold,italic");
+  }
+
+  #[test]
+  fn colorschemes() {
+    let config_dir = Path::new("tests/highlight");
+    let path = colorscheme_path(config_dir, "dim").unwrap();
+    assert_eq!(path, Path::new("tests/highlight/colors/dim.rim"));
+    // the lines of the file are executed, such as :highlight
+    let mut highlights = Highlights::new();
+    for line in BufReader::new(File::open(&path).unwrap()).lines() {
+      let line = line.unwrap();
+      if line.starts_with('"') { continue; }
+      match ex::parse(&line) {
+        Ok(Some(Cmd::Highlight(name, settings))) =>
+          match resolve(&name, &settings).unwrap() {
+            Change::Clear            => highlights = Highlights::new(),
+            Change::Set(group, keys) => highlights.set(group, &keys),
+            Change::Show(_)          => panic!("Showed a highlight."),
+          },
+        _                                        => panic!("Not a highlight."),
+      }
+    }
+    assert_eq!(highlights.style(Group::Comment), Style {
+      attributes: ATTR_ITALIC,
+      ..Style::new(Color::Indexed(240), Color::Black)
+    });
+    // names are looked for in the colors directory only
+    assert_eq!(colorscheme_path(config_dir, "missing"), None);
+    assert_eq!(colorscheme_path(config_dir, "../../highlight/colors/dim"),
+               None);
+    assert_eq!(colorscheme_path(config_dir, ""), None);
+  }
+}
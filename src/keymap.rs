@@ -6,6 +6,8 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/.
  */
 
+use std::fmt;
+
 /*
  * A key can be of any of the following types:
  *   - Function key such as F1 or F24
@@ -109,3 +111,253 @@ bitflags! {
     const MOD_CTRL  = 1 << 2,
   }
 }
+
+/*
+ * The names special keys are written with within angle brackets, the way Vim
+ * names them where it does. A key is written with the first name listed for
+ * it, while the names following are read as well. Characters are written by
+ * name only when they couldn't be told apart otherwise.
+ */
+const SYM_NAMES: &'static [(KeySym, &'static str)] = &[
+  (KeySym::Unknown, "Unknown"),
+  (KeySym::Backspace, "BS"),
+  (KeySym::Tab, "Tab"),
+  (KeySym::Enter, "CR"),
+  (KeySym::Escape, "Esc"),
+  (KeySym::Space, "Space"),
+  (KeySym::Del, "Char-127"),
+  (KeySym::Up, "Up"),
+  (KeySym::Down, "Down"),
+  (KeySym::Left, "Left"),
+  (KeySym::Right, "Right"),
+  (KeySym::Begin, "Begin"),
+  (KeySym::Find, "Find"),
+  (KeySym::Insert, "Insert"),
+  (KeySym::Delete, "Del"),
+  (KeySym::Select, "Select"),
+  (KeySym::Pageup, "PageUp"),
+  (KeySym::Pagedown, "PageDown"),
+  (KeySym::Home, "Home"),
+  (KeySym::End, "End"),
+  (KeySym::Cancel, "Cancel"),
+  (KeySym::Clear, "Clear"),
+  (KeySym::Close, "Close"),
+  (KeySym::Command, "Command"),
+  (KeySym::Copy, "Copy"),
+  (KeySym::Exit, "Exit"),
+  (KeySym::Help, "Help"),
+  (KeySym::Mark, "Mark"),
+  (KeySym::Message, "Message"),
+  (KeySym::Move, "Move"),
+  (KeySym::Open, "Open"),
+  (KeySym::Options, "Options"),
+  (KeySym::Print, "Print"),
+  (KeySym::Redo, "Redo"),
+  (KeySym::Reference, "Reference"),
+  (KeySym::Refresh, "Refresh"),
+  (KeySym::Replace, "Replace"),
+  (KeySym::Restart, "Restart"),
+  (KeySym::Resume, "Resume"),
+  (KeySym::Save, "Save"),
+  (KeySym::Suspend, "Suspend"),
+  (KeySym::Undo, "Undo"),
+  (KeySym::KP0, "k0"),
+  (KeySym::KP1, "k1"),
+  (KeySym::KP2, "k2"),
+  (KeySym::KP3, "k3"),
+  (KeySym::KP4, "k4"),
+  (KeySym::KP5, "k5"),
+  (KeySym::KP6, "k6"),
+  (KeySym::KP7, "k7"),
+  (KeySym::KP8, "k8"),
+  (KeySym::KP9, "k9"),
+  (KeySym::KPEnter, "kEnter"),
+  (KeySym::KPPlus, "kPlus"),
+  (KeySym::KPMinus, "kMinus"),
+  (KeySym::KPMult, "kMultiply"),
+  (KeySym::KPDiv, "kDivide"),
+  (KeySym::KPComma, "kComma"),
+  (KeySym::KPPeriod, "kPoint"),
+  (KeySym::KPEquals, "kEqual"),
+  (KeySym::Enter, "Enter"),
+  (KeySym::Enter, "Return"),
+  (KeySym::Escape, "Escape"),
+  (KeySym::Delete, "Delete"),
+];
+
+const CHAR_NAMES: &'static [(char, &'static str)] = &[
+  (' ', "Space"),
+  ('<', "lt"),
+  ('|', "Bar"),
+  ('\\', "Bslash"),
+];
+
+const MOD_NAMES: &'static [(KeyMod, char)] = &[
+  (MOD_CTRL, 'C'),
+  (MOD_SHIFT, 'S'),
+  (MOD_ALT, 'M'),
+  (MOD_ALT, 'A'),
+];
+
+/*
+ * Keys are displayed the way parse_notation reads them, e.g. <C-w> or <S-F5>.
+ * Symbolic keys without a name of their own, such as None, show as <Unknown>.
+ */
+impl fmt::Display for Key {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let (name, mods) = match *self {
+      Key::Fn{num, mods}             => (format!("F{}", num), mods),
+      Key::Sym{sym, mods}            => {
+        let name = SYM_NAMES.iter().find(|&&(named, _)| named == sym).
+          unwrap_or(&SYM_NAMES[0]).1;
+        (name.to_string(), mods)
+      }
+      Key::Unicode{codepoint, mods}  => {
+        let name = CHAR_NAMES.iter().find(|&&(c, _)| c == codepoint).
+          map(|&(_, name)| name.to_string());
+        match name {
+          Some(ref name) if codepoint == ' ' || codepoint == '<' ||
+                            mods != MOD_NONE => (name.clone(), mods),
+          _ if mods == MOD_NONE              =>
+            return write!(f, "{}", codepoint),
+          _                                  => (codepoint.to_string(), mods),
+        }
+      }
+    };
+    try!(write!(f, "<"));
+    let mut written = MOD_NONE;
+    for &(modifier, letter) in MOD_NAMES.iter() {
+      if mods.contains(modifier) && !written.contains(modifier) {
+        try!(write!(f, "{}-", letter));
+        written = written | modifier;
+      }
+    }
+    write!(f, "{}>", name)
+  }
+}
+
+// Writes keys the way parse_notation reads them.
+pub fn to_notation(keys: &[Key]) -> String {
+  keys.iter().map(|key| key.to_string()).collect()
+}
+
+// Parses keys written the way Vim writes them, e.g. "<C-w>v" or "ifoo<Esc>".
+// Special and modified keys are named within angle brackets, while any other
+// character stands for itself, as does anything within brackets which isn't
+// understood. Space is read as a character, the way the terminal reads it.
+pub fn parse_notation(notation: &str) -> Vec<Key> {
+  let mut keys = Vec::new();
+  let mut rest = notation;
+  while let Some(c) = rest.chars().next() {
+    let special = if c == '<' {
+      rest[1..].find('>').and_then(|end|
+        parse_special(&rest[1..end + 1]).map(|key| (key, end + 2)))
+    } else { None };
+    let (key, len) = special.unwrap_or_else(||
+      (Key::Unicode{codepoint: c, mods: MOD_NONE}, c.len_utf8()));
+    keys.push(key);
+    rest = &rest[len..];
+  }
+  keys
+}
+
+// Parses what's within angle brackets, such as "Esc", "C-w" or "S-F5".
+fn parse_special(name: &str) -> Option<Key> {
+  let mut mods = MOD_NONE;
+  let mut name = name;
+  while name.len() > 2 && name.as_bytes()[1] == b'-' {
+    let letter = name.as_bytes()[0].to_ascii_uppercase() as char;
+    match MOD_NAMES.iter().find(|&&(_, named)| named == letter) {
+      Some(&(modifier, _)) => mods = mods | modifier,
+      None                 => return None,
+    }
+    name = &name[2..];
+  }
+  let mut chars = name.chars();
+  match (chars.next(), chars.next()) {
+    // a lone character needs a modifier to be special, control going with
+    // lowercase letters the way keys are read from the terminal
+    (Some(c), None) if mods != MOD_NONE => {
+      let c = if mods.contains(MOD_CTRL) { c.to_ascii_lowercase() } else { c };
+      return Some(Key::Unicode{codepoint: c, mods: mods});
+    }
+    (Some(_), None)                     => return None,
+    _                                   => (),
+  }
+  let named = |named: &str| named.eq_ignore_ascii_case(name);
+  if let Some(&(c, _)) = CHAR_NAMES.iter().find(|&&(_, name)| named(name)) {
+    return Some(Key::Unicode{codepoint: c, mods: mods});
+  }
+  if let Some(&(sym, _)) = SYM_NAMES.iter().find(|&&(_, name)| named(name)) {
+    return Some(Key::Sym{sym: sym, mods: mods});
+  }
+  if name.starts_with('F') || name.starts_with('f') {
+    if let Ok(num) = name[1..].parse() {
+      return Some(Key::Fn{num: num, mods: mods});
+    }
+  }
+  None
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn parse() {
+    let key = |c| Key::Unicode{codepoint: c, mods: MOD_NONE};
+    let sym = |sym| Key::Sym{sym: sym, mods: MOD_NONE};
+    assert_eq!(parse_notation("<C-w>v"),
+               vec!(Key::Unicode{codepoint: 'w', mods: MOD_CTRL}, key('v')));
+    assert_eq!(parse_notation("ia b<space><esc>"), vec!(key('i'), key('a'),
+      key(' '), key('b'), key(' '), sym(KeySym::Escape)));
+    assert_eq!(parse_notation("<C-W><S-F5><M-x><a-C-Del>"),
+               vec!(Key::Unicode{codepoint: 'w', mods: MOD_CTRL},
+                    Key::Fn{num: 5, mods: MOD_SHIFT},
+                    Key::Unicode{codepoint: 'x', mods: MOD_ALT},
+                    Key::Sym{sym: KeySym::Delete, mods: MOD_ALT | MOD_CTRL}));
+    assert_eq!(parse_notation(":w<CR><PageDown><kEnter>"), vec!(key(':'),
+      key('w'), sym(KeySym::Enter), sym(KeySym::Pagedown),
+      sym(KeySym::KPEnter)));
+    // anything not understood within brackets is taken as it is
+    assert_eq!(parse_notation("<lt><a><>"), vec!(key('<'), key('<'), key('a'),
+      key('>'), key('<'), key('>')));
+    assert_eq!(parse_notation("<X-y><F>"),
+               "<X-y><F>".chars().map(key).collect::<Vec<_>>());
+    assert_eq!(parse_notation("<"), vec!(key('<')));
+  }
+
+  #[test]
+  fn display() {
+    let key = |c, mods| Key::Unicode{codepoint: c, mods: mods};
+    assert_eq!(to_notation(&[key('w', MOD_CTRL), key('v', MOD_NONE)]),
+               "<C-w>v");
+    assert_eq!(to_notation(&[key('<', MOD_NONE), key(' ', MOD_NONE),
+                             key('|', MOD_NONE), key('|', MOD_ALT)]),
+               "<lt><Space>|<M-Bar>");
+    assert_eq!(format!("{}", Key::Fn{num: 5, mods: MOD_SHIFT}), "<S-F5>");
+    assert_eq!(format!("{}", Key::Sym{sym: KeySym::Pagedown,
+                                      mods: MOD_CTRL | MOD_SHIFT | MOD_ALT}),
+               "<C-S-M-PageDown>");
+    assert_eq!(format!("{}", Key::Sym{sym: KeySym::NSyms, mods: MOD_NONE}),
+               "<Unknown>");
+  }
+
+  #[test]
+  fn round_trip() {
+    // every named key reads back as itself, with or without modifiers
+    for &(sym, _) in SYM_NAMES.iter() {
+      for &mods in [MOD_NONE, MOD_SHIFT, MOD_CTRL | MOD_ALT].iter() {
+        let key = Key::Sym{sym: sym, mods: mods};
+        let keys = parse_notation(&key.to_string());
+        // space is read as a character rather than a symbol
+        if sym == KeySym::Space {
+          assert_eq!(keys, vec!(Key::Unicode{codepoint: ' ', mods: mods}));
+        }
+        else { assert_eq!(keys, vec!(key)); }
+      }
+    }
+    let keys = parse_notation("<C-a>Ä<F12><M-lt><Bslash><S-BS>");
+    assert_eq!(parse_notation(&to_notation(&keys)), keys);
+  }
+}
@@ -0,0 +1,341 @@
+/*
+ * Copyright (c) 2015 Mathias Hällman
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::result;
+
+use ex::Setting;
+
+/*
+ * The options which may be set to change how rim behaves.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(test, derive(Debug))]
+pub enum Opt {
+  ClipboardCopy,
+  ClipboardPaste,
+  CursorLine,
+  ExpandTab,
+  IgnoreCase,
+  LineBreak,
+  Number,
+  RelativeNumber,
+  ScrollOff,
+  ShiftWidth,
+  SignColumn,
+  TabStop,
+  Timeout,
+  TimeoutLen,
+  Wrap,
+}
+
+#[derive(Clone, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum Value {
+  Bool(bool),
+  Number(usize),
+  Text(String),
+}
+
+/*
+ * Options are either global, or local to each buffer or window. Local options
+ * have global values too, which are given to buffers and windows as they are
+ * created.
+ */
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum Scope {
+  Global,
+  Buffer,
+  Window,
+}
+
+/*
+ * Definitions of the options, each having a name, a short name and a default
+ * value. Numbers may not be set below their minimum.
+ */
+struct Definition {
+  opt: Opt,
+  name: &'static str,
+  short_name: &'static str,
+  scope: Scope,
+  default: Value,
+  min: usize,
+}
+
+const DEFINITIONS: &'static [Definition] = &[
+  Definition { opt: Opt::ClipboardCopy, name: "clipboardcopy",
+               short_name: "cbc", scope: Scope::Global,
+               default: Value::Text(String::new()), min: 0 },
+  Definition { opt: Opt::ClipboardPaste, name: "clipboardpaste",
+               short_name: "cbp", scope: Scope::Global,
+               default: Value::Text(String::new()), min: 0 },
+  Definition { opt: Opt::CursorLine, name: "cursorline", short_name: "cul",
+               scope: Scope::Window, default: Value::Bool(false), min: 0 },
+  Definition { opt: Opt::ExpandTab, name: "expandtab", short_name: "et",
+               scope: Scope::Buffer, default: Value::Bool(false), min: 0 },
+  Definition { opt: Opt::IgnoreCase, name: "ignorecase", short_name: "ic",
+               scope: Scope::Global, default: Value::Bool(false), min: 0 },
+  Definition { opt: Opt::LineBreak, name: "linebreak", short_name: "lbr",
+               scope: Scope::Window, default: Value::Bool(false), min: 0 },
+  Definition { opt: Opt::Number, name: "number", short_name: "nu",
+               scope: Scope::Window, default: Value::Bool(false), min: 0 },
+  Definition { opt: Opt::RelativeNumber, name: "relativenumber",
+               short_name: "rnu", scope: Scope::Window,
+               default: Value::Bool(false), min: 0 },
+  Definition { opt: Opt::ScrollOff, name: "scrolloff", short_name: "so",
+               scope: Scope::Window, default: Value::Number(0), min: 0 },
+  Definition { opt: Opt::ShiftWidth, name: "shiftwidth", short_name: "sw",
+               scope: Scope::Buffer, default: Value::Number(2), min: 1 },
+  Definition { opt: Opt::SignColumn, name: "signcolumn", short_name: "scl",
+               scope: Scope::Window, default: Value::Number(0), min: 0 },
+  Definition { opt: Opt::TabStop, name: "tabstop", short_name: "ts",
+               scope: Scope::Buffer, default: Value::Number(8), min: 1 },
+  Definition { opt: Opt::Timeout, name: "timeout", short_name: "to",
+               scope: Scope::Global, default: Value::Bool(true), min: 0 },
+  Definition { opt: Opt::TimeoutLen, name: "timeoutlen", short_name: "tm",
+               scope: Scope::Global, default: Value::Number(3000), min: 0 },
+  Definition { opt: Opt::Wrap, name: "wrap", short_name: "wrap",
+               scope: Scope::Window, default: Value::Bool(false), min: 0 },
+];
+
+impl Definition {
+  fn is_bool(&self) -> bool {
+    match self.default { Value::Bool(_)                    => true,
+                         Value::Number(_) | Value::Text(_) => false }
+  }
+}
+
+fn definition(opt: Opt) -> &'static Definition {
+  DEFINITIONS.iter().find(|def| def.opt == opt).
+    expect("Option lacked a definition.")
+}
+
+fn find_definition(name: &str) -> Option<&'static Definition> {
+  DEFINITIONS.iter().find(|def| def.name == name || def.short_name == name)
+}
+
+impl Opt {
+  pub fn scope(&self) -> Scope {
+    definition(*self).scope
+  }
+}
+
+/*
+ * Options holds a value for each option of some scopes, such as those local to
+ * a buffer, or all of them in the case of the global values.
+ */
+#[derive(Clone)]
+pub struct Options {
+  values: HashMap<Opt, Value>,
+}
+
+impl Options {
+  // the default values of every option
+  pub fn new() -> Options {
+    Options {
+      values: DEFINITIONS.iter().map(|def| (def.opt, def.default.clone())).
+        collect()
+    }
+  }
+
+  // the values of the options local to |scope|
+  pub fn local(&self, scope: Scope) -> Options {
+    Options {
+      values: self.values.iter().filter(|&(opt, _)| opt.scope() == scope).
+        map(|(opt, value)| (*opt, value.clone())).collect()
+    }
+  }
+
+  pub fn get(&self, opt: Opt) -> Value {
+    self.values.get(&opt).cloned().
+      unwrap_or_else(|| definition(opt).default.clone())
+  }
+
+  pub fn bool(&self, opt: Opt) -> bool {
+    match self.get(opt) {
+      Value::Bool(on) => on,
+      _               => panic!("Option wasn't a boolean."),
+    }
+  }
+
+  pub fn number(&self, opt: Opt) -> usize {
+    match self.get(opt) {
+      Value::Number(number) => number,
+      _                     => panic!("Option wasn't a number."),
+    }
+  }
+
+  pub fn text(&self, opt: Opt) -> String {
+    match self.get(opt) {
+      Value::Text(text) => text,
+      _                 => panic!("Option wasn't text."),
+    }
+  }
+
+  pub fn set(&mut self, opt: Opt, value: Value) {
+    self.values.insert(opt, value);
+  }
+
+  // Describes the value of |opt| the way it would be set, e.g. shiftwidth=4,
+  // noexpandtab or clipboardcopy=xsel\ -i.
+  pub fn show(&self, opt: Opt) -> String {
+    let name = definition(opt).name;
+    match self.get(opt) {
+      Value::Bool(true)     => name.to_string(),
+      Value::Bool(false)    => format!("no{}", name),
+      Value::Number(number) => format!("{}={}", name, number),
+      Value::Text(text)     => format!("{}={}", name,
+        text.replace('\\', "\\\\").replace(' ', "\\ ")),
+    }
+  }
+}
+
+/*
+ * What a setting amounts to, either setting an option to a value or showing
+ * the value it has.
+ */
+#[derive(Clone, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum Change {
+  Set(Opt, Value),
+  Show(Opt),
+}
+
+// Resolves a setting into a change. Naming a boolean option turns it on, while
+// prefixing it by no turns it off. Naming any other option shows its value, as
+// does following any option by a question mark.
+pub fn resolve(setting: &Setting) -> Result<Change> {
+  let unknown = |name: &str| Error::UnknownOption(name.to_string());
+  match *setting {
+    Setting::Name(ref name)            => {
+      let negated = if name.starts_with("no") { find_definition(&name[2..]) }
+                    else                      { None };
+      match (find_definition(name), negated) {
+        (Some(def), _) if def.is_bool()    =>
+          Ok(Change::Set(def.opt, Value::Bool(true))),
+        (Some(def), _)                     => Ok(Change::Show(def.opt)),
+        (None, Some(def)) if def.is_bool() =>
+          Ok(Change::Set(def.opt, Value::Bool(false))),
+        _                                  => Err(unknown(name)),
+      }
+    }
+    Setting::Query(ref name)           =>
+      find_definition(name).map(|def| Change::Show(def.opt)).
+      ok_or(unknown(name)),
+    Setting::Value(ref name, ref value) => {
+      let def = try!(find_definition(name).ok_or(unknown(name)));
+      let invalid = || Error::InvalidArgument(format!("{}={}", name, value));
+      match def.default {
+        Value::Bool(_)   => Err(invalid()),
+        Value::Text(_)   =>
+          Ok(Change::Set(def.opt, Value::Text(value.to_string()))),
+        Value::Number(_) => match value.parse() {
+          Ok(number) if number >= def.min =>
+            Ok(Change::Set(def.opt, Value::Number(number))),
+          Ok(_)                           => Err(invalid()),
+          Err(_)                          =>
+            Err(Error::NumberRequired(format!("{}={}", name, value))),
+        },
+      }
+    }
+  }
+}
+
+/*
+ * The various errors that may result from resolving a setting.
+ */
+#[derive(Debug, PartialEq)]
+pub enum Error {
+  UnknownOption(String),
+  InvalidArgument(String),
+  NumberRequired(String),
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Error::UnknownOption(ref setting)   |
+      Error::InvalidArgument(ref setting) |
+      Error::NumberRequired(ref setting)  =>
+        write!(f, "{}: {}", error::Error::description(self), setting),
+    }
+  }
+}
+
+impl error::Error for Error {
+  fn description(&self) -> &str {
+    match *self {
+      Error::UnknownOption(_)   => "Unknown option",
+      Error::InvalidArgument(_) => "Invalid argument",
+      Error::NumberRequired(_)  => "Number required after =",
+    }
+  }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[cfg(test)]
+mod test {
+  use ex::Setting;
+
+  use super::*;
+
+  #[test]
+  fn resolve_settings() {
+    let name = |name: &str| Setting::Name(name.to_string());
+    let value = |name: &str, value: &str|
+      Setting::Value(name.to_string(), value.to_string());
+    assert_eq!(resolve(&name("expandtab")),
+               Ok(Change::Set(Opt::ExpandTab, Value::Bool(true))));
+    assert_eq!(resolve(&name("noic")),
+               Ok(Change::Set(Opt::IgnoreCase, Value::Bool(false))));
+    assert_eq!(resolve(&name("ts")), Ok(Change::Show(Opt::TabStop)));
+    assert_eq!(resolve(&Setting::Query("et".to_string())),
+               Ok(Change::Show(Opt::ExpandTab)));
+    assert_eq!(resolve(&value("sw", "4")),
+               Ok(Change::Set(Opt::ShiftWidth, Value::Number(4))));
+    assert_eq!(resolve(&value("so", "0")),
+               Ok(Change::Set(Opt::ScrollOff, Value::Number(0))));
+    assert_eq!(resolve(&name("nots")),
+               Err(Error::UnknownOption("nots".to_string())));
+    assert_eq!(resolve(&name("foo")),
+               Err(Error::UnknownOption("foo".to_string())));
+    assert_eq!(resolve(&value("ts", "0")),
+               Err(Error::InvalidArgument("ts=0".to_string())));
+    assert_eq!(resolve(&value("et", "1")),
+               Err(Error::InvalidArgument("et=1".to_string())));
+    assert_eq!(resolve(&value("ts", "x")),
+               Err(Error::NumberRequired("ts=x".to_string())));
+    assert_eq!(resolve(&value("cbc", "xsel -i")),
+               Ok(Change::Set(Opt::ClipboardCopy,
+                              Value::Text("xsel -i".to_string()))));
+    assert_eq!(resolve(&name("clipboardpaste")),
+               Ok(Change::Show(Opt::ClipboardPaste)));
+  }
+
+  #[test]
+  fn scopes_and_values() {
+    let mut options = Options::new();
+    assert_eq!(options.number(Opt::TabStop), 8);
+    assert!(!options.bool(Opt::ExpandTab));
+    options.set(Opt::TabStop, Value::Number(4));
+    let buffer_options = options.local(Scope::Buffer);
+    assert_eq!(buffer_options.number(Opt::TabStop), 4);
+    assert_eq!(buffer_options.values.len(), 3);
+    assert_eq!(options.local(Scope::Window).values.len(), 7);
+    assert_eq!(options.show(Opt::TabStop), "tabstop=4");
+    assert_eq!(options.show(Opt::ExpandTab), "noexpandtab");
+    assert_eq!(options.show(Opt::Timeout), "timeout");
+    options.set(Opt::ClipboardCopy, Value::Text("a\\b c".to_string()));
+    assert_eq!(options.text(Opt::ClipboardCopy), "a\\b c");
+    assert_eq!(options.show(Opt::ClipboardCopy), "clipboardcopy=a\\\\b\\ c");
+  }
+}
@@ -0,0 +1,294 @@
+/*
+ * Copyright (c) 2015 Mathias Hällman
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+use std::char;
+use std::collections::HashMap;
+
+use caret::SelectionKind;
+use keymap::{self, Key, KeySym, MOD_NONE};
+
+/*
+ * A register holds text along with the kind of selection it was taken from,
+ * which decides how it's put back. Linewise text always ends with a newline,
+ * while the lines of blockwise text are separated by newlines.
+ */
+#[derive(Clone, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct Register {
+  pub text: String,
+  pub kind: SelectionKind,
+}
+
+impl Register {
+  // the lines of the text, without their newlines
+  pub fn lines(&self) -> Vec<&str> {
+    let text = if self.kind == SelectionKind::Line && self.text.ends_with('\n')
+               { &self.text[..self.text.len() - 1] }
+               else { &self.text[..] };
+    text.split('\n').collect()
+  }
+
+  // Line |i| of a block put |count| times. Each copy is padded to the width of
+  // the block so that the copies line up, the last one only if text follows.
+  pub fn block_line(&self, i: usize, count: usize, followed: bool) -> String {
+    let lines = self.lines();
+    let width = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    let line = lines.get(i).cloned().unwrap_or("");
+    let padded = format!("{}{}", line,
+                         " ".repeat(width - line.chars().count()));
+    let mut text: String = (1..count).map(|_| &padded[..]).collect();
+    text.push_str(if followed { &padded } else { line });
+    text
+  }
+
+  // The text read as the notation of keys to be executed, the way recordings
+  // are kept, newlines being typed as enter.
+  pub fn keys(&self) -> Vec<Key> {
+    keymap::parse_notation(&self.text).into_iter().map(|key| match key {
+      Key::Unicode{codepoint: '\n', mods: MOD_NONE} =>
+        Key::Sym{sym: KeySym::Enter, mods: MOD_NONE},
+      key                                           => key,
+    }).collect()
+  }
+
+  // Appends |other|, joining the two as lines unless both are charwise. The
+  // result is linewise unless both are of the same kind.
+  fn append(&mut self, other: Register) {
+    if self.kind == SelectionKind::Char && other.kind == SelectionKind::Char {
+      self.text.push_str(&other.text);
+      return;
+    }
+    let kind = if self.kind == other.kind { self.kind }
+               else                       { SelectionKind::Line };
+    let text = {
+      let mut lines = self.lines();
+      lines.extend(other.lines());
+      lines.join("\n")
+    };
+    self.text = if kind == SelectionKind::Line { text + "\n" } else { text };
+    self.kind = kind;
+  }
+}
+
+/*
+ * Registers keeps text deleted or yanked in registers named the way Vim names
+ * them:
+ *  - " is the unnamed register, referring to the register last written to
+ *  - a through z are written to only when named, naming them in uppercase
+ *    appends to them rather than replacing what's there
+ *  - 0 keeps the last yank made without naming a register
+ *  - 1 through 9 keep the last deletes spanning lines, made without naming a
+ *    register, the most recent in 1 and the rest shifted one step each time
+ *  - - keeps the last delete within a line made without naming a register
+ *  - _ is the black hole register, which is always empty
+ *  - + and * are kept here as well as in the selections of the desktop, see
+ *    clipboard::Selection
+ * Keys recorded into a register are kept as the text of their notation, e.g.
+ * ix<Esc>, which is put like any other text and read back when executed.
+ */
+pub struct Registers {
+  registers: HashMap<char, Register>,
+  unnamed: Option<char>,
+}
+
+impl Registers {
+  pub fn new() -> Registers {
+    Registers { registers: HashMap::new(), unnamed: None }
+  }
+
+  pub fn is_valid(name: char) -> bool {
+    (name >= 'a' && name <= 'z') || (name >= 'A' && name <= 'Z') ||
+    name.is_digit(10) || name == '"' || name == '-' || name == '_' ||
+    name == '+' || name == '*'
+  }
+
+  pub fn get(&self, name: char) -> Option<&Register> {
+    match name {
+      '"'  => self.unnamed.and_then(|name| self.registers.get(&name)),
+      '_'  => None,
+      name => self.registers.get(&name.to_ascii_lowercase()),
+    }
+  }
+
+  // Keeps yanked text in register |name|, or in register 0 if none is named.
+  pub fn yank(&mut self, name: Option<char>, register: Register) {
+    match name {
+      None | Some('"') => self.store('0', register),
+      Some(name)       => self.store(name, register),
+    }
+  }
+
+  // Keeps deleted text in register |name|. If none is named, text spanning
+  // lines goes in register 1 and is shifted through register 9 by later
+  // deletes, while text within a line goes in register -.
+  pub fn delete(&mut self, name: Option<char>, register: Register) {
+    let spans_lines =
+      register.kind == SelectionKind::Line || register.text.contains('\n');
+    match name {
+      None | Some('"') if spans_lines => {
+        for number in (1..9).rev() {
+          let digit = |n| char::from_digit(n, 10).unwrap();
+          self.registers.remove(&digit(number)).map(|shifted|
+            self.registers.insert(digit(number + 1), shifted));
+        }
+        self.store('1', register);
+      }
+      None | Some('"')                => self.store('-', register),
+      Some(name)                      => self.store(name, register),
+    }
+  }
+
+  // Keeps keys recorded in register |name|, appending to what's there if
+  // named in uppercase. The unnamed register is left referring to what it did.
+  pub fn record(&mut self, name: char, keys: Vec<Key>) {
+    let unnamed = self.unnamed;
+    let text = keymap::to_notation(&keys);
+    self.store(name, Register { text: text, kind: SelectionKind::Char });
+    self.unnamed = unnamed;
+  }
+
+  // the keys register |name| holds to be executed
+  pub fn keys(&self, name: char) -> Option<Vec<Key>> {
+    self.get(name).map(|register| register.keys())
+  }
+
+  // the names of the registers holding anything, in order
+  pub fn names(&self) -> Vec<char> {
+    let mut names: Vec<char> = self.registers.keys().cloned().collect();
+    names.sort();
+    names
+  }
+
+  fn store(&mut self, name: char, register: Register) {
+    if name == '_' { return; }
+    let lowercase = name.to_ascii_lowercase();
+    let appending = name.is_uppercase();
+    match self.registers.get_mut(&lowercase) {
+      Some(existing) if appending => existing.append(register),
+      _                           => {
+        self.registers.insert(lowercase, register);
+      }
+    }
+    self.unnamed = Some(lowercase);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use caret::SelectionKind;
+  use keymap::{parse_notation, to_notation};
+
+  use super::*;
+
+  fn chars(text: &str) -> Register {
+    Register { text: text.to_string(), kind: SelectionKind::Char }
+  }
+
+  fn lines(text: &str) -> Register {
+    Register { text: text.to_string(), kind: SelectionKind::Line }
+  }
+
+  fn block(text: &str) -> Register {
+    Register { text: text.to_string(), kind: SelectionKind::Block }
+  }
+
+  #[test]
+  fn unnamed_and_named() {
+    let mut registers = Registers::new();
+    assert_eq!(registers.get('"'), None);
+    registers.yank(None, chars("foo"));
+    assert_eq!(registers.get('0'), Some(&chars("foo")));
+    assert_eq!(registers.get('"'), Some(&chars("foo")));
+    registers.yank(Some('a'), lines("bar\n"));
+    assert_eq!(registers.get('a'), Some(&lines("bar\n")));
+    assert_eq!(registers.get('A'), registers.get('a'));
+    assert_eq!(registers.get('"'), registers.get('a'));
+    assert_eq!(registers.get('0'), Some(&chars("foo")));
+    // the black hole swallows anything and leaves the unnamed register be
+    registers.delete(Some('_'), chars("baz"));
+    assert_eq!(registers.get('_'), None);
+    assert_eq!(registers.get('"'), registers.get('a'));
+    assert!(Registers::is_valid('Q') && Registers::is_valid('7'));
+    assert!(Registers::is_valid('+') && Registers::is_valid('*'));
+    assert!(!Registers::is_valid('!') && !Registers::is_valid('ä'));
+  }
+
+  #[test]
+  fn append() {
+    let mut registers = Registers::new();
+    registers.yank(Some('A'), chars("foo"));
+    registers.yank(Some('A'), chars("bar"));
+    assert_eq!(registers.get('a'), Some(&chars("foobar")));
+    registers.delete(Some('A'), lines("baz\n"));
+    assert_eq!(registers.get('"'), Some(&lines("foobar\nbaz\n")));
+    registers.yank(Some('A'), chars("qux"));
+    assert_eq!(registers.get('a'), Some(&lines("foobar\nbaz\nqux\n")));
+    registers.yank(Some('b'), block("ab\ncd"));
+    registers.yank(Some('B'), block("ef"));
+    assert_eq!(registers.get('b'), Some(&block("ab\ncd\nef")));
+    assert_eq!(registers.get('b').unwrap().lines(), vec!("ab", "cd", "ef"));
+  }
+
+  #[test]
+  fn block_lines() {
+    let ragged = block("abc\nd\n\nef");
+    assert_eq!(ragged.block_line(0, 1, false), "abc");
+    assert_eq!(ragged.block_line(1, 1, false), "d");
+    assert_eq!(ragged.block_line(1, 1, true), "d  ");
+    // copies put with a count line up, padded to the width of the block
+    assert_eq!(ragged.block_line(0, 3, false), "abcabcabc");
+    assert_eq!(ragged.block_line(1, 3, false), "d  d  d");
+    assert_eq!(ragged.block_line(2, 2, false), "   ");
+    assert_eq!(ragged.block_line(3, 2, true), "ef ef ");
+  }
+
+  #[test]
+  fn numbered_and_small_deletes() {
+    let mut registers = Registers::new();
+    for i in 0..10 {
+      registers.delete(None, lines(&format!("{}\n", i)));
+    }
+    registers.delete(None, chars("a\nb"));
+    assert_eq!(registers.get('1'), Some(&chars("a\nb")));
+    assert_eq!(registers.get('2'), Some(&lines("9\n")));
+    assert_eq!(registers.get('9'), Some(&lines("2\n")));
+    registers.delete(None, chars("x"));
+    assert_eq!(registers.get('-'), Some(&chars("x")));
+    assert_eq!(registers.get('"'), registers.get('-'));
+    assert_eq!(registers.get('2'), Some(&lines("9\n")));
+    // naming a register keeps the deleted text out of the numbered ones
+    registers.delete(Some('c'), lines("c\n"));
+    assert_eq!(registers.get('1'), Some(&chars("a\nb")));
+    assert_eq!(registers.get('"'), Some(&lines("c\n")));
+  }
+
+  #[test]
+  fn recordings() {
+    let mut registers = Registers::new();
+    registers.yank(None, chars("foo"));
+    registers.record('a', parse_notation("ix<Esc>"));
+    assert_eq!(registers.get('a'), Some(&chars("ix<Esc>")));
+    assert_eq!(registers.keys('a'), Some(parse_notation("ix<Esc>")));
+    registers.record('A', parse_notation("<C-w>"));
+    assert_eq!(registers.keys('a'), Some(parse_notation("ix<Esc><C-w>")));
+    // recording leaves the unnamed register be
+    assert_eq!(registers.get('"'), registers.get('0'));
+    // text is executed as typed, and replaces what was recorded
+    registers.yank(Some('a'), lines("dd\n"));
+    assert_eq!(registers.keys('a'), Some(parse_notation("dd<CR>")));
+    assert_eq!(registers.keys('"'), registers.keys('a'));
+    assert_eq!(registers.keys('b'), None);
+    // a recording put and yanked back is executed as recorded
+    registers.record('c', parse_notation("<C-w>v<lt>"));
+    registers.yank(Some('d'), registers.get('c').cloned().unwrap());
+    assert_eq!(registers.keys('d').map(|keys| to_notation(&keys)),
+               Some("<C-w>v<lt>".to_string()));
+    registers.yank(None, chars("x"));
+    assert_eq!(registers.names(), vec!('0', 'a', 'c', 'd'));
+  }
+}
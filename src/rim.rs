@@ -15,18 +15,37 @@ extern crate tokio_timer;
 
 mod buffer;
 mod caret;
+mod clipboard;
+mod cmdline;
 mod command;
+mod ex;
 mod frame;
+mod highlight;
 mod input;
 mod keymap;
+mod options;
+mod registers;
 mod screen;
+mod search;
+mod syntax;
+mod undo;
 mod view;
 
 #[cfg(not(test))]
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+#[cfg(not(test))]
+use std::env;
+#[cfg(not(test))]
+use std::fs;
+#[cfg(not(test))]
+use std::fs::File;
+#[cfg(not(test))]
+use std::io::{BufRead, BufReader};
 #[cfg(not(test))]
 use std::path::{Path, PathBuf};
 #[cfg(not(test))]
+use std::rc::Rc;
+#[cfg(not(test))]
 use std::time::Duration;
 
 #[cfg(not(test))]
@@ -35,16 +54,29 @@ use futures::{Future, Stream};
 #[cfg(not(test))]
 use buffer::Buffer;
 #[cfg(not(test))]
-use caret::Caret;
+use caret::{Caret, Selection, SelectionKind};
+#[cfg(not(test))]
+use cmdline::CmdLine;
 #[cfg(not(test))]
-use command::{Cmd, CmdThread, WinCmd};
+use command::{Changes, Cmd, CmdLineCmd, CmdThread, Confirmation, Operator,
+              RegisterUse, WinCmd};
 #[cfg(not(test))]
 use frame::{Frame, FrameContext};
 #[cfg(not(test))]
+use highlight::Highlights;
+#[cfg(not(test))]
 use keymap::{Key, KeySym};
 #[cfg(not(test))]
+use options::{Opt, Options, Scope};
+#[cfg(not(test))]
+use registers::{Register, Registers};
+#[cfg(not(test))]
 use screen::Screen;
 #[cfg(not(test))]
+use search::Search;
+#[cfg(not(test))]
+use syntax::Grammar;
+#[cfg(not(test))]
 use view::View;
 
 #[cfg(not(test))]
@@ -53,6 +85,36 @@ type BufferId = usize;
 #[cfg(not(test))]
 const INVALID_BUFFER_ID: BufferId = 0;
 
+/*
+ * The modes in which a window may be editing its buffer.
+ */
+#[cfg(not(test))]
+#[derive(Clone, Copy, PartialEq)]
+enum EditMode {
+  Normal,
+  Insert,
+  Replace(bool),  // whether to keep replacing after the first character
+  Visual(SelectionKind),
+  OperatorPending(Operator, usize),  // along with the count of the operator
+  ConfirmSubstitution,
+}
+
+#[cfg(not(test))]
+impl EditMode {
+  fn name(&self) -> &'static str {
+    match *self {
+      EditMode::Normal                       => "NORMAL",
+      EditMode::OperatorPending(..)          => "NORMAL",
+      EditMode::ConfirmSubstitution          => "CONFIRM",
+      EditMode::Insert                       => "INSERT",
+      EditMode::Replace(_)                   => "REPLACE",
+      EditMode::Visual(SelectionKind::Char)  => "VISUAL",
+      EditMode::Visual(SelectionKind::Line)  => "VISUAL LINE",
+      EditMode::Visual(SelectionKind::Block) => "VISUAL BLOCK",
+    }
+  }
+}
+
 #[cfg(not(test))]
 #[derive(Clone)]
 struct Window {
@@ -60,8 +122,21 @@ struct Window {
   states: HashMap<BufferId, (Caret, View)>,
   rect: screen::Rect,
   needs_redraw: bool,
+  mode: EditMode,
+  anchor: Caret,  // the other end of the selection while in visual mode
+  // a character search awaiting its character, along with its count
+  char_search: Option<(caret::CharSearch, usize)>,
+  // a register awaiting its name, along with its use and the count preceding it
+  register_prompt: Option<(RegisterUse, Option<usize>)>,
+  // the register named for the next command, along with the count preceding it
+  register: Option<(char, Option<usize>)>,
+  // an insert given a count, along with the command entering it and those
+  // given while inserting, which are repeated when returning to normal mode
+  insert_repeat: Option<(WinCmd, usize, Vec<WinCmd>)>,
   normal_mode: command::Mode,
   insert_mode: command::Mode,
+  visual_mode: command::Mode,
+  options: Options,  // the values of the options local to windows
 }
 
 #[cfg(not(test))]
@@ -72,8 +147,16 @@ impl Window {
       states: HashMap::new(),
       rect: screen::Rect(screen::Cell(0, 0), screen::Size(0, 0)),
       needs_redraw: true,
+      mode: EditMode::Normal,
+      anchor: Caret::new(),
+      char_search: None,
+      register_prompt: None,
+      register: None,
+      insert_repeat: None,
       normal_mode: default_normal_mode(),
       insert_mode: default_insert_mode(),
+      visual_mode: visual_mode(),
+      options: Options::new().local(Scope::Window),
     };
     win.set_buf_id(INVALID_BUFFER_ID);
     return win;
@@ -113,6 +196,15 @@ impl Window {
     self.view_mut_for(buf_id).expect("Window lacked state for its buffer id.")
   }
 
+  // scrolls the view of the window such that its caret is in it
+  fn scroll_into_view(&mut self, buffer: &Buffer) {
+    let caret = *self.caret();
+    let buf_id = self.buf_id;
+    let options = &self.options;
+    self.states.get_mut(&buf_id).map(|&mut (_, ref mut view)|
+      view.scroll_into_view(caret, buffer, options));
+  }
+
   fn set_buf_id(&mut self, buf_id: BufferId) {
     if !self.has_buf_id(buf_id) {
       self.states.insert(buf_id, (Caret::new(), View::new()));
@@ -124,6 +216,90 @@ impl Window {
   fn has_buf_id(&self, buf_id: BufferId) -> bool {
     self.states.contains_key(&buf_id)
   }
+
+  // a window keeps its last row for the status line unless it's a single row
+  fn has_status_line(&self) -> bool {
+    let screen::Rect(_, screen::Size(rows, _)) = self.rect;
+    rows > 1
+  }
+
+  fn view_size(&self) -> screen::Size {
+    let screen::Rect(_, screen::Size(rows, cols)) = self.rect;
+    if self.has_status_line() { screen::Size(rows - 1, cols) }
+    else                      { screen::Size(rows, cols) }
+  }
+
+  fn cmd_mode(&self) -> command::Mode {
+    if self.char_search.is_some() { return char_search_mode() }
+    if self.register_prompt.is_some() { return register_mode() }
+    match self.mode {
+      EditMode::Normal                       => self.normal_mode.clone(),
+      EditMode::Insert                       => self.insert_mode.clone(),
+      EditMode::Replace(replace_line)        => replace_mode(replace_line),
+      EditMode::Visual(_)                    => self.visual_mode.clone(),
+      EditMode::OperatorPending(operator, _) => operator_pending_mode(operator),
+      EditMode::ConfirmSubstitution          => confirm_substitution_mode(),
+    }
+  }
+
+  // the modes of the window which mappings made for |mode| are made in
+  fn mapped_modes_mut(&mut self, mode: ex::MapMode) -> Vec<&mut command::Mode> {
+    match mode {
+      ex::MapMode::NormalVisual =>
+        vec!(&mut self.normal_mode, &mut self.visual_mode),
+      ex::MapMode::Normal       => vec!(&mut self.normal_mode),
+      ex::MapMode::Insert       => vec!(&mut self.insert_mode),
+    }
+  }
+
+  // whether the window is in normal mode without anything pending, such as a
+  // character search or a named register, making it ready for a new command
+  fn is_idle(&self) -> bool {
+    self.mode == EditMode::Normal && self.char_search.is_none() &&
+    self.register_prompt.is_none() && self.register.is_none()
+  }
+
+  fn selection(&self) -> Option<Selection> {
+    match self.mode {
+      EditMode::Visual(kind) => Some(Selection {
+        kind: kind, anchor: self.anchor, caret: *self.caret()
+      }),
+      _                      => None,
+    }
+  }
+}
+
+/*
+ * A substitution being carried out over a range of lines. The matches on a
+ * line are found before anything on it is replaced, and visited in turn. Their
+ * columns are those of the line as it was, an anchor telling where a column of
+ * it ends up as the text before it is replaced. The last line of the range
+ * follows along as replacements insert new lines.
+ */
+#[cfg(not(test))]
+struct SubstitutionProgress {
+  search: Search,
+  replacement: String,
+  global: bool,
+  line: usize,
+  matches: Option<Vec<(usize, usize, String)>>,  // those left on the line
+  anchor: (usize, usize, usize),  // original column, now at line and column
+  last_line: usize,
+  substitutions: usize,
+  lines: usize,  // the number of lines substituted on
+  last_changed: Option<usize>,  // the line of the last substitution
+}
+
+#[cfg(not(test))]
+impl SubstitutionProgress {
+  // Moves on from the next match, which if replaced is by text ending at
+  // |replaced_end|, a line and column.
+  fn advance(&mut self, replaced_end: Option<(usize, usize)>) {
+    let matched = self.matches.as_mut().map(|matches| matches.remove(0));
+    if let (Some((_, end, _)), Some((line, column))) = (matched, replaced_end) {
+      self.anchor = (end, line, column);
+    }
+  }
 }
 
 #[cfg(not(test))]
@@ -135,6 +311,23 @@ struct Rim {
   focus: frame::WindowId,
   buffers: HashMap<BufferId, Buffer>,
   next_buf_id: BufferId,
+  cmdline: CmdLine,
+  cmdline_rect: screen::Rect,
+  cmdline_needs_redraw: bool,
+  registers: Registers,
+  options: Options,  // the global values of the options
+  highlights: Highlights,
+  loading_colorschemes: Vec<String>,  // those being loaded, innermost last
+  grammars: Vec<Rc<Grammar>>,
+  clipboard: Box<clipboard::Provider>,
+  recording: Option<char>,  // the register keys are being recorded into
+  last_executed: Option<char>,  // the register last executed, for @@
+  last_char_search: Option<(caret::CharSearch, char)>,
+  last_search: Option<(Search, bool)>,  // along with whether it went backward
+  incremental_search: Option<Search>,  // the search being typed, if valid
+  search_origin: Option<Caret>,  // where the caret was when typing a search
+  substitution: Option<SubstitutionProgress>,
+  changes: Changes,  // those of the focused window
   cmd_thread: CmdThread,
   quit: bool,
 }
@@ -156,20 +349,56 @@ impl Rim {
       focus: first_win_id,
       buffers: HashMap::new(),
       next_buf_id: INVALID_BUFFER_ID + 1,
+      cmdline: CmdLine::new(),
+      cmdline_rect: screen::Rect(screen::Cell(0, 0), screen::Size(0, 0)),
+      cmdline_needs_redraw: true,
+      registers: Registers::new(),
+      options: Options::new(),
+      highlights: Highlights::new(),
+      loading_colorschemes: Vec::new(),
+      grammars: Vec::new(),
+      clipboard: clipboard::detect(),
+      recording: None,
+      last_executed: None,
+      last_char_search: None,
+      last_search: None,
+      incremental_search: None,
+      search_origin: None,
+      substitution: None,
+      changes: Changes::new(),
       cmd_thread: cmd_thread,
       quit: false,
     }
   }
 
-  fn load_buffer(&mut self, path: &Path) -> Option<BufferId> {
-    for (buf_id, buf) in self.buffers.iter() {
-      if let Ok(buf_path) = buf.path() {
-        if path == buf_path { return Some(*buf_id) }
-      }
+  // The buffer of the file at |path|, which is loaded unless it already is. A
+  // loaded buffer with changes is loaded again if they are to be discarded.
+  fn load_buffer(&mut self, path: &Path, discard: bool) -> Option<BufferId> {
+    let loaded = self.buffers.iter().
+      find(|&(_, buf)| buf.path().ok() == Some(path)).
+      map(|(buf_id, buf)| (*buf_id, buf.is_modified()));
+    match loaded {
+      Some((buf_id, modified)) if !(modified && discard) => return Some(buf_id),
+      _                                                  => (),
     }
-    Buffer::open(path).map(|buf| {
-      let id = self.next_buf_id;
-      self.next_buf_id += 1;
+    Buffer::open(path).map(|mut buf| {
+      *buf.options_mut() = self.options.local(Scope::Buffer);
+      buf.set_grammar(self.grammars.iter().find(|g| g.is_for(path)).cloned());
+      let id = match loaded {
+        Some((id, _)) => {
+          // carets of other windows may be past the end of the file
+          for (_, win) in self.windows.iter_mut() {
+            win.caret_mut_for(id).map(|caret|
+              caret.adjust(caret::Adjustment::Clamp, &buf));
+            if win.buf_id == id { win.needs_redraw = true; }
+          }
+          id
+        }
+        None          => {
+          self.next_buf_id += 1;
+          self.next_buf_id - 1
+        }
+      };
       self.buffers.insert(id, buf);
       return id; }).ok()
   }
@@ -186,19 +415,23 @@ impl Rim {
 
   fn set_focus(&mut self, win_id: frame::WindowId) {
     assert!(self.windows.contains_key(&win_id));
+    self.leave_pending_modes();
     self.windows.get(&win_id).map(|win|
-      self.cmd_thread.set_mode(win.normal_mode.clone(), 1));
+      self.cmd_thread.set_mode(win.cmd_mode(), 1));
     self.windows.get_mut(&self.focus).map(|win| win.needs_redraw = true);
     self.windows.remove(&win_id).map(|mut win| {
       win.needs_redraw = true;
       self.buffers.get(&win.buf_id).map(|buffer| {
-        let caret = *win.caret();
-        win.view_mut().scroll_into_view(caret, buffer) });
+        win.scroll_into_view(buffer) });
       self.windows.insert(win_id.clone(), win); });
     self.focus = win_id;
   }
 
-  fn split_window(&mut self, orientation: frame::Orientation) {
+  // Splits the focused window, which keeps the first half unless |size| asks
+  // for a number of rows or columns.
+  fn split_window(&mut self, orientation: frame::Orientation,
+                  size: Option<usize>) {
+    self.leave_pending_modes();
     self.frame.split_window(&mut self.frame_ctx, &self.focus, orientation).
     map(|new_win_id| {
       let win = self.windows.get(&self.focus).map(|win| win.clone()).
@@ -206,6 +439,17 @@ impl Rim {
       self.windows.insert(new_win_id, win);
       self.invalidate_frame(); }).
     ok().expect("Failed to split window.");
+    let resize_orientation = orientation.opposite();
+    let rect = self.frame.get_window_rect(&self.frame_ctx, &self.focus).ok();
+    match (size, rect) {
+      (Some(size), Some(screen::Rect(_, screen::Size(rows, cols)))) => {
+        let current = if resize_orientation == frame::Orientation::Vertical
+                      { rows } else { cols };
+        self.resize_window(resize_orientation,
+                           size as isize - current as isize);
+      }
+      _                                                            => (),
+    }
   }
 
   fn resize_window(&mut self, orientation: frame::Orientation, amount: isize) {
@@ -229,11 +473,33 @@ impl Rim {
     map(|win| {
       let screen::Rect(position, _) = win.rect;
       let focused = self.focus == *win_id;
-      self.buffers.get(&win.buf_id).map(|buffer|
-        win.view().draw(buffer, *win.caret(), focused, position, screen)) }).
+      self.buffers.get(&win.buf_id).map(|buffer| {
+        // matches are highlighted while typing a search or confirming a
+        // substitution
+        let search = if !focused { None }
+                     else { self.incremental_search.as_ref().or(
+                       self.substitution.as_ref().map(|s| &s.search)) };
+        win.view().draw(buffer, *win.caret(), win.selection(), search,
+                        &win.options, &self.highlights, focused, position,
+                        screen);
+        if win.has_status_line() {
+          win.view().draw_status(buffer, *win.caret(), win.mode.name(),
+                                 &self.highlights, focused, position, screen);
+        } }) }).
     expect("Couldn't find window.");
   }
 
+  fn set_size(&mut self, size: screen::Size) {
+    // the last row is reserved for the command line
+    let screen::Size(rows, cols) = size;
+    let frame_rows = if rows > 0 { rows - 1 } else { 0 };
+    self.frame.set_size(screen::Size(frame_rows, cols));
+    self.cmdline_rect = screen::Rect(screen::Cell(frame_rows, 0),
+                                     screen::Size(rows - frame_rows, cols));
+    self.cmdline_needs_redraw = true;
+    self.invalidate_frame();
+  }
+
   fn invalidate_frame(&mut self) {
     let window_rects: Vec<(frame::WindowId, screen::Rect)> =
       self.windows.iter().
@@ -250,13 +516,13 @@ impl Rim {
       map(|mut win| {
         let screen::Rect(_, old_size) = win.rect;
         let screen::Rect(_, new_size) = new_rect;
+        win.rect = new_rect;
         if old_size != new_size {
-          win.view_mut().set_size(new_size);
+          let view_size = win.view_size();
+          win.view_mut().set_size(view_size);
           self.buffers.get(&win.buf_id).map(|buffer| {
-            let caret = *win.caret();
-            win.view_mut().scroll_into_view(caret, buffer) });
+            win.scroll_into_view(buffer) });
         }
-        win.rect = new_rect;
         win.needs_redraw = true;
         self.windows.insert(win_id.clone(), win); }).
       expect("Couldn't find window.");
@@ -265,55 +531,740 @@ impl Rim {
   }
 
   fn handle_cmd(&mut self, cmd: Cmd) {
+    self.exec_cmd(cmd);
+    self.cmd_thread.ack_cmd();
+  }
+
+  fn exec_cmd(&mut self, cmd: Cmd) {
+    let (cmd, count) = match cmd {
+      Cmd::Counted(count, cmd) => (*cmd, Some(count)),
+      cmd                      => (cmd, None),
+    };
     match cmd {
-      Cmd::MoveFocus(direction)      => self.move_focus(direction),
-      Cmd::ShiftFocus(window_order)  => self.shift_focus(window_order),
+      Cmd::MoveFocus(direction)      =>
+        for _ in 0..count.unwrap_or(1) { self.move_focus(direction); },
+      Cmd::ShiftFocus(window_order)  =>
+        for _ in 0..count.unwrap_or(1) { self.shift_focus(window_order); },
       Cmd::ResetLayout               => {
         self.frame.reset_layout();
         self.invalidate_frame();
       }
-      Cmd::SplitWindow(orientation)  => self.split_window(orientation),
-      Cmd::GrowWindow(orientation)   => self.resize_window(orientation, 10),
-      Cmd::ShrinkWindow(orientation) => self.resize_window(orientation, -10),
+      Cmd::SplitWindow(orientation)  => self.split_window(orientation, count),
+      Cmd::GrowWindow(orientation)   =>
+        self.resize_window(orientation, count.unwrap_or(10) as isize),
+      Cmd::ShrinkWindow(orientation) =>
+        self.resize_window(orientation, -(count.unwrap_or(10) as isize)),
       Cmd::CloseWindow               => self.close_window(),
-      Cmd::QuitWindow                =>
-        if self.windows.len() == 1 { self.quit = true; }
-        else                       { self.close_window(); },
-      Cmd::Quit                      => { self.quit = true; }
+      Cmd::QuitWindow(force)         => self.quit_window(force),
+      Cmd::WriteQuitWindow(to, force) => {
+        let buf_id = self.windows.get(&self.focus).map(|win| win.buf_id).
+          expect("Couldn't find focused window.");
+        if self.save_buffer(buf_id, to, force) { self.quit_window(false); }
+      }
+      Cmd::Quit(force)               => self.quit(force),
+      Cmd::WriteAll                  => { self.save_all_buffers(); }
+      Cmd::WriteQuitAll              =>
+        if self.save_all_buffers() { self.quit(false); },
+      Cmd::EnterCmdLineMode          => {
+        // a selection is handed over to the command line as a range of lines,
+        // as is a count of lines starting with the current one
+        let lines = self.windows.get(&self.focus).
+          and_then(|win| win.selection()).map(|selection| selection.lines());
+        let range = match (lines, count) {
+          (Some((first, last)), _) =>
+            Some(format!("{},{}", first + 1, last + 1)),
+          (None, Some(1))          => Some(".".to_string()),
+          (None, Some(count))      => Some(format!(".,.+{}", count - 1)),
+          (None, None)             => None,
+        };
+        self.leave_pending_modes();
+        self.cmdline.start_prompt(':');
+        range.map(|range| self.cmdline.insert(&range));
+        self.cmd_thread.set_mode(cmdline_mode(), 1);
+        self.cmdline_needs_redraw = true;
+      }
+      Cmd::EnterSearchMode(backward) => {
+        self.leave_pending_modes();
+        self.search_origin =
+          self.windows.get(&self.focus).map(|win| *win.caret());
+        self.cmdline.start_prompt(if backward { '?' } else { '/' });
+        self.cmd_thread.set_mode(cmdline_mode(), 1);
+        self.cmdline_needs_redraw = true;
+      }
+      cmd @ Cmd::Map(..) | cmd @ Cmd::Unmap(..) | cmd @ Cmd::Set(..) |
+      cmd @ Cmd::Highlight(..)       =>
+        if let Err(error) = self.configure(cmd) { self.show_message(error); },
+      Cmd::Colorscheme(name)         =>
+        if let Err(error) = self.colorscheme(&name) {
+          self.show_message(error);
+        },
+      Cmd::ListMappings(mode, keys)  => self.list_mappings(mode, &keys),
+      Cmd::ShowRegisters(names)      => self.show_registers(&names),
+      // mappings are carried out by the command thread
+      Cmd::Keys(..)                  => (),
+      Cmd::RecursiveMapping          =>
+        self.show_message("Recursive mapping".to_string()),
+      Cmd::CmdLine(cmd)              => self.handle_cmdline_cmd(cmd),
       Cmd::WinCmd(cmd)               => {
         self.windows.remove(&self.focus).
         map(|mut win| {
-          self.handle_win_cmd(cmd, &mut win);
+          self.changes.push(&cmd, count);
+          self.handle_win_cmd(cmd, count, &mut win);
+          // changes made by a command in normal mode make an undo step of their
+          // own, while those made in insert or replace mode are kept pending
+          // until returning to normal mode
+          let mut changed = false;
+          if win.mode == EditMode::Normal {
+            self.buffers.get_mut(&win.buf_id).map(|buffer| {
+              changed = buffer.has_pending_changes();
+              buffer.commit_changes(); });
+          }
+          // the commands given since the window was last idle are kept for
+          // repeating if they changed the buffer
+          if win.is_idle() { self.changes.finish(changed); }
           self.windows.insert(self.focus.clone(), win); }).
         expect("Couldn't find focused window.");
       }
+      // counts given twice are multiplied, like those of 2d3w
+      Cmd::Counted(inner, cmd)       =>
+        self.exec_cmd(Cmd::Counted(count.unwrap_or(1).saturating_mul(inner),
+                                   cmd)),
+    }
+  }
+
+  fn handle_cmdline_cmd(&mut self, cmd: CmdLineCmd) {
+    match cmd {
+      CmdLineCmd::Insert(string)  => self.cmdline.insert(&string),
+      CmdLineCmd::Backspace       =>
+        if !self.cmdline.backspace() { self.leave_cmdline_mode(); },
+      CmdLineCmd::Delete          => self.cmdline.delete(),
+      CmdLineCmd::CaretLeft       => self.cmdline.caret_left(),
+      CmdLineCmd::CaretRight      => self.cmdline.caret_right(),
+      CmdLineCmd::CaretStart      => self.cmdline.caret_start(),
+      CmdLineCmd::CaretEnd        => self.cmdline.caret_end(),
+      CmdLineCmd::HistoryPrevious => self.cmdline.history_previous(),
+      CmdLineCmd::HistoryNext     => self.cmdline.history_next(),
+      CmdLineCmd::Cancel          => self.leave_cmdline_mode(),
+      CmdLineCmd::Execute         => {
+        let prompt = self.cmdline.prompt();
+        let line = self.cmdline.finish_prompt();
+        self.leave_cmdline_mode();
+        if prompt == Some(':') {
+          if let Err(error) = self.exec_cmdline(&line) {
+            self.show_message(error);
+          }
+        }
+        else { self.search(line, prompt == Some('?')); }
+      }
+    }
+    self.update_incremental_search();
+    self.cmdline_needs_redraw = true;
+  }
+
+  // Executes a command line, returning rather than showing any error such that
+  // the config file may tell on which line it occurred.
+  fn exec_cmdline(&mut self, line: &str) -> Result<(), String> {
+    match try!(ex::parse(line).map_err(|error| format!("{}", error))) {
+      Some(cmd @ Cmd::Map(..)) | Some(cmd @ Cmd::Unmap(..)) |
+      Some(cmd @ Cmd::Set(..)) | Some(cmd @ Cmd::Highlight(..)) =>
+        self.configure(cmd),
+      Some(Cmd::Colorscheme(name))                              =>
+        self.colorscheme(&name),
+      Some(cmd)                                                 => {
+        self.exec_cmd(cmd);
+        Ok(())
+      }
+      None                                                      => Ok(()),
+    }
+  }
+
+  // Executes the lines of the config file at |path|, returning the first line
+  // failing along with how many more did.
+  fn source(&mut self, path: &Path) -> Result<(), String> {
+    let lines = try!(File::open(path).
+      map(|file| BufReader::new(file).lines()).
+      map_err(|error| format!("{}: {}", path.display(), error)));
+    let mut errors = Vec::new();
+    for (number, line) in lines.enumerate() {
+      let result = line.map_err(|error| format!("{}", error)).
+        and_then(|line|
+          // lines starting with a double quote are comments
+          if line.trim_left().starts_with('"') { Ok(()) }
+          else                                 { self.exec_cmdline(&line) });
+      if let Err(error) = result {
+        errors.push(format!("{} line {}: {}", path.display(), number + 1,
+                            error));
+      }
+    }
+    first_error(errors).map_or(Ok(()), Err)
+  }
+
+  // Loads the grammars in |dir|, being the files ending in .json, reporting the
+  // first failing along with how many more did.
+  fn load_grammars(&mut self, dir: &Path) {
+    let paths = match fs::read_dir(dir) {
+      Ok(entries) => entries.filter_map(|entry| entry.ok()).
+                     map(|entry| entry.path()).
+                     filter(|path| path.extension().map(|e| e == "json").
+                                   unwrap_or(false)),
+      Err(_)      => return,  // there are no grammars to load
+    };
+    let mut errors = Vec::new();
+    for path in paths {
+      match Grammar::load(&path) {
+        Ok(grammar) => self.grammars.push(Rc::new(grammar)),
+        Err(error)  => errors.push(format!("{}: {}", path.display(), error)),
+      }
+    }
+    first_error(errors).map(|message| self.show_message(message));
+  }
+
+  // Carries out the commands changing the key mappings, options or
+  // highlights.
+  fn configure(&mut self, cmd: Cmd) -> Result<(), String> {
+    match cmd {
+      Cmd::Map(mode, keys, to, remap) => {
+        for win in self.windows.values_mut() {
+          for win_mode in win.mapped_modes_mut(mode) {
+            win_mode.mappings.bind(&keys, Cmd::Keys(to.clone(), remap));
+          }
+        }
+      }
+      // a default binding is removed when there's no mapping to remove
+      Cmd::Unmap(mode, keys)          => {
+        let mut unmapped = false;
+        for win in self.windows.values_mut() {
+          for win_mode in win.mapped_modes_mut(mode) {
+            unmapped = win_mode.mappings.unbind(&keys) ||
+                       win_mode.keychain.unbind(&keys) || unmapped;
+          }
+        }
+        if !unmapped { return Err("No such mapping".to_string()); }
+      }
+      Cmd::Set(settings, local)       =>
+        for setting in settings { try!(self.set_option(&setting, local)); },
+      Cmd::Highlight(name, settings)  =>
+        try!(self.highlight(&name, &settings)),
+      _                               =>
+        panic!("Not a configuring command."),
+    }
+    self.windows.get(&self.focus).map(|win|
+      self.cmd_thread.set_mode(win.cmd_mode(), 1));
+    Ok(())
+  }
+
+  // Changes the highlight of a group, shows it, or resets every group.
+  fn highlight(&mut self, name: &str, settings: &[ex::Setting])
+      -> Result<(), String> {
+    match try!(highlight::resolve(name, settings).
+               map_err(|error| format!("{}", error))) {
+      highlight::Change::Clear            =>
+        self.highlights = Highlights::new(),
+      highlight::Change::Set(group, keys) =>
+        self.highlights.set(group, &keys),
+      highlight::Change::Show(group)      => {
+        let message = self.highlights.show(group);
+        self.show_message(message);
+        return Ok(());
+      }
+    }
+    self.invalidate_highlights();
+    Ok(())
+  }
+
+  // Loads the color scheme |name| from the colors directory of the config
+  // directory, starting over from the default highlights. A color scheme may
+  // load another, but not one already being loaded. Lines of the color scheme
+  // failing are reported like those of the config file.
+  fn colorscheme(&mut self, name: &str) -> Result<(), String> {
+    if self.loading_colorschemes.iter().any(|loading| loading == name) {
+      return Err(format!("Color scheme '{}' is already being loaded", name));
+    }
+    let path = try!(config_dir().
+      and_then(|dir| highlight::colorscheme_path(&dir, name)).
+      ok_or(format!("Cannot find color scheme '{}'", name)));
+    self.highlights = Highlights::new();
+    self.loading_colorschemes.push(name.to_string());
+    let result = self.source(&path);
+    self.loading_colorschemes.pop();
+    self.invalidate_highlights();
+    result
+  }
+
+  // Has everything drawn again in the current highlights.
+  fn invalidate_highlights(&mut self) {
+    for win in self.windows.values_mut() { win.needs_redraw = true; }
+    self.cmdline_needs_redraw = true;
+    self.invalidate_frame();
+  }
+
+  // Shows the mappings made for |mode| of keys starting with |keys|.
+  fn list_mappings(&mut self, mode: ex::MapMode, keys: &[Key]) {
+    let mut mappings = BTreeSet::new();
+    self.windows.get_mut(&self.focus).map(|win|
+      for win_mode in win.mapped_modes_mut(mode) {
+        for (mapped, cmd) in win_mode.mappings.bindings() {
+          if let Cmd::Keys(ref to, remap) = *cmd {
+            if mapped.starts_with(keys) {
+              mappings.insert(format!("{} {}{}", keymap::to_notation(&mapped),
+                                      if remap { "" } else { "* " },
+                                      keymap::to_notation(to)));
+            }
+          }
+        }
+      });
+    let message = if mappings.is_empty() { "No mapping found".to_string() }
+      else { mappings.into_iter().collect::<Vec<String>>().join("  ") };
+    self.show_message(message);
+  }
+
+  // Shows what the registers named hold, or what all of them do if none are
+  // named. Text is shown as the keys it would be executed as.
+  fn show_registers(&mut self, names: &str) {
+    let names: Vec<char> =
+      if names.is_empty() {
+        Some('"').into_iter().chain(self.registers.names()).collect()
+      }
+      else { names.chars().collect() };
+    let shown: Vec<String> = names.iter().filter_map(|&name|
+      self.registers.keys(name).map(|keys|
+        format!("\"{} {}", name, keymap::to_notation(&keys)))).collect();
+    let message = if shown.is_empty() { "Nothing in registers".to_string() }
+                  else                { shown.join("  ") };
+    self.show_message(message);
+  }
+
+  // Sets an option, or shows its value. Options local to buffers or windows
+  // are set for the focused one, and unless |local| also globally such that
+  // buffers and windows created later get the same value.
+  fn set_option(&mut self, setting: &ex::Setting, local: bool)
+      -> Result<(), String> {
+    let (opt, value) =
+      match try!(options::resolve(setting).map_err(|err| format!("{}", err))) {
+        options::Change::Set(opt, value) => (opt, value),
+        options::Change::Show(opt)       => {
+          let message = self.local_options(opt.scope()).show(opt);
+          self.show_message(message);
+          return Ok(());
+        }
+      };
+    if !local || opt.scope() == Scope::Global {
+      self.options.set(opt, value.clone());
+    }
+    self.local_options_mut(opt.scope()).set(opt, value);
+    match opt {
+      Opt::Timeout | Opt::TimeoutLen           =>
+        self.cmd_thread.set_timeout(
+          if self.options.bool(Opt::Timeout) {
+            Some(self.options.number(Opt::TimeoutLen) as u64)
+          } else { None }),
+      Opt::ClipboardCopy | Opt::ClipboardPaste =>
+        self.clipboard = clipboard::configured(
+          &self.options.text(Opt::ClipboardCopy),
+          &self.options.text(Opt::ClipboardPaste)),
+      _                                        => {}
+    }
+    // the options may change how windows are drawn or scrolled
+    for win in self.windows.values_mut() { win.needs_redraw = true; }
+    let focus = self.focus.clone();
+    self.windows.remove(&focus).map(|mut win| {
+      self.buffers.get(&win.buf_id).map(|buffer| win.scroll_into_view(buffer));
+      self.windows.insert(focus, win); });
+    Ok(())
+  }
+
+  // the values of the options of |scope| in effect for the focused window
+  fn local_options(&self, scope: Scope) -> &Options {
+    let win = self.windows.get(&self.focus).expect("Couldn't find window.");
+    match scope {
+      Scope::Global => &self.options,
+      Scope::Buffer => self.buffers.get(&win.buf_id).
+        map(|buffer| buffer.options()).unwrap_or(&self.options),
+      Scope::Window => &win.options,
     }
-    self.cmd_thread.ack_cmd();
   }
 
-  fn handle_win_cmd(&mut self, cmd: WinCmd, win: &mut Window) {
+  fn local_options_mut(&mut self, scope: Scope) -> &mut Options {
+    let win = self.windows.get_mut(&self.focus).
+      expect("Couldn't find window.");
+    match scope {
+      Scope::Global => &mut self.options,
+      Scope::Buffer => match self.buffers.get_mut(&win.buf_id) {
+        Some(buffer) => buffer.options_mut(),
+        None         => &mut self.options,
+      },
+      Scope::Window => &mut win.options,
+    }
+  }
+
+  // Moves the caret of the focused window to the first match of the search
+  // being typed, or back to where it was if there is no match.
+  fn update_incremental_search(&mut self) {
+    let origin = match self.search_origin { Some(origin) => origin,
+                                            None         => return };
+    let backward = self.cmdline.prompt() == Some('?');
+    let pattern = self.cmdline.text();
+    let ignore_case = self.options.bool(Opt::IgnoreCase);
+    self.incremental_search =
+      if pattern.is_empty() { None }
+      else                  { Search::new(&pattern, ignore_case).ok() };
+    let focus = self.focus.clone();
+    self.windows.remove(&focus).map(|mut win| {
+      let found = self.incremental_search.as_ref().and_then(|search|
+        self.buffers.get(&win.buf_id).and_then(|buffer|
+          search.find(origin.line(), origin.column(), backward, buffer)));
+      *win.caret_mut() = origin;
+      match found {
+        Some(((line, column), _)) =>
+          self.move_caret(caret::Adjustment::Set(line, column), &mut win),
+        None                      =>
+          self.move_caret(caret::Adjustment::Clamp, &mut win),
+      }
+      self.windows.insert(focus, win); });
+  }
+
+  // Searches for |pattern| from the caret of the focused window, an empty
+  // pattern repeating the last search.
+  fn search(&mut self, pattern: String, backward: bool) {
+    let search = if pattern.is_empty() {
+      self.last_search.take().map(|(search, _)| search).
+      ok_or("No previous regular expression".to_string())
+    } else {
+      Search::new(&pattern, self.options.bool(Opt::IgnoreCase)).
+      map_err(|err| format!("{}", err))
+    };
+    match search {
+      Ok(search)   => {
+        self.last_search = Some((search, backward));
+        self.exec_cmd(Cmd::WinCmd(WinCmd::SearchNext(false)));
+      }
+      Err(message) => self.show_message(message),
+    }
+  }
+
+  // Finds the |count|th match of the last search from the caret of |win|,
+  // telling about failing or wrapping around the buffer on the way.
+  fn find_next_match(&mut self, reverse: bool, count: usize, win: &Window)
+      -> Option<(usize, usize)> {
+    let (found, message) =
+      match (self.last_search.as_ref(), self.buffers.get(&win.buf_id)) {
+        (Some(&(ref search, backward)), Some(buffer)) => {
+          let backward = backward != reverse;
+          let caret = win.caret();
+          let mut found = Some(((caret.line(), caret.column()), false));
+          for _ in 0..count {
+            found = found.and_then(|((line, column), wrapped)|
+              search.find(line, column, backward, buffer).
+              map(|(position, wraps)| (position, wrapped || wraps)));
+          }
+          let message = match found {
+            None                        =>
+              Some(format!("Pattern not found: {}", search.pattern())),
+            Some((_, true)) if backward =>
+              Some("search hit TOP, continuing at BOTTOM".to_string()),
+            Some((_, true))             =>
+              Some("search hit BOTTOM, continuing at TOP".to_string()),
+            Some((_, false))            => None,
+          };
+          (found.map(|(position, _)| position), message)
+        }
+        _                                             =>
+          (None, Some("No previous regular expression".to_string())),
+      };
+    message.map(|message| self.show_message(message));
+    found
+  }
+
+  // Returns the focused window to normal mode if it's selecting, has an
+  // operator pending or is confirming a substitution.
+  fn leave_pending_modes(&mut self) {
+    let focus = self.focus.clone();
+    self.windows.remove(&focus).map(|mut win| {
+      match win.mode {
+        EditMode::Visual(_) | EditMode::OperatorPending(..) =>
+          self.set_edit_mode(EditMode::Normal, &mut win),
+        EditMode::ConfirmSubstitution                       => {
+          self.finish_substitution(&mut win);
+          self.buffers.get_mut(&win.buf_id).map(|buffer|
+            buffer.commit_changes());
+        }
+        mode if win.char_search.is_some() ||
+                win.register_prompt.is_some()               =>
+          self.set_edit_mode(mode, &mut win),
+        _                                                   => (),
+      }
+      self.windows.insert(focus, win); });
+  }
+
+  fn leave_cmdline_mode(&mut self) {
+    self.cmdline.cancel_prompt();
+    // a search being typed leaves the caret where it was
+    self.incremental_search = None;
+    self.search_origin.take().map(|origin| {
+      let focus = self.focus.clone();
+      self.windows.remove(&focus).map(|mut win| {
+        *win.caret_mut() = origin;
+        self.move_caret(caret::Adjustment::Clamp, &mut win);
+        self.windows.insert(focus, win); }); });
+    self.windows.get(&self.focus).map(|win|
+      self.cmd_thread.set_mode(win.cmd_mode(), 1));
+    self.cmdline_needs_redraw = true;
+  }
+
+  fn show_message(&mut self, message: String) {
+    self.cmdline.set_message(message);
+    self.cmdline_needs_redraw = true;
+  }
+
+  // Quits unless there are buffers with unsaved changes, in which case the
+  // first one found is reported instead. Forcing quits regardless.
+  fn quit(&mut self, force: bool) {
+    let modified_buffer = self.buffers.values().
+      find(|buffer| buffer.is_modified()).
+      map(|buffer| buffer.path().map(|path| format!("{}", path.display())).
+                   unwrap_or("[No Name]".to_string()));
+    match modified_buffer {
+      Some(ref name) if !force => self.show_message(format!(
+        "No write since last change for buffer \"{}\" (add ! to override)",
+        name)),
+      _                        => self.quit = true,
+    }
+  }
+
+  // Closes the focused window, or quits if it's the last one. Buffers are kept
+  // around when their windows close, so only quitting may lose changes.
+  fn quit_window(&mut self, force: bool) {
+    if self.windows.len() == 1 { self.quit(force); }
+    else                       { self.close_window(); }
+  }
+
+  // Writes a buffer to its own path or |path| if given, reporting the outcome
+  // as a message. Returns whether the buffer was written.
+  // Writes a buffer to its file, or to the file at |path|. A file other than
+  // that of the buffer is only written over if |overwrite| is set. Returns
+  // whether the buffer was written.
+  fn save_buffer(&mut self, buf_id: BufferId, path: Option<PathBuf>,
+                 overwrite: bool) -> bool {
+    let exists = self.buffers.get(&buf_id).and_then(|buffer|
+      path.as_ref().map(|path|
+        path.exists() && buffer.path().ok() != Some(path.as_path()))).
+      unwrap_or(false);
+    if exists && !overwrite {
+      self.show_message("File exists (add ! to override)".to_string());
+      return false;
+    }
+    let result = self.buffers.get_mut(&buf_id).map(|buffer|
+      match path {
+        Some(ref path) => buffer.write_to(path).map(|_| path.clone()),
+        None           => buffer.write().and_then(|_|
+          buffer.path().map(|path| path.to_path_buf())),
+      }).
+      expect("Couldn't find buffer.");
+    // let the status lines catch up with the buffer no longer being modified
+    for (_, win) in self.windows.iter_mut() {
+      if win.buf_id == buf_id { win.needs_redraw = true; }
+    }
+    let message = match result {
+      Ok(ref path) => format!("\"{}\" written", path.display()),
+      Err(ref err) => format!("Failed writing buffer: {}", err),
+    };
+    self.show_message(message);
+    result.is_ok()
+  }
+
+  // Writes all modified buffers. Returns whether they were all written.
+  fn save_all_buffers(&mut self) -> bool {
+    let modified_buffers: Vec<BufferId> = self.buffers.iter().
+      filter(|&(_, buffer)| buffer.is_modified()).
+      map(|(buf_id, _)| *buf_id).
+      collect();
+    modified_buffers.into_iter().
+      fold(true, |all_saved, buf_id|
+        self.save_buffer(buf_id, None, false) && all_saved)
+  }
+
+  fn handle_win_cmd(&mut self, cmd: WinCmd, count: Option<usize>,
+                    win: &mut Window) {
+    // character searches turn into motions once their character is known
+    let (cmd, count) = match cmd {
+      // q stops a recording rather than starting one
+      WinCmd::PendRegister(RegisterUse::Record)
+          if self.recording.is_some() => {
+        self.stop_recording();
+        return;
+      }
+      WinCmd::PendRegister(register_use) => {
+        win.register_prompt = Some((register_use, count));
+        self.cmd_thread.set_mode(win.cmd_mode(), 1);
+        return;
+      }
+      WinCmd::SelectRegister(name)      => {
+        let prompt = win.register_prompt.take();
+        self.cmd_thread.set_mode(win.cmd_mode(), 1);
+        match (prompt, name) {
+          (Some((RegisterUse::Name, count)), Some(name))
+              if Registers::is_valid(name) =>
+            win.register = Some((name, count)),
+          (Some((RegisterUse::Record, _)), Some(name))
+              if Registers::is_valid(name) && name.is_alphanumeric() =>
+            self.start_recording(name),
+          (Some((RegisterUse::Execute, count)), Some(name))  =>
+            self.execute_register(name, count.unwrap_or(1)),
+          _                                                  => (),
+        }
+        return;
+      }
+      WinCmd::PendCharSearch(search)    => {
+        win.char_search = Some((search, count.unwrap_or(1)));
+        self.cmd_thread.set_mode(win.cmd_mode(), 1);
+        return;
+      }
+      WinCmd::SearchChar(c)             => {
+        let search = win.char_search.take();
+        self.cmd_thread.set_mode(win.cmd_mode(), 1);
+        match (search, c) {
+          (Some((search, count)), Some(c)) => {
+            self.last_char_search = Some((search, c));
+            let adjustment = caret::Adjustment::SearchChar(search, c, false);
+            (WinCmd::MoveCaret(adjustment), Some(count))
+          }
+          _                                => (WinCmd::SearchChar(c), count),
+        }
+      }
+      WinCmd::RepeatCharSearch(reverse) => match self.last_char_search {
+        Some((search, c)) => {
+          let search = if reverse { search.reversed() } else { search };
+          let adjustment = caret::Adjustment::SearchChar(search, c, true);
+          (WinCmd::MoveCaret(adjustment), count)
+        }
+        None              => (WinCmd::RepeatCharSearch(reverse), count),
+      },
+      // gj and gk move by display line when lines wrap, or else by line
+      WinCmd::MoveCaretByDisplayLine(down) => {
+        use caret::Adjustment::*;
+        let wrapping = self.buffers.get(&win.buf_id).and_then(|buffer|
+          win.view().wrapping(buffer, &win.options));
+        let adjustment = match (wrapping, down) {
+          (Some(wrapping), true)  => DisplayLineDown(wrapping),
+          (Some(wrapping), false) => DisplayLineUp(wrapping),
+          (None, true)            => LineDown,
+          (None, false)           => LineUp,
+        };
+        (WinCmd::MoveCaret(adjustment), count)
+      }
+      // a count takes gg and G to that line
+      WinCmd::MoveCaret(caret::Adjustment::FirstLine) |
+      WinCmd::MoveCaret(caret::Adjustment::LastLine) if count.is_some() => {
+        let line = count.unwrap_or(1).saturating_sub(1);
+        (WinCmd::MoveCaret(caret::Adjustment::Line(line)), None)
+      }
+      WinCmd::SearchNext(reverse)       =>
+        match self.find_next_match(reverse, count.unwrap_or(1), win) {
+          Some((line, column)) => {
+            let adjustment = caret::Adjustment::Set(line, column);
+            (WinCmd::MoveCaret(adjustment), None)
+          }
+          None                 => (WinCmd::SearchNext(reverse), count),
+        },
+      _                                 => (cmd, count),
+    };
+
+    // a register named is used by the command following it, with the counts
+    // preceding and following the name multiplied
+    let (register, count) = match win.register.take() {
+      Some((name, Some(register_count))) =>
+        (Some(name), Some(register_count.saturating_mul(count.unwrap_or(1)))),
+      Some((name, None))                 => (Some(name), count),
+      None                               => (None, count),
+    };
+
+    // a pending operator is applied by the motion following it, while any
+    // other command cancels it
+    if let EditMode::OperatorPending(operator, operator_count) = win.mode {
+      let count = operator_count.saturating_mul(count.unwrap_or(1));
+      match cmd {
+        WinCmd::MoveCaret(adjustment)    =>
+          self.operate_on_motion(operator, adjustment, count, register, win),
+        WinCmd::OperateOnLines(operator) =>
+          self.operate_on_lines(operator, count, register, win),
+        _                                =>
+          self.set_edit_mode(EditMode::Normal, win),
+      }
+      return;
+    }
+
+    // an insert given a count is kept track of to be repeated when it's done
+    match cmd {
+      WinCmd::EnterInsertMode | WinCmd::EnterInsertModeStartOfLine |
+      WinCmd::EnterInsertModeAppend | WinCmd::EnterInsertModeAppendEndOfLine |
+      WinCmd::EnterInsertModeNextLine | WinCmd::EnterInsertModePreviousLine |
+      WinCmd::EnterReplaceMode(_) if count.unwrap_or(1) > 1 =>
+        win.insert_repeat = Some((cmd.clone(), count.unwrap_or(1), Vec::new())),
+      WinCmd::EnterNormalMode                                => (),
+      _                                                      => {
+        win.insert_repeat.as_mut().map(|&mut (_, _, ref mut cmds)|
+          cmds.push(cmd.clone()));
+      }
+    }
+
     match cmd {
       WinCmd::MoveCaret(adjustment)          => {
-        self.move_caret(adjustment, win);
+        let mode = win.mode;
+        self.buffers.get(&win.buf_id).map(|buffer| {
+          win.caret_mut().adjust_repeatedly(adjustment, count.unwrap_or(1),
+                                            buffer);
+          // some motions find the end of the buffer, beyond the last character
+          if mode != EditMode::Insert {
+            win.caret_mut().adjust(caret::Adjustment::Clamp, buffer);
+          }
+          win.scroll_into_view(buffer); });
+        win.needs_redraw = true;
       }
       WinCmd::PageUp                         => {
-        let screen::Rect(_, screen::Size(rows, _)) = win.rect;
-        self.scroll_view(-(rows as isize), win);
+        let screen::Size(rows, _) = win.view_size();
+        let pages = count.unwrap_or(1) as isize;
+        self.scroll_view(-(rows as isize) * pages, win);
       }
       WinCmd::PageDown                       => {
-        let screen::Rect(_, screen::Size(rows, _)) = win.rect;
-        self.scroll_view(rows as isize, win);
+        let screen::Size(rows, _) = win.view_size();
+        let pages = count.unwrap_or(1) as isize;
+        self.scroll_view(rows as isize * pages, win);
       }
+      // a count scrolls that many lines rather than half a window
       WinCmd::HalfPageUp                     => {
-        let screen::Rect(_, screen::Size(rows, _)) = win.rect;
-        self.scroll_view(-(rows as isize) / 2, win);
+        let screen::Size(rows, _) = win.view_size();
+        let lines = count.unwrap_or(rows as usize / 2) as isize;
+        self.scroll_view(-lines, win);
       }
       WinCmd::HalfPageDown                   => {
-        let screen::Rect(_, screen::Size(rows, _)) = win.rect;
-        self.scroll_view(rows as isize / 2, win);
+        let screen::Size(rows, _) = win.view_size();
+        let lines = count.unwrap_or(rows as usize / 2) as isize;
+        self.scroll_view(lines, win);
+      }
+      WinCmd::GotoLine(address)              => {
+        let line = win.caret().line();
+        let num_lines = self.buffers.get(&win.buf_id).map(|buffer|
+          buffer.num_lines()).expect("Couldn't find buffer.");
+        let line = address.resolve_clamped(line, num_lines);
+        self.move_caret(caret::Adjustment::Set(line, 0), win);
       }
       WinCmd::EnterNormalMode                => {
-        self.set_win_cmd_mode(&win.normal_mode);
+        // an insert given a count is repeated, opening a new line each time if
+        // that's how it started
+        if let Some((enter, count, cmds)) = win.insert_repeat.take() {
+          for _ in 1..count {
+            match enter {
+              WinCmd::EnterInsertModeNextLine |
+              WinCmd::EnterInsertModePreviousLine =>
+                self.handle_win_cmd(enter.clone(), None, win),
+              _                                   => (),
+            }
+            for cmd in cmds.iter() {
+              self.handle_win_cmd(cmd.clone(), None, win);
+            }
+          }
+        }
+        self.set_edit_mode(EditMode::Normal, win);
         let id = win.buf_id;
         self.buffers.remove(&id).map(|buffer| {
           win.caret_mut().adjust(caret::Adjustment::Clamp, &buffer);
@@ -325,62 +1276,116 @@ impl Rim {
         win.needs_redraw = true;
       }
       WinCmd::EnterReplaceMode(replace_line) => {
-        self.set_win_cmd_mode(&replace_mode(replace_line));
+        self.set_edit_mode(EditMode::Replace(replace_line), win);
       }
       WinCmd::EnterInsertMode                => {
-        self.set_win_cmd_mode(&win.insert_mode);
+        self.set_edit_mode(EditMode::Insert, win);
       }
       WinCmd::EnterInsertModeStartOfLine     => {
         self.move_caret(caret::Adjustment::Set(win.caret().line(), 0), win);
-        self.set_win_cmd_mode(&win.insert_mode);
+        self.set_edit_mode(EditMode::Insert, win);
       }
       WinCmd::EnterInsertModeAppend          => {
         self.move_caret(caret::Adjustment::CharNextAppending, win);
-        self.set_win_cmd_mode(&win.insert_mode);
+        self.set_edit_mode(EditMode::Insert, win);
       }
       WinCmd::EnterInsertModeAppendEndOfLine => {
         let col = self.buffers.get(&win.buf_id).map(|buf|
           buf.line_length(win.caret().line()).unwrap()).unwrap();
         self.move_caret(caret::Adjustment::Set(win.caret().line(), col), win);
-        self.set_win_cmd_mode(&win.insert_mode);
+        self.set_edit_mode(EditMode::Insert, win);
       }
       WinCmd::EnterInsertModeNextLine        => {
         let col = self.buffers.get(&win.buf_id).map(|buf|
           buf.line_length(win.caret().line()).unwrap()).unwrap();
         self.move_caret(caret::Adjustment::Set(win.caret().line(), col), win);
         self.insert("\n".to_string(), win);
-        self.set_win_cmd_mode(&win.insert_mode);
+        self.set_edit_mode(EditMode::Insert, win);
       }
       WinCmd::EnterInsertModePreviousLine    => {
         let line = win.caret().line();
         self.move_caret(caret::Adjustment::Set(line, 0), win);
         self.insert("\n".to_string(), win);
         self.move_caret(caret::Adjustment::Set(line, 0), win);
-        self.set_win_cmd_mode(&win.insert_mode);
+        self.set_edit_mode(EditMode::Insert, win);
+      }
+      WinCmd::EnterVisualMode(kind)          => {
+        match win.mode {
+          // entering the current kind of visual mode again leaves it
+          EditMode::Visual(current) if current == kind =>
+            self.set_edit_mode(EditMode::Normal, win),
+          EditMode::Visual(_)                          =>
+            self.set_edit_mode(EditMode::Visual(kind), win),
+          _                                            => {
+            win.anchor = *win.caret();
+            self.set_edit_mode(EditMode::Visual(kind), win);
+          }
+        }
+      }
+      WinCmd::SwapSelectionEnds              => {
+        let anchor = win.anchor;
+        win.anchor = *win.caret();
+        self.move_caret(
+          caret::Adjustment::Set(anchor.line(), anchor.column()), win);
       }
-      WinCmd::OpenBuffer(path)               => {
-        self.load_buffer(path.as_path()).map(|buf_id| {
+      WinCmd::OperateOnSelection(operator)   => {
+        win.selection().map(|selection|
+          self.operate(operator, selection, register, win));
+      }
+      WinCmd::PendOperator(operator)         => {
+        let mode = EditMode::OperatorPending(operator, count.unwrap_or(1));
+        self.set_edit_mode(mode, win);
+        // the register is kept for the motion applying the operator
+        win.register = register.map(|name| (name, None));
+      }
+      WinCmd::Operate(operator, adjustment)  => {
+        self.operate_on_motion(operator, adjustment, count.unwrap_or(1),
+                               register, win);
+      }
+      WinCmd::OperateOnLines(operator)       => {
+        self.operate_on_lines(operator, count.unwrap_or(1), register, win);
+      }
+      WinCmd::Put(before)                    => {
+        self.put(register, before, count.unwrap_or(1), win);
+      }
+      // searches which were cancelled or didn't find anything
+      WinCmd::PendCharSearch(_) | WinCmd::SearchChar(_) |
+      WinCmd::RepeatCharSearch(_) | WinCmd::SearchNext(_) => (),
+      // handled before getting here
+      WinCmd::PendRegister(_) | WinCmd::SelectRegister(_) |
+      WinCmd::MoveCaretByDisplayLine(_)                   => (),
+      WinCmd::OpenBuffer(path, discard)      => {
+        self.load_buffer(path.as_path(), discard).map(|buf_id| {
           win.set_buf_id(buf_id);
-          let screen::Rect(_, size) = win.rect;
-          win.view_mut().set_size(size);
+          let view_size = win.view_size();
+          win.view_mut().set_size(view_size);
           self.buffers.get(&win.buf_id).map(|buffer| {
-            let caret = *win.caret();
-            win.view_mut().scroll_into_view(caret, buffer) }); });
+            win.caret_mut().adjust(caret::Adjustment::Clamp, buffer);
+            win.scroll_into_view(buffer) }); });
       }
-      WinCmd::SaveBuffer                     => {
-        self.buffers.get(&win.buf_id).map(|buffer|
-          buffer.write().ok().expect("Failed to save buffer."));
+      WinCmd::SaveBuffer(path, overwrite)    => {
+        self.save_buffer(win.buf_id, path, overwrite);
+        win.needs_redraw = true;
       }
       WinCmd::Insert(string)                 => {
+        let string = self.expand_tab(string, win);
         self.insert(string, win);
       }
       WinCmd::ReplaceLine(string)            => {
         self.replace(string, win);
       }
       WinCmd::Replace(string)                => {
-        self.replace(string, win);
-        self.set_win_cmd_mode(&win.normal_mode);
-        self.move_caret(caret::Adjustment::CharPrev, win);
+        // a count replaces as many characters, if the line has that many left
+        let count = win.insert_repeat.take().map(|(_, count, _)| count).
+          unwrap_or(1);
+        let (line, column) = (win.caret().line(), win.caret().column());
+        let line_length = self.buffers.get(&win.buf_id).and_then(|buffer|
+          buffer.line_length(line)).unwrap_or(0);
+        self.set_edit_mode(EditMode::Normal, win);
+        if column + count <= line_length {
+          for _ in 0..count { self.replace(string.clone(), win); }
+          self.move_caret(caret::Adjustment::CharPrev, win);
+        }
       }
       WinCmd::Backspace                      => {
         let mut start = win.caret().clone();
@@ -397,63 +1402,86 @@ impl Rim {
       WinCmd::BackspaceOnLine                => {
         let mut start = win.caret().clone();
         self.buffers.get(&win.buf_id).map(|buffer|
-          start.adjust(caret::Adjustment::CharPrev, buffer));
-        self.delete_range(start, win.caret().clone(), win);
+          for _ in 0..count.unwrap_or(1) {
+            start.adjust(caret::Adjustment::CharPrev, buffer);
+          });
+        let end = win.caret().clone();
+        self.delete_to_register(start, end, register, win);
       }
       WinCmd::DeleteOnLine                   => {
         let mut end = win.caret().clone();
         self.buffers.get(&win.buf_id).map(|buffer|
-          end.adjust(caret::Adjustment::CharNextAppending, buffer));
-        self.delete_range(win.caret().clone(), end, win);
+          for _ in 0..count.unwrap_or(1) {
+            end.adjust(caret::Adjustment::CharNextAppending, buffer);
+          });
+        let start = win.caret().clone();
+        self.delete_to_register(start, end, register, win);
         self.move_caret(caret::Adjustment::Clamp, win);
       }
-      WinCmd::DeleteLine                     => {
-        let mut start = win.caret().clone();
-        let mut end = win.caret().clone();
-        let mut last_line = false;
-        self.buffers.get(&win.buf_id).map(|buffer| {
-          let line = win.caret().line();
-          let line_len = buffer.line_length(line).unwrap();
-          last_line = line + 1 == buffer.num_lines();
-          start.adjust(caret::Adjustment::Set(line, 0), buffer);
-          end.adjust(caret::Adjustment::Set(line, line_len), buffer);
-          if !last_line { end.adjust(caret::Adjustment::CharNextFlat, buffer); }
-          else { start.adjust(caret::Adjustment::CharPrevFlat, buffer); } });
-        self.delete_range(start, end, win);
-        if last_line {
-          self.move_caret(caret::Adjustment::Set(win.caret().line(), 0), win);
+      WinCmd::DeleteLines(range)             => {
+        let line = win.caret().line();
+        let num_lines = self.buffers.get(&win.buf_id).map(|buffer|
+          buffer.num_lines()).expect("Couldn't find buffer.");
+        match range.resolve(line, num_lines) {
+          Ok((first, last)) => {
+            let (mut anchor, mut caret) = (*win.caret(), *win.caret());
+            self.buffers.get(&win.buf_id).map(|buffer| {
+              anchor.adjust(caret::Adjustment::Set(first, 0), buffer);
+              caret.adjust(caret::Adjustment::Set(last, 0), buffer); });
+            let selection = Selection {
+              kind: SelectionKind::Line, anchor: anchor, caret: caret
+            };
+            self.operate(Operator::Delete, selection, register, win);
+          }
+          Err(error)        => self.show_message(format!("{}", error)),
         }
       }
-      WinCmd::DeleteRestOfLine               => {
-        let mut end = win.caret().clone();
-        self.buffers.get(&win.buf_id).map(|buffer| {
-          let line = win.caret().line();
-          let line_len = buffer.line_length(line).unwrap();
-          end.adjust(caret::Adjustment::Set(line, line_len), buffer) });
-        self.delete_range(win.caret().clone(), end, win);
-        self.move_caret(caret::Adjustment::Clamp, win);
+      WinCmd::Substitute(range, subst)       => {
+        self.substitute(range, subst, win);
       }
-      WinCmd::ChangeRestOfLine               => {
-        let mut end = win.caret().clone();
-        self.buffers.get(&win.buf_id).map(|buffer| {
-          let line = win.caret().line();
-          let line_len = buffer.line_length(line).unwrap();
-          end.adjust(caret::Adjustment::Set(line, line_len), buffer) });
-        self.delete_range(win.caret().clone(), end, win);
-        self.set_win_cmd_mode(&win.insert_mode);
+      WinCmd::ConfirmSubstitution(answer)    => {
+        self.confirm_substitution(answer, win);
       }
+      WinCmd::Undo                           => {
+        for _ in 0..count.unwrap_or(1) { self.undo(false, win); }
+      }
+      WinCmd::Redo                           => {
+        for _ in 0..count.unwrap_or(1) { self.undo(true, win); }
+      }
+      WinCmd::RepeatChange                   => {
+        self.repeat_change(count, register, win);
+      }
+    }
+  }
+
+  // Gives the commands of the last change again at the caret.
+  fn repeat_change(&mut self, count: Option<usize>, register: Option<char>,
+                   win: &mut Window) {
+    let change = self.changes.repeat(count);
+    // a register named is used by the change in place of its own
+    win.register = register.map(|name| (name, None));
+    for (cmd, count) in change {
+      self.handle_win_cmd(cmd, count, win);
     }
+    win.register = None;
   }
 
-  fn set_win_cmd_mode(&mut self, mode: &command::Mode) {
-    self.cmd_thread.set_mode(mode.clone(), 1);
+  fn set_edit_mode(&mut self, mode: EditMode, win: &mut Window) {
+    win.mode = mode;
+    win.char_search = None;
+    win.register_prompt = None;
+    self.cmd_thread.set_mode(win.cmd_mode(), 1);
+    win.needs_redraw = true;
   }
 
   fn scroll_view(&mut self, amount: isize, win: &mut Window) {
     let line = win.view().scroll_line();
     let new_line = std::cmp::max(line as isize + amount, 0) as usize;
     win.view_mut().set_scroll(new_line, 0);
-    let caret_line = win.view().line_clamped_to_view(win.caret().line());
+    let caret_line = self.buffers.get(&win.buf_id).map(|buffer|
+      win.view().line_clamped_to_view(win.caret().line(), buffer,
+                                      &win.options)).
+      expect("Couldn't find buffer.");
     self.move_caret(caret::Adjustment::Set(caret_line, 0), win);
     self.move_caret(caret::Adjustment::Clamp, win);
   }
@@ -461,118 +1489,709 @@ impl Rim {
   fn move_caret(&mut self, adjustment: caret::Adjustment, win: &mut Window) {
      self.buffers.get(&win.buf_id).map(|buffer| {
        win.caret_mut().adjust(adjustment, buffer);
-       let caret = *win.caret();
-       win.view_mut().scroll_into_view(caret, buffer); });
+       win.scroll_into_view(buffer); });
      win.needs_redraw = true;
   }
 
-  fn replace(&mut self, string: String, win: &mut Window) {
+  // deletes the lines from |first| through |last|
+  fn delete_lines(&mut self, first: usize, last: usize, win: &mut Window) {
+    let mut start = win.caret().clone();
     let mut end = win.caret().clone();
-    self.buffers.get(&win.buf_id).map(|buffer|
-      end.adjust(caret::Adjustment::CharNextAppending, buffer));
-    self.delete_range(win.caret().clone(), end, win);
-    self.insert(string, win);
+    let mut last_line = false;
+    self.buffers.get(&win.buf_id).map(|buffer| {
+      let line_len = buffer.line_length(last).unwrap();
+      last_line = last + 1 == buffer.num_lines();
+      start.adjust(caret::Adjustment::Set(first, 0), buffer);
+      end.adjust(caret::Adjustment::Set(last, line_len), buffer);
+      if !last_line { end.adjust(caret::Adjustment::CharNextFlat, buffer); }
+      else { start.adjust(caret::Adjustment::CharPrevFlat, buffer); } });
+    self.delete_range(start, end, win);
+    if last_line {
+      self.move_caret(caret::Adjustment::Set(win.caret().line(), 0), win);
+    }
   }
 
-  fn insert(&mut self, string: String, win: &mut Window) {
-    self.buffers.remove(&win.buf_id).map(|mut buffer| {
-      let (insert_line, insert_col) =
-        (win.caret().line(), win.caret().column());
-      // update windows displaying the buffer, character by character
-      let (mut c_line, mut c_col) = (insert_line, insert_col);
-      for c in string.chars() {
-        let newline = c == '\n';
-        // update the caret of the focused window
-        let (new_line, new_col) =
-          if newline { (win.caret().line() + 1, 0) }
-          else       { (win.caret().line(), win.caret().column() + 1) };
-        win.caret_mut().adjust(
-          caret::Adjustment::Set(new_line, new_col), &buffer);
-        // update other windows which has viewed the buffer
-        let id = win.buf_id;
-        for (_, win) in self.windows.iter_mut() {
-          if !win.has_buf_id(id) { continue }
-          // keep the content of other views still if possible
-          let (scroll_line, scroll_col) = win.view_for(id).map(|v|
-            (v.scroll_line(), v.scroll_column())).unwrap();
-          if scroll_line > c_line && newline {
-            win.view_mut_for(id).unwrap().set_scroll(
-              scroll_line + 1, scroll_col);
+  // Applies |operator| to the text between the caret and where |adjustment|,
+  // repeated |count| times, takes it.
+  fn operate_on_motion(&mut self, operator: Operator,
+                       adjustment: caret::Adjustment, count: usize,
+                       register: Option<char>, win: &mut Window) {
+    let caret = *win.caret();
+    let on_blank = self.buffers.get(&win.buf_id).and_then(|buffer|
+      buffer.get_char_by_line_column(caret.line(), caret.column())).
+      map(|c| c.is_whitespace()).unwrap_or(true);
+    // changing words leaves the blanks following them alone
+    let changes_words = operator == Operator::Change && !on_blank &&
+      match adjustment { caret::Adjustment::WordNext(_) => true, _ => false };
+    let adjustment = match adjustment {
+      // operating to the right may reach beyond the last character of the line
+      caret::Adjustment::CharNext                        =>
+        caret::Adjustment::CharNextAppending,
+      caret::Adjustment::WordNext(big) if changes_words =>
+        caret::Adjustment::WordEnd(big),
+      _                                                  => adjustment,
+    };
+    let selection = self.buffers.get(&win.buf_id).and_then(|buffer| {
+      let mut start = caret;
+      // a word ending at the caret is changed on its own
+      if changes_words {
+        start.adjust(caret::Adjustment::CharPrevFlat, buffer);
+      }
+      let target = match start.motion_target(adjustment, count, buffer) {
+        Some(target) => target,
+        None         => return None,
+      };
+      let selection =
+        Selection { kind: SelectionKind::Char, anchor: caret, caret: target };
+      let (start, mut end) = selection.ordered_ends();
+      match adjustment.motion_kind() {
+        caret::MotionKind::Linewise  =>
+          Some(Selection { kind: SelectionKind::Line, .. selection }),
+        caret::MotionKind::Inclusive |
+        caret::MotionKind::Exclusive => {
+          // an inclusive motion never includes the newline
+          if adjustment.motion_kind() == caret::MotionKind::Inclusive {
+            end.adjust(caret::Adjustment::CharNextAppending, buffer);
+          }
+          if (start.line(), start.column()) == (end.line(), end.column()) {
+            return None;
           }
-          // update caret of other window
-          let (cur_line, cur_col) =
-            win.caret_for(id).map(|c| (c.line(), c.column())).unwrap();
-          let (new_line, new_col) =
-            if c_line < cur_line && newline { (cur_line + 1, cur_col) }
-            else if cur_line == c_line && c_col <= cur_col {
-              if !newline { (cur_line, cur_col + 1) }
-              else { (cur_line, if c_col == 0 { 0 } else { c_col - 1 }) } }
-            else { (cur_line, cur_col) };
-          win.caret_mut_for(id).unwrap().adjust(
-            caret::Adjustment::WeakSet(new_line, new_col), &buffer);
-          win.needs_redraw = true;
+          end.adjust(caret::Adjustment::CharPrevFlat, buffer);
+          Some(Selection { anchor: start, caret: end, .. selection })
         }
-        if newline { c_line += 1; } else { c_col += 1; }
-      }
-      // insert string into buffer
-      buffer.insert_at_line_column(string, insert_line, insert_col).ok().
-        expect("View had invalid caret.");
+      } });
+    match selection {
+      Some(selection) => self.operate(operator, selection, register, win),
+      None            => self.set_edit_mode(EditMode::Normal, win),
+    }
+  }
+
+  // Applies |operator| to |count| lines, starting with the line of the caret.
+  fn operate_on_lines(&mut self, operator: Operator, count: usize,
+                      register: Option<char>, win: &mut Window) {
+    let caret = *win.caret();
+    let mut last = caret;
+    self.buffers.get(&win.buf_id).map(|buffer|
+      for _ in 1..count { last.adjust(caret::Adjustment::LineDown, buffer); });
+    let selection =
+      Selection { kind: SelectionKind::Line, anchor: caret, caret: last };
+    self.operate(operator, selection, register, win);
+  }
+
+  // Applies |operator| to |selection|, returning the focused window to normal
+  // mode unless changing. Text deleted or yanked is kept in |register|, or in
+  // the registers used when none is named.
+  fn operate(&mut self, operator: Operator, selection: Selection,
+             register: Option<char>, win: &mut Window) {
+    self.set_edit_mode(EditMode::Normal, win);
+    let (first_line, last_line) = selection.lines();
+    let (start, end) = selection.ordered_ends();
+
+    // find the selected ranges, ends being exclusive, with a block selection
+    // making up one range per line
+    let ranges: Vec<(usize, usize, usize, usize)> =
+      self.buffers.get(&win.buf_id).map(|buffer| match selection.kind {
+        SelectionKind::Char  => {
+          let line_len = buffer.line_length(end.line()).unwrap();
+          let (end_line, end_col) =
+            if end.column() < line_len { (end.line(), end.column() + 1) }
+            else if end.line() + 1 < buffer.num_lines() { (end.line() + 1, 0) }
+            else { (end.line(), line_len) };
+          vec!((start.line(), start.column(), end_line, end_col))
+        }
+        SelectionKind::Line  => {
+          let line_len = buffer.line_length(last_line).unwrap();
+          vec!((first_line, 0, last_line, line_len))
+        }
+        SelectionKind::Block =>
+          (first_line..last_line + 1).filter_map(|line|
+            selection.columns_on_line(line, buffer).map(|(start, end)|
+              (line, start, line, end))).
+          collect(),
+      }).
+      expect("Couldn't find buffer.");
+
+    if operator == Operator::Indent || operator == Operator::Unindent {
+      self.shift_lines(first_line, last_line, operator == Operator::Indent,
+                       win);
+      return;
+    }
+
+    let text = self.buffers.get(&win.buf_id).map(|buffer| {
+      let texts: Vec<String> = ranges.iter().
+        map(|&(start_line, start_col, end_line, end_col)|
+          buffer.get_range(start_line, start_col, end_line, end_col).
+          unwrap_or(String::new())).
+        collect();
+      let text = texts.join("\n");
+      if selection.kind == SelectionKind::Line { text + "\n" } else { text } }).
+      expect("Couldn't find buffer.");
+    let contents = Register { text: text, kind: selection.kind };
+    self.fill_register(register, contents, operator == Operator::Yank);
+
+    let deleting = operator == Operator::Delete;
+    if operator == Operator::Yank {
+      // move to the start of the yanked text, or just to its first line if it
+      // was whole lines
+      let column = win.caret().column();
+      ranges.first().map(|&(line, start_col, _, _)| {
+        let column = if selection.kind == SelectionKind::Line { column }
+                     else                                     { start_col };
+        self.move_caret(caret::Adjustment::Set(line, column), win) });
+    }
+    else if deleting && selection.kind == SelectionKind::Line {
+      self.delete_lines(first_line, last_line, win);
+    }
+    else {
+      // delete backwards to keep the locations of the earlier ranges intact
+      for &(start_line, start_col, end_line, end_col) in ranges.iter().rev() {
+        let (mut start, mut end) = (win.caret().clone(), win.caret().clone());
+        self.buffers.get(&win.buf_id).map(|buffer| {
+          start.adjust(caret::Adjustment::Set(start_line, start_col), buffer);
+          end.adjust(caret::Adjustment::Set(end_line, end_col), buffer); });
+        self.delete_range(start, end, win);
+      }
+    }
+    if operator == Operator::Change {
+      self.set_edit_mode(EditMode::Insert, win);
+    }
+    else {
+      self.move_caret(caret::Adjustment::Clamp, win);
+    }
+  }
+
+  // Indents or unindents the lines from |first| through |last| by the shift
+  // width, leaving the caret at the start of the first line. Empty lines are
+  // not indented.
+  fn shift_lines(&mut self, first: usize, last: usize, indent: bool,
+                 win: &mut Window) {
+    let (shift_width, tab_stop) = self.buffers.get(&win.buf_id).map(|buffer|
+      (buffer.options().number(Opt::ShiftWidth),
+       buffer.options().number(Opt::TabStop))).
+      expect("Couldn't find buffer.");
+    for line in first..last + 1 {
+      let (line_len, indentation) = self.buffers.get(&win.buf_id).
+        and_then(|buffer| buffer.line_iter().from(line).next().map(|chars| {
+          let line_len = buffer.line_length(line).unwrap();
+          // the leading whitespace making up at most one shift width
+          let indentation = chars.take(line_len).
+            take_while(|&c| c == ' ' || c == '\t').
+            scan(0, |width, c| if *width >= shift_width { None } else {
+              *width += if c == '\t' { tab_stop - *width % tab_stop }
+                        else          { 1 };
+              Some(()) }).
+            count();
+          (line_len, indentation) })).
+        expect("Couldn't find line to shift.");
+      if indent && line_len > 0 {
+        self.move_caret(caret::Adjustment::Set(line, 0), win);
+        self.insert(std::iter::repeat(' ').take(shift_width).collect(), win);
+      }
+      else if !indent && indentation > 0 {
+        let (mut start, mut end) = (win.caret().clone(), win.caret().clone());
+        self.buffers.get(&win.buf_id).map(|buffer| {
+          start.adjust(caret::Adjustment::Set(line, 0), buffer);
+          end.adjust(caret::Adjustment::Set(line, indentation), buffer); });
+        self.delete_range(start, end, win);
+      }
+    }
+    self.move_caret(caret::Adjustment::Set(first, 0), win);
+  }
+
+  // Substitutes over |range| in |win|, either at once or one match at a time
+  // as the user confirms each of them. Since the window leaves normal mode
+  // while confirming, the substitution makes a single undo step either way.
+  fn substitute(&mut self, range: ex::Range, substitution: ex::Substitution,
+                win: &mut Window) {
+    let num_lines = self.buffers.get(&win.buf_id).map(|buffer|
+      buffer.num_lines()).expect("Couldn't find buffer.");
+    let (first, last) = match range.resolve(win.caret().line(), num_lines) {
+      Ok(lines)  => lines,
+      Err(error) => return self.show_message(format!("{}", error)),
+    };
+    // an empty pattern repeats the last search
+    let pattern = if !substitution.pattern.is_empty() {
+      Some(substitution.pattern.clone())
+    } else {
+      self.last_search.as_ref().map(|&(ref search, _)|
+        search.pattern().to_string())
+    };
+    let ignore_case =
+      substitution.ignore_case || self.options.bool(Opt::IgnoreCase);
+    let search = match pattern.map(|pattern|
+                         Search::new(&pattern, ignore_case)) {
+      Some(Ok(search)) => search,
+      Some(Err(error)) => return self.show_message(format!("{}", error)),
+      None             => return self.show_message(
+        "No previous regular expression".to_string()),
+    };
+    self.substitution = Some(SubstitutionProgress {
+      search: search,
+      replacement: substitution.replacement,
+      global: substitution.global,
+      line: first,
+      matches: None,
+      anchor: (0, first, 0),
+      last_line: last,
+      substitutions: 0,
+      lines: 0,
+      last_changed: None,
+    });
+    if self.find_substitution_match(win).is_none() {
+      let pattern = self.substitution.take().map(|progress|
+        progress.search.pattern().to_string()).unwrap();
+      return self.show_message(format!("Pattern not found: {}", pattern));
+    }
+    if substitution.confirm {
+      self.set_edit_mode(EditMode::ConfirmSubstitution, win);
+      self.prompt_substitution(win);
+    }
+    else { self.substitute_remaining(win); }
+  }
+
+  fn confirm_substitution(&mut self, answer: Confirmation, win: &mut Window) {
+    let found = self.find_substitution_match(win);
+    match (answer, found) {
+      (Confirmation::Yes, Some(found))  => {
+        self.replace_substitution_match(found, win);
+        self.prompt_substitution(win);
+      }
+      (Confirmation::No, Some(_))       => {
+        self.substitution.as_mut().map(|progress| progress.advance(None));
+        self.prompt_substitution(win);
+      }
+      (Confirmation::All, _)            => self.substitute_remaining(win),
+      (Confirmation::Last, Some(found)) => {
+        self.replace_substitution_match(found, win);
+        self.finish_substitution(win);
+      }
+      _                                 => self.finish_substitution(win),
+    }
+  }
+
+  // Moves the caret of |win| to the next match of the substitution in progress
+  // and asks whether to replace it, finishing once there are no more matches.
+  fn prompt_substitution(&mut self, win: &mut Window) {
+    match self.find_substitution_match(win) {
+      Some((line, start, _, _)) => {
+        self.move_caret(caret::Adjustment::Set(line, start), win);
+        let replacement = self.substitution.as_ref().map(|progress|
+          progress.replacement.clone()).unwrap_or(String::new());
+        self.show_message(
+          format!("replace with {} (y/n/a/q/l)?", replacement));
+      }
+      None                      => self.finish_substitution(win),
+    }
+  }
+
+  fn substitute_remaining(&mut self, win: &mut Window) {
+    while let Some(found) = self.find_substitution_match(win) {
+      self.replace_substitution_match(found, win);
+    }
+    self.finish_substitution(win);
+  }
+
+  // Finds the next match of the substitution in progress, its line, start and
+  // end columns along with the text replacing it.
+  fn find_substitution_match(&mut self, win: &Window)
+      -> Option<(usize, usize, usize, String)> {
+    let progress = match self.substitution.as_mut() {
+      Some(progress) => progress,
+      None           => return None,
+    };
+    let buffer = self.buffers.get(&win.buf_id).expect("Couldn't find buffer.");
+    while progress.line <= progress.last_line {
+      if progress.matches.is_none() {
+        progress.matches = Some(progress.search.substitutions_on_line(
+          progress.line, &progress.replacement, progress.global, buffer));
+        progress.anchor = (0, progress.line, 0);
+      }
+      let (origin, line, column) = progress.anchor;
+      match progress.matches.as_ref().and_then(|matches| matches.first()) {
+        Some(&(start, end, ref replacement)) =>
+          return Some((line, column + start - origin, column + end - origin,
+                       replacement.clone())),
+        None                                 => {
+          // the rest of the line is on the line of the anchor
+          progress.line = line + 1;
+          progress.matches = None;
+        }
+      }
+    }
+    None
+  }
+
+  fn replace_substitution_match(&mut self,
+                                found: (usize, usize, usize, String),
+                                win: &mut Window) {
+    let (line, start, end, replacement) = found;
+    self.move_caret(caret::Adjustment::Set(line, end), win);
+    let end_caret = *win.caret();
+    self.move_caret(caret::Adjustment::Set(line, start), win);
+    if end > start {
+      let start_caret = *win.caret();
+      self.delete_range(start_caret, end_caret, win);
+    }
+    let new_lines = replacement.matches('\n').count();
+    if !replacement.is_empty() { self.insert(replacement, win); }
+    let (line_after, column_after) = (win.caret().line(), win.caret().column());
+    self.substitution.as_mut().map(|progress| {
+      if progress.last_changed != Some(line) { progress.lines += 1; }
+      progress.substitutions += 1;
+      progress.last_changed = Some(line_after);
+      progress.last_line += new_lines;
+      progress.advance(Some((line_after, column_after))); });
+  }
+
+  // Ends the substitution in progress, leaving the caret at the start of the
+  // last line substituted on and telling how much was substituted.
+  fn finish_substitution(&mut self, win: &mut Window) {
+    if win.mode == EditMode::ConfirmSubstitution {
+      self.set_edit_mode(EditMode::Normal, win);
+    }
+    self.substitution.take().map(|progress| {
+      let plural = |n| if n == 1 { "" } else { "s" };
+      let message = match progress.last_changed {
+        Some(line) => {
+          self.move_caret(caret::Adjustment::Set(line, 0), win);
+          self.move_caret(caret::Adjustment::Clamp, win);
+          format!("{} substitution{} on {} line{}", progress.substitutions,
+                  plural(progress.substitutions), progress.lines,
+                  plural(progress.lines))
+        }
+        None       => String::new(),
+      };
+      self.show_message(message); });
+  }
+
+  // Deletes the text between |start| and |end|, keeping it in |register|.
+  fn delete_to_register(&mut self, start: Caret, end: Caret,
+                        register: Option<char>, win: &mut Window) {
+    let text = self.buffers.get(&win.buf_id).and_then(|buffer|
+      buffer.get_range(start.line(), start.column(), end.line(), end.column()).
+      ok());
+    match text {
+      Some(ref text) if !text.is_empty() => self.fill_register(register,
+        Register { text: text.clone(), kind: SelectionKind::Char }, false),
+      _                                  => (),
+    }
+    self.delete_range(start, end, win);
+  }
+
+  // Keeps |contents| in |register| as yanked or deleted text. Text kept in the
+  // registers of the selections of the desktop is copied there as well.
+  fn fill_register(&mut self, register: Option<char>, contents: Register,
+                   yanked: bool) {
+    let selection = register.and_then(clipboard::Selection::from_register);
+    let copied = match selection {
+      Some(selection) => self.clipboard.copy(selection, &contents.text),
+      None            => Ok(()),
+    };
+    if yanked { self.registers.yank(register, contents); }
+    else      { self.registers.delete(register, contents); }
+    copied.unwrap_or_else(|error| self.show_message(format!("{}", error)));
+  }
+
+  // Gets the contents of register |name|. The registers of the selections of
+  // the desktop are pasted from there, text ending with a newline being taken
+  // as lines unless it was copied from here.
+  fn register_contents(&mut self, name: char) -> Option<Register> {
+    let kept = self.registers.get(name).cloned();
+    let selection = match clipboard::Selection::from_register(name) {
+      Some(selection) => selection,
+      None            => return kept,
+    };
+    match self.clipboard.paste(selection) {
+      Ok(ref text) if text.is_empty() => None,
+      Ok(text)                        => Some(match kept {
+        Some(ref register) if register.text == text => register.clone(),
+        _ if text.ends_with('\n')                   =>
+          Register { text: text, kind: SelectionKind::Line },
+        _                                           =>
+          Register { text: text, kind: SelectionKind::Char },
+      }),
+      Err(error)                      => {
+        self.show_message(format!("{}", error));
+        kept
+      }
+    }
+  }
+
+  // Records the keys typed from now on into the register |name|.
+  fn start_recording(&mut self, name: char) {
+    self.cmd_thread.start_recording();
+    self.recording = Some(name);
+    self.show_message(format!("recording @{}", name));
+  }
+
+  // Stops recording, keeping the keys typed in the register recorded to.
+  fn stop_recording(&mut self) {
+    let keys = self.cmd_thread.stop_recording();
+    self.recording.take().map(|name| self.registers.record(name, keys));
+    self.show_message(String::new());
+  }
+
+  // Has the keys in a register, recorded or written as their notation,
+  // processed as if typed |count| times. Register @ is the one last executed.
+  fn execute_register(&mut self, name: char, count: usize) {
+    let name = match name {
+      '@'  => match self.last_executed {
+        Some(name) => name,
+        None       => {
+          self.show_message("No previously used register".to_string());
+          return;
+        }
+      },
+      name => name,
+    };
+    let keys = match clipboard::Selection::from_register(name) {
+      Some(_) => self.register_contents(name).map(|register| register.keys()),
+      None    => self.registers.keys(name),
+    };
+    self.last_executed = Some(name);
+    keys.map(|keys| {
+      let repeated = (0..count).flat_map(|_| keys.iter().cloned()).collect();
+      self.cmd_thread.push_keys(repeated); });
+  }
+
+  // Puts the contents of |register|, or of the unnamed register, |count| times
+  // after the caret or before it. Lines are put below or above the line of the
+  // caret, and a block is put into the lines from the caret and down.
+  fn put(&mut self, register: Option<char>, before: bool, count: usize,
+         win: &mut Window) {
+    let name = register.unwrap_or('"');
+    let contents = match self.register_contents(name) {
+      Some(contents) => contents,
+      None           =>
+        return self.show_message(format!("Nothing in register {}", name)),
+    };
+    let (buf_id, line) = (win.buf_id, win.caret().line());
+    let line_length = |rim: &Rim, line| rim.buffers.get(&buf_id).
+      and_then(|buffer| buffer.line_length(line)).unwrap_or(0);
+    match contents.kind {
+      SelectionKind::Char  => {
+        if !before {
+          self.move_caret(caret::Adjustment::CharNextAppending, win);
+        }
+        let start = *win.caret();
+        let text: String = (0..count).map(|_| &contents.text[..]).collect();
+        let spans_lines = text.contains('\n');
+        self.insert(text, win);
+        // the caret ends up on the last character put, or at the start if
+        // putting lines of text
+        if spans_lines {
+          self.move_caret(
+            caret::Adjustment::Set(start.line(), start.column()), win);
+        }
+        else { self.move_caret(caret::Adjustment::CharPrev, win); }
+      }
+      SelectionKind::Line  => {
+        let text: String = (0..count).map(|_| &contents.text[..]).collect();
+        let first_line = if before {
+          self.move_caret(caret::Adjustment::Set(line, 0), win);
+          self.insert(text, win);
+          line
+        } else {
+          let line_len = line_length(self, line);
+          self.move_caret(caret::Adjustment::Set(line, line_len), win);
+          self.insert(format!("\n{}", &text[..text.len() - 1]), win);
+          line + 1
+        };
+        self.move_caret(caret::Adjustment::Set(first_line, 0), win);
+      }
+      SelectionKind::Block => {
+        let column = if before || line_length(self, line) == 0 {
+          win.caret().column()
+        } else { win.caret().column() + 1 };
+        for i in 0..contents.lines().len() {
+          let num_lines = self.buffers.get(&win.buf_id).map(|buffer|
+            buffer.num_lines()).unwrap_or(0);
+          // the block may need more lines than there are below the caret
+          if line + i >= num_lines {
+            let line_len = line_length(self, num_lines - 1);
+            self.move_caret(
+              caret::Adjustment::Set(num_lines - 1, line_len), win);
+            self.insert("\n".to_string(), win);
+          }
+          // lines too short to reach the block are padded, while text
+          // following the block is kept aligned
+          let line_len = line_length(self, line + i);
+          let padding = if line_len < column { column - line_len }
+                        else                 { 0 };
+          let text = format!("{}{}", " ".repeat(padding),
+                             contents.block_line(i, count, line_len > column));
+          self.move_caret(
+            caret::Adjustment::Set(line + i, column - padding), win);
+          self.insert(text, win);
+        }
+        self.move_caret(caret::Adjustment::Set(line, column), win);
+      }
+    }
+    self.move_caret(caret::Adjustment::Clamp, win);
+  }
+
+  fn replace(&mut self, string: String, win: &mut Window) {
+    let mut end = win.caret().clone();
+    self.buffers.get(&win.buf_id).map(|buffer|
+      end.adjust(caret::Adjustment::CharNextAppending, buffer));
+    self.delete_range(win.caret().clone(), end, win);
+    self.insert(string, win);
+  }
+
+  // Expands a tab being inserted into spaces up to the next tab stop if the
+  // expandtab option of the buffer is set.
+  fn expand_tab(&self, string: String, win: &Window) -> String {
+    match self.buffers.get(&win.buf_id) {
+      Some(buffer) if string == "\t" &&
+                      buffer.options().bool(Opt::ExpandTab) => {
+        let tab_stop = buffer.options().number(Opt::TabStop);
+        let column = caret::buffer_to_screen_column(
+          win.caret().line(), win.caret().column(), buffer);
+        " ".repeat(tab_stop - column % tab_stop)
+      }
+      _                                                     => string,
+    }
+  }
+
+  fn insert(&mut self, string: String, win: &mut Window) {
+    self.buffers.remove(&win.buf_id).map(|mut buffer| {
+      let (insert_line, insert_col) =
+        (win.caret().line(), win.caret().column());
+      // update the caret of the focused window, character by character
+      for c in string.chars() {
+        let (new_line, new_col) =
+          if c == '\n' { (win.caret().line() + 1, 0) }
+          else         { (win.caret().line(), win.caret().column() + 1) };
+        win.caret_mut().adjust(
+          caret::Adjustment::Set(new_line, new_col), &buffer);
+      }
+      self.adjust_windows_for_insert(win.buf_id, &string, insert_line,
+                                     insert_col, &buffer);
+      // insert string into buffer
+      buffer.insert_at_line_column(string, insert_line, insert_col).ok().
+        expect("View had invalid caret.");
       // ensure the caret is in the view
-      let caret = *win.caret();
-      win.view_mut().scroll_into_view(caret, &buffer);
+      win.scroll_into_view(&buffer);
       win.needs_redraw = true;
       self.buffers.insert(win.buf_id, buffer); });
   }
 
+  // Updates the windows, other than the focused one, which has viewed buffer
+  // |id| for |string| being inserted at |line|, |column|.
+  fn adjust_windows_for_insert(&mut self, id: BufferId, string: &str,
+                               line: usize, column: usize, buffer: &Buffer) {
+    // update windows displaying the buffer, character by character
+    let (mut c_line, mut c_col) = (line, column);
+    for c in string.chars() {
+      let newline = c == '\n';
+      for (_, win) in self.windows.iter_mut() {
+        if !win.has_buf_id(id) { continue }
+        // keep the content of other views still if possible
+        let (scroll_line, scroll_col) = win.view_for(id).map(|v|
+          (v.scroll_line(), v.scroll_column())).unwrap();
+        if scroll_line > c_line && newline {
+          win.view_mut_for(id).unwrap().set_scroll(
+            scroll_line + 1, scroll_col);
+        }
+        // update caret of other window
+        let (cur_line, cur_col) =
+          win.caret_for(id).map(|c| (c.line(), c.column())).unwrap();
+        let (new_line, new_col) =
+          if c_line < cur_line && newline { (cur_line + 1, cur_col) }
+          else if cur_line == c_line && c_col <= cur_col {
+            if !newline { (cur_line, cur_col + 1) }
+            else { (cur_line, if c_col == 0 { 0 } else { c_col - 1 }) } }
+          else { (cur_line, cur_col) };
+        win.caret_mut_for(id).unwrap().adjust(
+          caret::Adjustment::WeakSet(new_line, new_col), buffer);
+        win.needs_redraw = true;
+      }
+      if newline { c_line += 1; } else { c_col += 1; }
+    }
+  }
+
   fn delete_range(&mut self, start: Caret, end: Caret, win: &mut Window) {
     self.buffers.remove(&win.buf_id).map(|mut buffer| {
       let (start_line, start_col) = (start.line(), start.column());
       let (end_line, end_col) = (end.line(), end.column());
       if buffer.delete_range(start_line, start_col, end_line, end_col).is_ok() {
-        // update other windows which has viewed the buffer
-        let id = win.buf_id;
-        for (_, win) in self.windows.iter_mut() {
-          if !win.has_buf_id(id) { continue }
-          // keep the content of other views still if possible
-          let (scroll_line, scroll_col) = win.view_for(id).map(|v|
-            (v.scroll_line(), v.scroll_column())).unwrap();
-          let new_scroll_line = if scroll_line <= start_line { scroll_line }
-                                else if scroll_line <= end_line { start_line }
-                                else { scroll_line - end_line + start_line };
-          win.view_mut_for(id).unwrap().set_scroll(new_scroll_line, scroll_col);
-          // update caret of other window
-          let (cur_line, cur_col) =
-            win.caret_for(id).map(|c| (c.line(), c.column())).unwrap();
-          let clamped_start_col = if start_col > 0 { start_col - 1 } else { 0 };
-          let new_col =
-            if cur_line < start_line || cur_line > end_line { cur_col }
-            else if start_line == end_line {
-              if cur_col < start_col { cur_col }
-              else if cur_col < end_col { clamped_start_col }
-              else { cur_col - end_col + start_col } }
-            else {
-              if cur_line == start_line {
-                if cur_col < start_col { cur_col } else { clamped_start_col } }
-              else if cur_line == end_line && cur_col >= end_col {
-                cur_col - end_col + start_col }
-              else { 0 } };
-          let new_line =
-            if cur_line >= start_line && cur_line <= end_line { start_line }
-            else if cur_line > end_line { cur_line - end_line + start_line }
-            else { cur_line };
-          win.caret_mut_for(id).unwrap().adjust(
-            caret::Adjustment::WeakSet(new_line, new_col), &buffer);
-          win.needs_redraw = true;
-        }
+        self.adjust_windows_for_delete(win.buf_id, start_line, start_col,
+                                       end_line, end_col, &buffer);
         // update the caret of the focused window and scroll it into view
         win.caret_mut().adjust(
           caret::Adjustment::Set(start_line, start_col), &buffer);
-        let caret = *win.caret();
-        win.view_mut().scroll_into_view(caret, &buffer);
+        win.scroll_into_view(&buffer);
         win.needs_redraw = true;
       }
       self.buffers.insert(win.buf_id, buffer); });
   }
+
+  // Updates the windows, other than the focused one, which has viewed buffer
+  // |id| for the range between |start_line|, |start_col| and |end_line|,
+  // |end_col| being deleted.
+  fn adjust_windows_for_delete(&mut self, id: BufferId, start_line: usize,
+                               start_col: usize, end_line: usize,
+                               end_col: usize, buffer: &Buffer) {
+    for (_, win) in self.windows.iter_mut() {
+      if !win.has_buf_id(id) { continue }
+      // keep the content of other views still if possible
+      let (scroll_line, scroll_col) = win.view_for(id).map(|v|
+        (v.scroll_line(), v.scroll_column())).unwrap();
+      let new_scroll_line = if scroll_line <= start_line { scroll_line }
+                            else if scroll_line <= end_line { start_line }
+                            else { scroll_line - end_line + start_line };
+      win.view_mut_for(id).unwrap().set_scroll(new_scroll_line, scroll_col);
+      // update caret of other window
+      let (cur_line, cur_col) =
+        win.caret_for(id).map(|c| (c.line(), c.column())).unwrap();
+      let clamped_start_col = if start_col > 0 { start_col - 1 } else { 0 };
+      let new_col =
+        if cur_line < start_line || cur_line > end_line { cur_col }
+        else if start_line == end_line {
+          if cur_col < start_col { cur_col }
+          else if cur_col < end_col { clamped_start_col }
+          else { cur_col - end_col + start_col } }
+        else {
+          if cur_line == start_line {
+            if cur_col < start_col { cur_col } else { clamped_start_col } }
+          else if cur_line == end_line && cur_col >= end_col {
+            cur_col - end_col + start_col }
+          else { 0 } };
+      let new_line =
+        if cur_line >= start_line && cur_line <= end_line { start_line }
+        else if cur_line > end_line { cur_line - end_line + start_line }
+        else { cur_line };
+      win.caret_mut_for(id).unwrap().adjust(
+        caret::Adjustment::WeakSet(new_line, new_col), buffer);
+      win.needs_redraw = true;
+    }
+  }
+
+  // Undoes or redoes changes to the buffer of the focused window. The focused
+  // caret is left where the last edit was made.
+  fn undo(&mut self, redo: bool, win: &mut Window) {
+    self.buffers.remove(&win.buf_id).map(|mut buffer| {
+      let id = win.buf_id;
+      let edits = if redo { buffer.redo() } else { buffer.undo() };
+      for edit in edits.unwrap_or(Vec::new()) {
+        let (line, column) = match edit {
+          buffer::Edit::Insert(string, line, column)                    => {
+            self.adjust_windows_for_insert(id, &string, line, column, &buffer);
+            (line, column)
+          }
+          buffer::Edit::Delete(start_line, start_col, end_line, end_col) => {
+            self.adjust_windows_for_delete(id, start_line, start_col,
+                                           end_line, end_col, &buffer);
+            (start_line, start_col)
+          }
+        };
+        win.caret_mut().adjust(caret::Adjustment::Set(line, column), &buffer);
+      }
+      win.caret_mut().adjust(caret::Adjustment::Clamp, &buffer);
+      win.scroll_into_view(&buffer);
+      win.needs_redraw = true;
+      self.buffers.insert(id, buffer); });
+  }
 }
 
 #[cfg(not(test))]
@@ -580,11 +2199,12 @@ const USAGE: &'static str = "
 Rim - Vim-style text editor.
 
 Usage:
-  rim [<file>]
+  rim [-u <rimrc>] [<file>]
   rim -h | --help
   rim --version
 
 Options:
+  -u <rimrc>       Use the given config file, or none if NONE.
   -h --help        Show this screen.
   --version        Show version.
 ";
@@ -593,9 +2213,42 @@ Options:
 #[derive(RustcDecodable)]
 struct Args {
   arg_file: Option<String>,
+  flag_u: Option<String>,
   flag_version: bool,
 }
 
+// rim in the XDG config directory
+#[cfg(not(test))]
+fn config_dir() -> Option<PathBuf> {
+  env::var_os("XDG_CONFIG_HOME").map(PathBuf::from).
+  or_else(|| env::var_os("HOME").map(|home|
+    PathBuf::from(home).join(".config"))).
+  map(|config_home| config_home.join("rim"))
+}
+
+// The config file given, or else rimrc in the config directory if there is
+// one.
+#[cfg(not(test))]
+fn config_path(given: Option<String>) -> Option<PathBuf> {
+  match given {
+    Some(ref given) if given == "NONE" => None,
+    Some(given)                        => Some(PathBuf::from(given)),
+    None                               =>
+      config_dir().map(|dir| dir.join("rimrc")).
+      and_then(|path| if path.is_file() { Some(path) } else { None }),
+  }
+}
+
+// The first of |errors| along with how many more there are, if there are any.
+#[cfg(not(test))]
+fn first_error(mut errors: Vec<String>) -> Option<String> {
+  match errors.len() {
+    0 => None,
+    1 => Some(errors.remove(0)),
+    n => Some(format!("{} (and {} more errors)", errors[0], n - 1)),
+  }
+}
+
 /*
  * Events to the main loop.
  */
@@ -623,10 +2276,13 @@ fn main() {
   cmd_tx.send(Cmd::ResetLayout).unwrap();
   let filename = args.arg_file.unwrap_or("src/rim.rs".to_string());
   cmd_tx.send(Cmd::WinCmd(WinCmd::OpenBuffer(
-    PathBuf::from(&filename)))).unwrap();
+    PathBuf::from(&filename), false))).unwrap();
   let cmd_thread = command::start(key_rx, cmd_tx);
 
   let mut rim = Rim::new(cmd_thread);
+  config_dir().map(|dir| rim.load_grammars(&dir.join("syntax")));
+  config_path(args.flag_u).map(|path|
+    if let Err(error) = rim.source(&path) { rim.show_message(error); });
 
   // attempt to redraw at a regular interval
   let draw_pulse =
@@ -641,12 +2297,16 @@ fn main() {
       Event::Draw           => (),
     }
 
+    // text copied through the terminal is written to it right away
+    for (selection, text) in rim.clipboard.take_terminal_copies() {
+      screen.set_selection(selection.osc52_name(), &text);
+    }
+
     if rim.quit { return Err(()); }
 
     // clear/redraw/update/invalidate everything if the screen size changed
     if screen.update_size() {
-      rim.frame.set_size(screen.size());
-      rim.invalidate_frame();
+      rim.set_size(screen.size());
       for (_, win) in rim.windows.iter_mut() { win.needs_redraw = true; }
       screen.clear();
     }
@@ -655,7 +2315,7 @@ fn main() {
 
     // draw frame if necessary
     if rim.frame_needs_redraw {
-      rim.frame.draw_borders(&mut screen);
+      rim.frame.draw_borders(&rim.highlights, &mut screen);
       rim.frame_needs_redraw = false;
     }
 
@@ -670,13 +2330,29 @@ fn main() {
     // mark windows as not needing redraw
     for (_, win) in rim.windows.iter_mut() { win.needs_redraw = false; }
 
+    // draw the command line if necessary
+    if rim.cmdline_needs_redraw {
+      rim.cmdline.draw(rim.cmdline_rect, &rim.highlights, &mut screen);
+      rim.cmdline_needs_redraw = false;
+      did_draw = true;
+    }
+
     // set caret position and flush screen if we did any drawing
-    if did_draw {
+    if did_draw && rim.cmdline.is_prompting() {
+      let screen::Rect(cmdline_position, screen::Size(_, cols)) =
+        rim.cmdline_rect;
+      let column = rim.cmdline.caret_column(cols as usize).unwrap_or(0);
+      screen.set_cursor_position(cmdline_position +
+                                 screen::Cell(0, column as u16));
+      screen.flush();
+    }
+    else if did_draw {
       rim.windows.get(&rim.focus).map(|win|
         rim.buffers.get(&win.buf_id).map(|buffer| {
           let screen::Rect(win_position, _) = win.rect;
           screen.set_cursor_position(win_position +
-            win.view().caret_position(*win.caret(), buffer)); })).
+            win.view().caret_position(*win.caret(), buffer, &win.options));
+          })).
       expect("Couldn't find focused window.");
       screen.flush();
     }
@@ -690,6 +2366,7 @@ fn main() {
 #[cfg(not(test))]
 fn default_mode() -> command::Mode {
   let mut mode = command::Mode::new();
+  mode.accepts_count = true;
   mode.keychain.bind(&[Key::Unicode{codepoint: 'w', mods: keymap::MOD_CTRL},
                        Key::Unicode{codepoint: 'h', mods: keymap::MOD_NONE}],
     Cmd::MoveFocus(frame::Direction::Left));
@@ -711,15 +2388,21 @@ fn default_mode() -> command::Mode {
   mode.keychain.bind(&[Key::Unicode{codepoint: 'w', mods: keymap::MOD_CTRL},
                        Key::Unicode{codepoint: 'c', mods: keymap::MOD_NONE}],
     Cmd::CloseWindow);
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'w', mods: keymap::MOD_CTRL},
+                       Key::Unicode{codepoint: 'w', mods: keymap::MOD_NONE}],
+    Cmd::ShiftFocus(frame::WindowOrder::NextWindow));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'w', mods: keymap::MOD_CTRL},
+                       Key::Unicode{codepoint: 'W', mods: keymap::MOD_NONE}],
+    Cmd::ShiftFocus(frame::WindowOrder::PreviousWindow));
   mode.keychain.bind(&[Key::Unicode{codepoint: 'w', mods: keymap::MOD_CTRL},
                        Key::Unicode{codepoint: '=', mods: keymap::MOD_NONE}],
     Cmd::ResetLayout);
   mode.keychain.bind(&[Key::Unicode{codepoint: 'w', mods: keymap::MOD_CTRL},
                        Key::Unicode{codepoint: 'q', mods: keymap::MOD_CTRL}],
-    Cmd::QuitWindow);
+    Cmd::QuitWindow(false));
   mode.keychain.bind(&[Key::Unicode{codepoint: 'w', mods: keymap::MOD_CTRL},
                        Key::Unicode{codepoint: 'q', mods: keymap::MOD_NONE}],
-    Cmd::QuitWindow);
+    Cmd::QuitWindow(false));
   mode.keychain.bind(&[Key::Sym{sym: KeySym::Left, mods: keymap::MOD_NONE}],
     Cmd::WinCmd(WinCmd::MoveCaret(caret::Adjustment::CharPrev)));
   mode.keychain.bind(&[Key::Sym{sym: KeySym::Right, mods: keymap::MOD_NONE}],
@@ -736,39 +2419,30 @@ fn default_mode() -> command::Mode {
     Cmd::WinCmd(WinCmd::PageUp));
   mode.keychain.bind(&[Key::Sym{sym: KeySym::Pagedown, mods: keymap::MOD_NONE}],
     Cmd::WinCmd(WinCmd::PageDown));
-  // for testing purposes
-  mode.keychain.bind(&[Key::Unicode{codepoint: 'y', mods: keymap::MOD_NONE}],
-    Cmd::GrowWindow(frame::Orientation::Horizontal));
-  mode.keychain.bind(&[Key::Unicode{codepoint: 'y', mods: keymap::MOD_CTRL}],
-    Cmd::ShrinkWindow(frame::Orientation::Horizontal));
-  mode.keychain.bind(&[Key::Unicode{codepoint: 'u', mods: keymap::MOD_NONE}],
+  mode.keychain.bind(&[Key::Unicode{codepoint: ':', mods: keymap::MOD_NONE}],
+    Cmd::EnterCmdLineMode);
+  mode.keychain.bind(&[Key::Unicode{codepoint: '/', mods: keymap::MOD_NONE}],
+    Cmd::EnterSearchMode(false));
+  mode.keychain.bind(&[Key::Unicode{codepoint: '?', mods: keymap::MOD_NONE}],
+    Cmd::EnterSearchMode(true));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'w', mods: keymap::MOD_CTRL},
+                       Key::Unicode{codepoint: '+', mods: keymap::MOD_NONE}],
     Cmd::GrowWindow(frame::Orientation::Vertical));
-  mode.keychain.bind(&[Key::Unicode{codepoint: 'u', mods: keymap::MOD_CTRL}],
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'w', mods: keymap::MOD_CTRL},
+                       Key::Unicode{codepoint: '-', mods: keymap::MOD_NONE}],
     Cmd::ShrinkWindow(frame::Orientation::Vertical));
-  mode.keychain.bind(&[Key::Unicode{codepoint: 'n', mods: keymap::MOD_NONE}],
-    Cmd::ShiftFocus(frame::WindowOrder::NextWindow));
-  mode.keychain.bind(&[Key::Unicode{codepoint: 'N', mods: keymap::MOD_NONE}],
-    Cmd::ShiftFocus(frame::WindowOrder::PreviousWindow));
-  // for convenience until a proper command line is implemented
-  mode.keychain.bind(&[Key::Unicode{codepoint: ':', mods: keymap::MOD_NONE},
-                       Key::Unicode{codepoint: 'q', mods: keymap::MOD_NONE},
-                       Key::Unicode{codepoint: 'a', mods: keymap::MOD_NONE},
-                       Key::Sym{sym: KeySym::Enter, mods: keymap::MOD_NONE}],
-    Cmd::Quit);
-  mode.keychain.bind(&[Key::Unicode{codepoint: ':', mods: keymap::MOD_NONE},
-                       Key::Unicode{codepoint: 'q', mods: keymap::MOD_NONE},
-                       Key::Sym{sym: KeySym::Enter, mods: keymap::MOD_NONE}],
-    Cmd::QuitWindow);
-  mode.keychain.bind(&[Key::Unicode{codepoint: ':', mods: keymap::MOD_NONE},
-                       Key::Unicode{codepoint: 'w', mods: keymap::MOD_NONE},
-                       Key::Sym{sym: KeySym::Enter, mods: keymap::MOD_NONE}],
-    Cmd::WinCmd(WinCmd::SaveBuffer));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'w', mods: keymap::MOD_CTRL},
+                       Key::Unicode{codepoint: '>', mods: keymap::MOD_NONE}],
+    Cmd::GrowWindow(frame::Orientation::Horizontal));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'w', mods: keymap::MOD_CTRL},
+                       Key::Unicode{codepoint: '<', mods: keymap::MOD_NONE}],
+    Cmd::ShrinkWindow(frame::Orientation::Horizontal));
   return mode;
 }
 
+// binds the keys for caret movement, used as motions by operators as well
 #[cfg(not(test))]
-fn default_normal_mode() -> command::Mode {
-  let mut mode = command::Mode::new();
+fn bind_motions(mode: &mut command::Mode) {
   mode.keychain.bind(&[Key::Unicode{codepoint: 'h', mods: keymap::MOD_NONE}],
     Cmd::WinCmd(WinCmd::MoveCaret(caret::Adjustment::CharPrev)));
   mode.keychain.bind(&[Key::Unicode{codepoint: 'l', mods: keymap::MOD_NONE}],
@@ -777,20 +2451,18 @@ fn default_normal_mode() -> command::Mode {
     Cmd::WinCmd(WinCmd::MoveCaret(caret::Adjustment::LineUp)));
   mode.keychain.bind(&[Key::Unicode{codepoint: 'j', mods: keymap::MOD_NONE}],
     Cmd::WinCmd(WinCmd::MoveCaret(caret::Adjustment::LineDown)));
-  mode.keychain.bind(&[Key::Sym{sym: KeySym::Insert, mods: keymap::MOD_NONE}],
-    Cmd::WinCmd(WinCmd::EnterInsertMode));
-  mode.keychain.bind(&[Key::Unicode{codepoint: 'i', mods: keymap::MOD_NONE}],
-    Cmd::WinCmd(WinCmd::EnterInsertMode));
-  mode.keychain.bind(&[Key::Unicode{codepoint: 'I', mods: keymap::MOD_NONE}],
-    Cmd::WinCmd(WinCmd::EnterInsertModeStartOfLine));
-  mode.keychain.bind(&[Key::Unicode{codepoint: 'a', mods: keymap::MOD_NONE}],
-    Cmd::WinCmd(WinCmd::EnterInsertModeAppend));
-  mode.keychain.bind(&[Key::Unicode{codepoint: 'A', mods: keymap::MOD_NONE}],
-    Cmd::WinCmd(WinCmd::EnterInsertModeAppendEndOfLine));
-  mode.keychain.bind(&[Key::Unicode{codepoint: 'o', mods: keymap::MOD_NONE}],
-    Cmd::WinCmd(WinCmd::EnterInsertModeNextLine));
-  mode.keychain.bind(&[Key::Unicode{codepoint: 'O', mods: keymap::MOD_NONE}],
-    Cmd::WinCmd(WinCmd::EnterInsertModePreviousLine));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'g', mods: keymap::MOD_NONE},
+                       Key::Unicode{codepoint: 'k', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::MoveCaretByDisplayLine(false)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'g', mods: keymap::MOD_NONE},
+                       Key::Unicode{codepoint: 'j', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::MoveCaretByDisplayLine(true)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'g', mods: keymap::MOD_NONE},
+                       Key::Sym{sym: KeySym::Up, mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::MoveCaretByDisplayLine(false)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'g', mods: keymap::MOD_NONE},
+                       Key::Sym{sym: KeySym::Down, mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::MoveCaretByDisplayLine(true)));
   mode.keychain.bind(&[Key::Unicode{codepoint: 'g', mods: keymap::MOD_NONE},
                        Key::Unicode{codepoint: 'g', mods: keymap::MOD_NONE}],
     Cmd::WinCmd(WinCmd::MoveCaret(caret::Adjustment::FirstLine)));
@@ -809,6 +2481,47 @@ fn default_normal_mode() -> command::Mode {
     Cmd::WinCmd(WinCmd::MoveCaret(caret::Adjustment::StartOfLine)));
   mode.keychain.bind(&[Key::Unicode{codepoint: '$', mods: keymap::MOD_NONE}],
     Cmd::WinCmd(WinCmd::MoveCaret(caret::Adjustment::EndOfLine)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'w', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::MoveCaret(caret::Adjustment::WordNext(false))));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'b', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::MoveCaret(caret::Adjustment::WordPrev(false))));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'e', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::MoveCaret(caret::Adjustment::WordEnd(false))));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'W', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::MoveCaret(caret::Adjustment::WordNext(true))));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'B', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::MoveCaret(caret::Adjustment::WordPrev(true))));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'E', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::MoveCaret(caret::Adjustment::WordEnd(true))));
+  mode.keychain.bind(&[Key::Unicode{codepoint: '}', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::MoveCaret(caret::Adjustment::ParagraphNext)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: '{', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::MoveCaret(caret::Adjustment::ParagraphPrev)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: ')', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::MoveCaret(caret::Adjustment::SentenceNext)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: '(', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::MoveCaret(caret::Adjustment::SentencePrev)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'f', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::PendCharSearch(caret::CharSearch::Find)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'F', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::PendCharSearch(caret::CharSearch::FindBackward)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 't', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::PendCharSearch(caret::CharSearch::Till)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'T', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::PendCharSearch(caret::CharSearch::TillBackward)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: ';', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::RepeatCharSearch(false)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: ',', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::RepeatCharSearch(true)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'n', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::SearchNext(false)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'N', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::SearchNext(true)));
+}
+
+// binds the keys for scrolling shared by normal and visual mode
+#[cfg(not(test))]
+fn bind_scrolling(mode: &mut command::Mode) {
   mode.keychain.bind(&[Key::Unicode{codepoint: 'b', mods: keymap::MOD_CTRL}],
     Cmd::WinCmd(WinCmd::PageUp));
   mode.keychain.bind(&[Key::Unicode{codepoint: 'f', mods: keymap::MOD_CTRL}],
@@ -817,32 +2530,87 @@ fn default_normal_mode() -> command::Mode {
     Cmd::WinCmd(WinCmd::HalfPageUp));
   mode.keychain.bind(&[Key::Unicode{codepoint: 'd', mods: keymap::MOD_CTRL}],
     Cmd::WinCmd(WinCmd::HalfPageDown));
+}
+
+#[cfg(not(test))]
+fn default_normal_mode() -> command::Mode {
+  let mut mode = command::Mode::new();
+  mode.accepts_count = true;
+  bind_motions(&mut mode);
+  bind_scrolling(&mut mode);
+  mode.keychain.bind(&[Key::Sym{sym: KeySym::Insert, mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::EnterInsertMode));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'i', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::EnterInsertMode));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'I', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::EnterInsertModeStartOfLine));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'a', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::EnterInsertModeAppend));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'A', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::EnterInsertModeAppendEndOfLine));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'o', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::EnterInsertModeNextLine));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'O', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::EnterInsertModePreviousLine));
   mode.keychain.bind(&[Key::Sym{sym: KeySym::Delete, mods: keymap::MOD_NONE}],
     Cmd::WinCmd(WinCmd::DeleteOnLine));
   mode.keychain.bind(&[Key::Unicode{codepoint: 'x', mods: keymap::MOD_NONE}],
     Cmd::WinCmd(WinCmd::DeleteOnLine));
   mode.keychain.bind(&[Key::Unicode{codepoint: 'X', mods: keymap::MOD_NONE}],
     Cmd::WinCmd(WinCmd::BackspaceOnLine));
-  mode.keychain.bind(&[Key::Unicode{codepoint: 'd', mods: keymap::MOD_NONE},
-                       Key::Unicode{codepoint: 'd', mods: keymap::MOD_NONE}],
-    Cmd::WinCmd(WinCmd::DeleteLine));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'd', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::PendOperator(Operator::Delete)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'c', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::PendOperator(Operator::Change)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'y', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::PendOperator(Operator::Yank)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: '>', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::PendOperator(Operator::Indent)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: '<', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::PendOperator(Operator::Unindent)));
   mode.keychain.bind(&[Key::Unicode{codepoint: 'D', mods: keymap::MOD_NONE}],
-    Cmd::WinCmd(WinCmd::DeleteRestOfLine));
+    Cmd::WinCmd(WinCmd::Operate(Operator::Delete,
+                                caret::Adjustment::EndOfLine)));
   mode.keychain.bind(&[Key::Unicode{codepoint: 'C', mods: keymap::MOD_NONE}],
-    Cmd::WinCmd(WinCmd::ChangeRestOfLine));
+    Cmd::WinCmd(WinCmd::Operate(Operator::Change,
+                                caret::Adjustment::EndOfLine)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'Y', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::OperateOnLines(Operator::Yank)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: '"', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::PendRegister(RegisterUse::Name)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'p', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::Put(false)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'P', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::Put(true)));
   mode.keychain.bind(&[Key::Unicode{codepoint: 'r', mods: keymap::MOD_NONE}],
     Cmd::WinCmd(WinCmd::EnterReplaceMode(false)));
   mode.keychain.bind(&[Key::Unicode{codepoint: 'R', mods: keymap::MOD_NONE}],
     Cmd::WinCmd(WinCmd::EnterReplaceMode(true)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'v', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::EnterVisualMode(SelectionKind::Char)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'V', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::EnterVisualMode(SelectionKind::Line)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'v', mods: keymap::MOD_CTRL}],
+    Cmd::WinCmd(WinCmd::EnterVisualMode(SelectionKind::Block)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'u', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::Undo));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'r', mods: keymap::MOD_CTRL}],
+    Cmd::WinCmd(WinCmd::Redo));
+  mode.keychain.bind(&[Key::Unicode{codepoint: '.', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::RepeatChange));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'q', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::PendRegister(RegisterUse::Record)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: '@', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::PendRegister(RegisterUse::Execute)));
   // for testing purposes
   mode.keychain.bind(&[Key::Fn{num: 1, mods: keymap::MOD_NONE}],
-    Cmd::WinCmd(WinCmd::OpenBuffer(PathBuf::from("src/rim.rs"))));
+    Cmd::WinCmd(WinCmd::OpenBuffer(PathBuf::from("src/rim.rs"), false)));
   mode.keychain.bind(&[Key::Fn{num: 2, mods: keymap::MOD_NONE}],
-    Cmd::WinCmd(WinCmd::OpenBuffer(PathBuf::from("src/buffer.rs"))));
+    Cmd::WinCmd(WinCmd::OpenBuffer(PathBuf::from("src/buffer.rs"), false)));
   mode.keychain.bind(&[Key::Fn{num: 3, mods: keymap::MOD_NONE}],
-    Cmd::WinCmd(WinCmd::OpenBuffer(PathBuf::from("src/command.rs"))));
+    Cmd::WinCmd(WinCmd::OpenBuffer(PathBuf::from("src/command.rs"), false)));
   mode.keychain.bind(&[Key::Fn{num: 4, mods: keymap::MOD_NONE}],
-    Cmd::WinCmd(WinCmd::OpenBuffer(PathBuf::from("src/frame.rs"))));
+    Cmd::WinCmd(WinCmd::OpenBuffer(PathBuf::from("src/frame.rs"), false)));
   return mode;
 }
 
@@ -851,6 +2619,7 @@ fn key_to_string(key: Key) -> Option<String> {
   match key {
     Key::Unicode{codepoint, .. }      => Some(format!("{}", codepoint)),
     Key::Sym{sym: KeySym::Enter, .. } => Some("\n".to_string()),
+    Key::Sym{sym: KeySym::Tab, .. }   => Some("\t".to_string()),
     _                                 => None,
   }
 }
@@ -889,3 +2658,162 @@ fn replace_mode(replace_line: bool) -> command::Mode {
                   else            { replace_fallback };
   return mode;
 }
+
+// the mode of a character search, the next key being the character
+#[cfg(not(test))]
+fn char_search_mode() -> command::Mode {
+  let mut mode = command::Mode::new();
+  fn fallback(key: Key) -> Option<Cmd> {
+    match key {
+      Key::Unicode{codepoint, mods} if !mods.contains(keymap::MOD_CTRL) =>
+        Some(Cmd::WinCmd(WinCmd::SearchChar(Some(codepoint)))),
+      _                                                              =>
+        Some(Cmd::WinCmd(WinCmd::SearchChar(None))),
+    }
+  }
+  mode.fallback = fallback;
+  return mode;
+}
+
+// Asks whether to replace a match of a substitution, see command::Confirmation.
+#[cfg(not(test))]
+fn confirm_substitution_mode() -> command::Mode {
+  let mut mode = command::Mode::new();
+  let answers = [('y', Confirmation::Yes),
+                 ('n', Confirmation::No),
+                 ('a', Confirmation::All),
+                 ('l', Confirmation::Last),
+                 ('q', Confirmation::Quit)];
+  for &(codepoint, answer) in answers.iter() {
+    mode.keychain.bind(
+      &[Key::Unicode{codepoint: codepoint, mods: keymap::MOD_NONE}],
+      Cmd::WinCmd(WinCmd::ConfirmSubstitution(answer)));
+  }
+  mode.keychain.bind(&[Key::Sym{sym: KeySym::Escape, mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::ConfirmSubstitution(Confirmation::Quit)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'c', mods: keymap::MOD_CTRL}],
+    Cmd::WinCmd(WinCmd::ConfirmSubstitution(Confirmation::Quit)));
+  return mode;
+}
+
+// the mode of naming a register, the next key being the name
+#[cfg(not(test))]
+fn register_mode() -> command::Mode {
+  let mut mode = command::Mode::new();
+  fn fallback(key: Key) -> Option<Cmd> {
+    match key {
+      Key::Unicode{codepoint, mods} if !mods.contains(keymap::MOD_CTRL) =>
+        Some(Cmd::WinCmd(WinCmd::SelectRegister(Some(codepoint)))),
+      _                                                              =>
+        Some(Cmd::WinCmd(WinCmd::SelectRegister(None))),
+    }
+  }
+  mode.fallback = fallback;
+  return mode;
+}
+
+#[cfg(not(test))]
+fn visual_mode() -> command::Mode {
+  let mut mode = command::Mode::new();
+  mode.accepts_count = true;
+  bind_motions(&mut mode);
+  bind_scrolling(&mut mode);
+  mode.keychain.bind(&[Key::Sym{sym: KeySym::Escape, mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::EnterNormalMode));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'c', mods: keymap::MOD_CTRL}],
+    Cmd::WinCmd(WinCmd::EnterNormalMode));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'v', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::EnterVisualMode(SelectionKind::Char)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'V', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::EnterVisualMode(SelectionKind::Line)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'v', mods: keymap::MOD_CTRL}],
+    Cmd::WinCmd(WinCmd::EnterVisualMode(SelectionKind::Block)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'o', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::SwapSelectionEnds));
+  mode.keychain.bind(&[Key::Unicode{codepoint: '"', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::PendRegister(RegisterUse::Name)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'd', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::OperateOnSelection(Operator::Delete)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'x', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::OperateOnSelection(Operator::Delete)));
+  mode.keychain.bind(&[Key::Sym{sym: KeySym::Delete, mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::OperateOnSelection(Operator::Delete)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'c', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::OperateOnSelection(Operator::Change)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 's', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::OperateOnSelection(Operator::Change)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'y', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::OperateOnSelection(Operator::Yank)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: '>', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::OperateOnSelection(Operator::Indent)));
+  mode.keychain.bind(&[Key::Unicode{codepoint: '<', mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::OperateOnSelection(Operator::Unindent)));
+  return mode;
+}
+
+// Follows an operator, which is applied by a motion or, when its key is
+// repeated, to whole lines.
+#[cfg(not(test))]
+fn operator_pending_mode(operator: Operator) -> command::Mode {
+  let mut mode = command::Mode::new();
+  mode.accepts_count = true;
+  bind_motions(&mut mode);
+  mode.keychain.bind(&[Key::Sym{sym: KeySym::Escape, mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::EnterNormalMode));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'c', mods: keymap::MOD_CTRL}],
+    Cmd::WinCmd(WinCmd::EnterNormalMode));
+  let codepoint = match operator {
+    Operator::Delete   => 'd',
+    Operator::Change   => 'c',
+    Operator::Yank     => 'y',
+    Operator::Indent   => '>',
+    Operator::Unindent => '<',
+  };
+  mode.keychain.bind(
+    &[Key::Unicode{codepoint: codepoint, mods: keymap::MOD_NONE}],
+    Cmd::WinCmd(WinCmd::OperateOnLines(operator)));
+  return mode;
+}
+
+#[cfg(not(test))]
+fn cmdline_mode() -> command::Mode {
+  let mut mode = command::Mode::new();
+  mode.keychain.bind(&[Key::Sym{sym: KeySym::Escape, mods: keymap::MOD_NONE}],
+    Cmd::CmdLine(CmdLineCmd::Cancel));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'c', mods: keymap::MOD_CTRL}],
+    Cmd::CmdLine(CmdLineCmd::Cancel));
+  mode.keychain.bind(&[Key::Sym{sym: KeySym::Enter, mods: keymap::MOD_NONE}],
+    Cmd::CmdLine(CmdLineCmd::Execute));
+  mode.keychain.bind(
+    &[Key::Sym{sym: KeySym::Backspace, mods: keymap::MOD_NONE}],
+    Cmd::CmdLine(CmdLineCmd::Backspace));
+  mode.keychain.bind(&[Key::Sym{sym: KeySym::Del, mods: keymap::MOD_NONE}],
+    Cmd::CmdLine(CmdLineCmd::Backspace));
+  mode.keychain.bind(&[Key::Sym{sym: KeySym::Delete, mods: keymap::MOD_NONE}],
+    Cmd::CmdLine(CmdLineCmd::Delete));
+  mode.keychain.bind(&[Key::Sym{sym: KeySym::Left, mods: keymap::MOD_NONE}],
+    Cmd::CmdLine(CmdLineCmd::CaretLeft));
+  mode.keychain.bind(&[Key::Sym{sym: KeySym::Right, mods: keymap::MOD_NONE}],
+    Cmd::CmdLine(CmdLineCmd::CaretRight));
+  mode.keychain.bind(&[Key::Sym{sym: KeySym::Home, mods: keymap::MOD_NONE}],
+    Cmd::CmdLine(CmdLineCmd::CaretStart));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'b', mods: keymap::MOD_CTRL}],
+    Cmd::CmdLine(CmdLineCmd::CaretStart));
+  mode.keychain.bind(&[Key::Sym{sym: KeySym::End, mods: keymap::MOD_NONE}],
+    Cmd::CmdLine(CmdLineCmd::CaretEnd));
+  mode.keychain.bind(&[Key::Unicode{codepoint: 'e', mods: keymap::MOD_CTRL}],
+    Cmd::CmdLine(CmdLineCmd::CaretEnd));
+  mode.keychain.bind(&[Key::Sym{sym: KeySym::Up, mods: keymap::MOD_NONE}],
+    Cmd::CmdLine(CmdLineCmd::HistoryPrevious));
+  mode.keychain.bind(&[Key::Sym{sym: KeySym::Down, mods: keymap::MOD_NONE}],
+    Cmd::CmdLine(CmdLineCmd::HistoryNext));
+  fn fallback(key: Key) -> Option<Cmd> {
+    match key {
+      Key::Unicode{codepoint, mods} if !mods.contains(keymap::MOD_CTRL) =>
+        Some(Cmd::CmdLine(CmdLineCmd::Insert(format!("{}", codepoint)))),
+      _                                                              => None,
+    }
+  }
+  mode.fallback = fallback;
+  return mode;
+}
@@ -11,11 +11,15 @@ extern crate unicode_width;
 
 use std::cmp;
 #[cfg(not(test))]
+use std::env;
+#[cfg(not(test))]
 use std::iter;
 use std::ops::{Add, Sub};
 
 #[cfg(not(test))]
 use self::unicode_width::UnicodeWidthChar as CharWidth;
+#[cfg(not(test))]
+use rustc_serialize::base64::{ToBase64, STANDARD};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Size(pub u16, pub u16);
@@ -121,6 +125,7 @@ pub struct Screen {
 #[cfg(not(test))]
 impl Drop for Screen {
   fn drop(&mut self) {
+    self.terminal.reset_style();
     self.terminal.clear();
     self.terminal.show_cursor();
     self.terminal.disable_altscreen();
@@ -161,12 +166,11 @@ impl Screen {
     self.buffer.clear();
   }
 
-  pub fn put(&mut self, position: Cell, character: char, fg: Color, bg: Color) {
+  pub fn put(&mut self, position: Cell, character: char, style: Style) {
     position.within(self.size).map(|Cell(row, col)| {
-      if self.buffer.update(position, character, fg, bg) {
+      if self.buffer.update(position, character, style) {
         self.terminal.set_cursor_position(row, col);
-        self.terminal.set_fg(fg);
-        self.terminal.set_bg(bg);
+        self.terminal.set_style(style);
         self.terminal.put(character);
       }
     });
@@ -177,6 +181,12 @@ impl Screen {
       self.terminal.set_cursor_position(row, col));
   }
 
+  // Sets the selection named |selection|, c for the clipboard and p for the
+  // primary selection, through an OSC 52 escape sequence.
+  pub fn set_selection(&mut self, selection: char, text: &str) {
+    self.terminal.set_selection(selection, text);
+  }
+
   pub fn flush(&mut self) {
     self.terminal.flush();
   }
@@ -188,7 +198,7 @@ impl Screen {
  */
 #[cfg(not(test))]
 struct ScreenBuffer {
-  cells: Vec<Option<(char, Color, Color)>>,
+  cells: Vec<Option<(char, Style)>>,
   width: u16,
 }
 
@@ -220,9 +230,9 @@ impl ScreenBuffer {
 
   // a character taking up multiple screen columns is represented in the buffer
   // by one Some(character) followed by Nones in the additional cells it covers
-  fn update(&mut self, Cell(row, col): Cell, character: char, fg: Color,
-      bg: Color) -> bool {
-    let cell = Some((character, fg, bg));
+  fn update(&mut self, Cell(row, col): Cell, character: char, style: Style)
+      -> bool {
+    let cell = Some((character, style));
     let idx = (row as usize * self.width as usize) + col as usize;
     let buffer_size = self.cells.len();
     let nones = || (1..CharWidth::width(character).unwrap_or(1)).
@@ -239,25 +249,35 @@ impl ScreenBuffer {
 
 /*
  * Terminal is a simple wrapper that provides some helpful methods for common
- * ouput operations.
+ * ouput operations. It remembers the style last written such that only changes
+ * to it need to be written.
  */
 #[cfg(not(test))]
-struct Terminal {
+pub struct Terminal {
   terminal: Box<term::StdoutTerminal>,
+  color_support: ColorSupport,
+  style: Option<Style>,  // the style last written, if known
 }
 
 #[cfg(not(test))]
 impl Terminal {
   pub fn new() -> Option<Terminal> {
-    term::stdout().map(|terminal| Terminal { terminal: terminal })
+    term::stdout().map(|terminal| Terminal {
+      terminal: terminal,
+      color_support: ColorSupport::detect(),
+      style: None,
+    })
   }
 
-  pub fn set_fg(&mut self, fg: Color) {
-    self.terminal.fg(fg.to_term_color()).unwrap();
+  pub fn set_style(&mut self, style: Style) {
+    let sgr = sgr(self.style, style, self.color_support);
+    if !sgr.is_empty() { (write!(self.terminal, "{}", sgr)).unwrap(); }
+    self.style = Some(style);
   }
 
-  pub fn set_bg(&mut self, bg: Color) {
-    self.terminal.bg(bg.to_term_color()).unwrap();
+  pub fn reset_style(&mut self) {
+    (write!(self.terminal, "\x1B[0m")).unwrap();
+    self.style = None;
   }
 
   pub fn clear(&mut self) {
@@ -289,17 +309,24 @@ impl Terminal {
     (write!(self.terminal, "{}", character)).unwrap();
   }
 
+  pub fn set_selection(&mut self, selection: char, text: &str) {
+    (write!(self.terminal, "\x1B]52;{};{}\x07", selection,
+            text.as_bytes().to_base64(STANDARD))).unwrap();
+    self.flush();
+  }
+
   pub fn flush(&mut self) {
     self.terminal.flush().unwrap();
   }
 }
 
 /*
- * Color values for terminal output.
+ * Color values for terminal output. Besides the 16 ANSI colors there are the
+ * 256 indexed colors and 24-bit RGB colors, which are downgraded to the closest
+ * color the terminal supports when written.
  */
-#[allow(dead_code)]  // colors are not used much yet
-#[cfg(not(test))]
-#[derive(Clone, Copy, PartialEq)]
+#[allow(dead_code)]  // not every color is used
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Color {
   Black,
   Red,
@@ -317,31 +344,187 @@ pub enum Color {
   BrightMagenta,
   BrightCyan,
   BrightWhite,
+  Indexed(u8),
+  Rgb(u8, u8, u8),
 }
 
-#[allow(dead_code)]  // colors are not used much yet
-#[cfg(not(test))]
+// the ANSI colors in the order of their indices
+const ANSI_COLORS: [Color; 16] = [
+  Color::Black, Color::Red, Color::Green, Color::Yellow, Color::Blue,
+  Color::Magenta, Color::Cyan, Color::White, Color::BrightBlack,
+  Color::BrightRed, Color::BrightGreen, Color::BrightYellow, Color::BrightBlue,
+  Color::BrightMagenta, Color::BrightCyan, Color::BrightWhite,
+];
+
+// what the ANSI colors look like in xterm, as terminals don't agree on it
+const ANSI_RGB: [(u8, u8, u8); 16] = [
+  (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0), (0, 0, 238),
+  (205, 0, 205), (0, 205, 205), (229, 229, 229), (127, 127, 127),
+  (255, 0, 0), (0, 255, 0), (255, 255, 0), (92, 92, 255), (255, 0, 255),
+  (0, 255, 255), (255, 255, 255),
+];
+
+// the levels of each component of the 6x6x6 color cube of indices 16 to 231
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
 impl Color {
-  pub fn to_term_color(&self) -> term::color::Color {
-    match *self {
-      Color::Black         => term::color::BLACK,
-      Color::Red           => term::color::RED,
-      Color::Green         => term::color::GREEN,
-      Color::Yellow        => term::color::YELLOW,
-      Color::Blue          => term::color::BLUE,
-      Color::Magenta       => term::color::MAGENTA,
-      Color::Cyan          => term::color::CYAN,
-      Color::White         => term::color::WHITE,
-      Color::BrightBlack   => term::color::BRIGHT_BLACK,
-      Color::BrightRed     => term::color::BRIGHT_RED,
-      Color::BrightGreen   => term::color::BRIGHT_GREEN,
-      Color::BrightYellow  => term::color::BRIGHT_YELLOW,
-      Color::BrightBlue    => term::color::BRIGHT_BLUE,
-      Color::BrightMagenta => term::color::BRIGHT_MAGENTA,
-      Color::BrightCyan    => term::color::BRIGHT_CYAN,
-      Color::BrightWhite   => term::color::BRIGHT_WHITE,
+  // The closest color a terminal of |support| shows. Indexed colors below 16
+  // are the ANSI colors.
+  pub fn downgrade(self, support: ColorSupport) -> Color {
+    match (self, support) {
+      (Color::Indexed(index), _) if index < 16     =>
+        ANSI_COLORS[index as usize],
+      (Color::Rgb(..), ColorSupport::Indexed)      =>
+        self.closest((16..256).map(|index| Color::Indexed(index as u8))),
+      (Color::Indexed(_), ColorSupport::Basic) |
+      (Color::Rgb(..), ColorSupport::Basic)        =>
+        self.closest(ANSI_COLORS.iter().cloned()),
+      _                                            => self,
+    }
+  }
+
+  fn closest<I>(self, candidates: I) -> Color where I: Iterator<Item=Color> {
+    let (r, g, b) = self.rgb();
+    let distance = |color: &Color| {
+      let (cr, cg, cb) = color.rgb();
+      let square = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+      square(r, cr) + square(g, cg) + square(b, cb)
+    };
+    candidates.min_by_key(distance).unwrap_or(self)
+  }
+
+  fn rgb(self) -> (u8, u8, u8) {
+    match self {
+      Color::Rgb(r, g, b)                => (r, g, b),
+      Color::Indexed(index) if index < 16 => ANSI_RGB[index as usize],
+      Color::Indexed(index) if index < 232 => {
+        let cube = (index - 16) as usize;
+        let level = |i: usize| CUBE_LEVELS[i % 6];
+        (level(cube / 36), level(cube / 6), level(cube))
+      }
+      Color::Indexed(index)              => {
+        let level = 8 + 10 * (index - 232);  // a ramp of grays
+        (level, level, level)
+      }
+      ansi                               => ANSI_RGB[ansi.ansi_index()],
+    }
+  }
+
+  fn ansi_index(self) -> usize {
+    ANSI_COLORS.iter().position(|&color| color == self).
+    expect("Not an ANSI color.")
+  }
+
+  // the parameters of an SGR sequence setting the color, a downgraded one
+  fn sgr(self, background: bool) -> String {
+    let (base, extended) = if background { (40, 48) } else { (30, 38) };
+    match self {
+      Color::Indexed(index) => format!("{};5;{}", extended, index),
+      Color::Rgb(r, g, b)   => format!("{};2;{};{};{}", extended, r, g, b),
+      ansi                  => {
+        let index = ansi.ansi_index();
+        // the bright colors are found 60 past the others
+        format!("{}", base + index % 8 + if index < 8 { 0 } else { 60 })
+      }
+    }
+  }
+}
+
+/*
+ * The colors a terminal supports, the more the better.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorSupport {
+  Basic,  // the 16 ANSI colors
+  Indexed,  // 256 colors
+  TrueColor,  // 24-bit RGB colors
+}
+
+#[cfg(not(test))]
+impl ColorSupport {
+  // Tells from COLORTERM whether the terminal supports RGB colors, or else
+  // from the number of colors given by its terminfo entry.
+  fn detect() -> ColorSupport {
+    match env::var("COLORTERM") {
+      Ok(ref colorterm) if colorterm == "truecolor" || colorterm == "24bit" =>
+        return ColorSupport::TrueColor,
+      _                                                                     =>
+        (),
+    }
+    term::terminfo::TermInfo::from_env().ok().
+    and_then(|info| info.numbers.get("colors").cloned()).
+    map(|colors| if colors >= 256 { ColorSupport::Indexed }
+                 else             { ColorSupport::Basic }).
+    unwrap_or(ColorSupport::Basic)
+  }
+}
+
+bitflags! {
+  pub flags Attributes: u8 {
+    const ATTR_NONE          = 0,
+    const ATTR_BOLD          = 1 << 0,
+    const ATTR_ITALIC        = 1 << 1,
+    const ATTR_UNDERLINE     = 1 << 2,
+    const ATTR_UNDERCURL     = 1 << 3,
+    const ATTR_REVERSE       = 1 << 4,
+    const ATTR_STRIKETHROUGH = 1 << 5,
+  }
+}
+
+// the SGR parameters turning on each attribute
+const ATTRIBUTE_SGRS: &'static [(Attributes, &'static str)] = &[
+  (ATTR_BOLD,          "1"),
+  (ATTR_ITALIC,        "3"),
+  (ATTR_UNDERLINE,     "4"),
+  (ATTR_UNDERCURL,     "4:3"),
+  (ATTR_REVERSE,       "7"),
+  (ATTR_STRIKETHROUGH, "9"),
+];
+
+/*
+ * The style of a cell is its colors along with its attributes.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Style {
+  pub fg: Color,
+  pub bg: Color,
+  pub attributes: Attributes,
+}
+
+impl Style {
+  pub fn new(fg: Color, bg: Color) -> Style {
+    Style { fg: fg, bg: bg, attributes: ATTR_NONE }
+  }
+}
+
+// The SGR sequence changing the style written from |from|, if known, to |to|,
+// empty if there is nothing to change. Since turning an attribute off may turn
+// others off as well, the style is reset when any is. Terminals lacking RGB
+// colors are taken to lack undercurl as well, which is then an underline.
+fn sgr(from: Option<Style>, to: Style, support: ColorSupport) -> String {
+  let (fg, bg) = (to.fg.downgrade(support), to.bg.downgrade(support));
+  let mut params = Vec::new();
+  let (attributes, from_fg, from_bg) = match from {
+    Some(from) if (from.attributes - to.attributes).is_empty() =>
+      (from.attributes, Some(from.fg.downgrade(support)),
+       Some(from.bg.downgrade(support))),
+    _                                                         => {
+      params.push("0".to_string());
+      (ATTR_NONE, None, None)
+    }
+  };
+  for &(attribute, param) in ATTRIBUTE_SGRS {
+    if to.attributes.contains(attribute) && !attributes.contains(attribute) {
+      let param = if attribute == ATTR_UNDERCURL &&
+                     support != ColorSupport::TrueColor { "4" }
+                  else                                  { param };
+      params.push(param.to_string());
     }
   }
+  if from_fg != Some(fg) { params.push(fg.sgr(false)); }
+  if from_bg != Some(bg) { params.push(bg.sgr(true)); }
+  if params.is_empty() { String::new() }
+  else                 { format!("\x1B[{}m", params.join(";")) }
 }
 
 /*
@@ -376,3 +559,57 @@ mod term_size {
     }
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn downgrade() {
+    let red = Color::Rgb(255, 0, 0);
+    assert_eq!(red.downgrade(ColorSupport::TrueColor), red);
+    assert_eq!(red.downgrade(ColorSupport::Indexed), Color::Indexed(196));
+    assert_eq!(red.downgrade(ColorSupport::Basic), Color::BrightRed);
+    let gray = Color::Rgb(128, 128, 128);
+    assert_eq!(gray.downgrade(ColorSupport::Indexed), Color::Indexed(244));
+    assert_eq!(Color::Indexed(244).downgrade(ColorSupport::Basic),
+               Color::BrightBlack);
+    assert_eq!(Color::Indexed(244).downgrade(ColorSupport::Indexed),
+               Color::Indexed(244));
+    // the first 16 indexed colors are the ANSI colors
+    assert_eq!(Color::Indexed(3).downgrade(ColorSupport::TrueColor),
+               Color::Yellow);
+    assert_eq!(Color::Cyan.downgrade(ColorSupport::Basic), Color::Cyan);
+  }
+
+  #[test]
+  fn sgr_changes() {
+    let plain = Style::new(Color::White, Color::Black);
+    assert_eq!(sgr(None, plain, ColorSupport::TrueColor), "\x1B[0;37;40m");
+    assert_eq!(sgr(Some(plain), plain, ColorSupport::TrueColor), "");
+    let rgb = Style::new(Color::Rgb(1, 2, 3), Color::BrightBlue);
+    assert_eq!(sgr(Some(plain), rgb, ColorSupport::TrueColor),
+               "\x1B[38;2;1;2;3;104m");
+    assert_eq!(sgr(Some(plain), rgb, ColorSupport::Indexed),
+               "\x1B[38;5;16;104m");
+    // colors downgraded to the same make no change
+    let almost = Style::new(Color::Rgb(1, 2, 4), Color::BrightBlue);
+    assert_eq!(sgr(Some(rgb), almost, ColorSupport::Indexed), "");
+    // attributes are turned on one by one, but off all at once
+    let bold = Style { attributes: ATTR_BOLD, ..plain };
+    let bold_italic = Style { attributes: ATTR_BOLD | ATTR_ITALIC, ..plain };
+    assert_eq!(sgr(Some(plain), bold, ColorSupport::Basic), "\x1B[1m");
+    assert_eq!(sgr(Some(bold), bold_italic, ColorSupport::Basic), "\x1B[3m");
+    assert_eq!(sgr(Some(bold_italic), bold, ColorSupport::Basic),
+               "\x1B[0;1;37;40m");
+    let decorated = Style {
+      attributes: ATTR_UNDERCURL | ATTR_REVERSE | ATTR_STRIKETHROUGH |
+                  ATTR_UNDERLINE,
+      ..plain
+    };
+    assert_eq!(sgr(Some(plain), decorated, ColorSupport::TrueColor),
+               "\x1B[4;4:3;7;9m");
+    assert_eq!(sgr(Some(plain), decorated, ColorSupport::Indexed),
+               "\x1B[4;4;7;9m");
+  }
+}
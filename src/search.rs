@@ -0,0 +1,280 @@
+/*
+ * Copyright (c) 2015 Mathias Hällman
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+extern crate regex;
+
+use std::error;
+use std::fmt;
+use std::result;
+
+use self::regex::{Captures, Regex, RegexBuilder};
+
+use buffer::{Buffer, CharIterator};
+
+/*
+ * Search finds the matches of a regular expression in a buffer. The buffer is
+ * streamed a line at a time through the character iterators of its page tree,
+ * so matches never span lines. Matches are located by line and column, just
+ * like a caret.
+ */
+pub struct Search {
+  regex: Regex,
+}
+
+impl Search {
+  pub fn new(pattern: &str, ignore_case: bool) -> Result<Search> {
+    RegexBuilder::new(pattern).case_insensitive(ignore_case).build().
+    map(|regex| Search { regex: regex }).
+    map_err(|err| Error::InvalidPattern(format!("{}", err)))
+  }
+
+  pub fn pattern(&self) -> &str {
+    self.regex.as_str()
+  }
+
+  // the columns spanned by the matches on a line, the ends being exclusive
+  pub fn matches_on_line(&self, line: usize, buffer: &Buffer)
+      -> Vec<(usize, usize)> {
+    buffer.line_iter().from(line).next().
+    map(|chars| self.matches_in(chars)).unwrap_or(Vec::new())
+  }
+
+  // Finds the first match following a position, or preceding it if searching
+  // backward. The search wraps around the ends of the buffer, which is told
+  // along with the position of the match.
+  pub fn find(&self, line: usize, column: usize, backward: bool,
+              buffer: &Buffer) -> Option<((usize, usize), bool)> {
+    let num_lines = buffer.num_lines();
+    // the line of the position is searched both first and last, from and up to
+    // the position respectively
+    if !backward {
+      let lines = buffer.line_iter().from(line).zip(line..num_lines).
+        chain(buffer.line_iter().zip(0..line + 1));
+      for (i, (chars, l)) in lines.enumerate() {
+        let mut matches = self.matches_in(chars).into_iter();
+        let found = if i == 0 { matches.find(|&(start, _)| start > column) }
+                    else      { matches.next() };
+        if let Some((start, _)) = found {
+          return Some(((l, start), i >= num_lines - line));
+        }
+      }
+    }
+    else {
+      let lines = (0..line + 1).rev().chain((line..num_lines).rev());
+      for (i, l) in lines.enumerate() {
+        let matches = self.matches_on_line(l, buffer).into_iter();
+        let found = if i == 0 { matches.filter(|&(start, _)| start < column).
+                                last() }
+                    else      { matches.last() };
+        if let Some((start, _)) = found {
+          return Some(((l, start), i > line));
+        }
+      }
+    }
+    None
+  }
+
+  // The matches a substitution replaces on a line, found all at once such that
+  // the text replacing one match is never matched itself. Like in Vim, an
+  // empty match is passed over when right after another match, or at the end
+  // of the line following one. Unless |global|, only the first match is
+  // replaced.
+  pub fn substitutions_on_line(&self, line: usize, replacement: &str,
+                               global: bool, buffer: &Buffer)
+      -> Vec<(usize, usize, String)> {
+    let text = line_text(line, buffer);
+    let length = text.chars().count();
+    let mut substitutions = Vec::new();
+    let mut last_end = None;  // where the last match replaced ended
+    for (start, end, replacement) in self.replacements_in(&text, replacement) {
+      let passed_over = start == end && last_end.map(|last_end|
+        start == last_end || start >= length).unwrap_or(false);
+      if passed_over { continue }
+      last_end = Some(end);
+      substitutions.push((start, end, replacement));
+      if !global { break }
+    }
+    substitutions
+  }
+
+  // The matches in |text| along with |replacement| expanded for each. In the
+  // replacement & stands for the whole match and \1 through \9 for the groups
+  // of the pattern, while \n and \t are a newline and a tab. Any other
+  // character preceded by a backslash is taken as it is.
+  fn replacements_in(&self, text: &str, replacement: &str)
+      -> Vec<(usize, usize, String)> {
+    let column = |offset| text[..offset].chars().count();
+    self.regex.captures_iter(text).
+    map(|captures| {
+      let found = captures.get(0).expect("Captures lacked the whole match.");
+      (column(found.start()), column(found.end()),
+       expand(replacement, &captures)) }).
+    collect()
+  }
+
+  fn matches_in(&self, chars: CharIterator) -> Vec<(usize, usize)> {
+    let text: String = chars.take_while(|&c| c != '\n').collect();
+    // the regex finds byte offsets which are turned into columns
+    let column = |offset| text[..offset].chars().count();
+    self.regex.find_iter(&text).
+    map(|found| (column(found.start()), column(found.end()))).collect()
+  }
+}
+
+fn line_text(line: usize, buffer: &Buffer) -> String {
+  buffer.line_iter().from(line).next().
+  map(|chars| chars.take_while(|&c| c != '\n').collect()).
+  unwrap_or(String::new())
+}
+
+fn expand(replacement: &str, captures: &Captures) -> String {
+  let group = |index| captures.get(index).map(|group| group.as_str()).
+    unwrap_or("");
+  let mut expanded = String::new();
+  let mut chars = replacement.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      '&'  => expanded.push_str(group(0)),
+      '\\' => match chars.next() {
+        Some(c) if c.is_digit(10) =>
+          expanded.push_str(group(c.to_digit(10).unwrap() as usize)),
+        Some('n')                 => expanded.push('\n'),
+        Some('t')                 => expanded.push('\t'),
+        Some(c)                   => expanded.push(c),
+        None                      => expanded.push('\\'),
+      },
+      c    => expanded.push(c),
+    }
+  }
+  expanded
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+  InvalidPattern(String),
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Error::InvalidPattern(ref reason) =>
+        write!(f, "Invalid pattern: {}", reason),
+    }
+  }
+}
+
+impl error::Error for Error {
+  fn description(&self) -> &str {
+    match *self {
+      Error::InvalidPattern(_) => "Invalid pattern",
+    }
+  }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[cfg(test)]
+mod test {
+  use std::path::Path;
+
+  use buffer::Buffer;
+
+  use super::*;
+
+  #[test]
+  fn matches_on_line() {
+    let buffer = Buffer::open(&Path::new("tests/search/search.txt")).unwrap();
+    let search = Search::new("o", false).unwrap();
+    assert_eq!(search.matches_on_line(0, &buffer),
+               vec!((12, 13), (17, 18), (26, 27)));
+    assert_eq!(search.matches_on_line(2, &buffer), vec!());
+    // columns count characters rather than bytes
+    let search = Search::new("f.x", false).unwrap();
+    assert_eq!(search.matches_on_line(4, &buffer), vec!((3, 6)));
+    // matches spanning pages
+    let search = Search::new("jumps over", false).unwrap();
+    assert_eq!(search.matches_on_line(0, &buffer), vec!((20, 30)));
+    assert_eq!(search.pattern(), "jumps over");
+  }
+
+  #[test]
+  fn find() {
+    let buffer = Buffer::open(&Path::new("tests/search/search.txt")).unwrap();
+    let search = Search::new("fox", false).unwrap();
+    assert_eq!(search.find(0, 0, false, &buffer), Some(((0, 16), false)));
+    assert_eq!(search.find(0, 16, false, &buffer), Some(((3, 0), false)));
+    assert_eq!(search.find(3, 0, false, &buffer), Some(((4, 3), false)));
+    assert_eq!(search.find(4, 3, false, &buffer), Some(((0, 16), true)));
+    assert_eq!(search.find(3, 0, true, &buffer), Some(((0, 16), false)));
+    assert_eq!(search.find(0, 16, true, &buffer), Some(((4, 3), true)));
+    let search = Search::new("the", false).unwrap();
+    assert_eq!(search.find(2, 2, true, &buffer), Some(((1, 0), false)));
+    assert_eq!(search.find(2, 2, false, &buffer), Some(((0, 0), true)));
+    // a single match is found again after wrapping around
+    let search = Search::new("lazy", false).unwrap();
+    assert_eq!(search.find(1, 4, false, &buffer), Some(((1, 4), true)));
+    assert_eq!(search.find(1, 4, true, &buffer), Some(((1, 4), true)));
+    let search = Search::new("cat", false).unwrap();
+    assert_eq!(search.find(0, 0, false, &buffer), None);
+    assert_eq!(search.find(0, 0, true, &buffer), None);
+  }
+
+  #[test]
+  fn replacements() {
+    let buffer = Buffer::open(&Path::new("tests/search/search.txt")).unwrap();
+    let search = Search::new("(\\w)(o)", false).unwrap();
+    assert_eq!(search.substitutions_on_line(0, "[&|\\2\\1]", true, &buffer),
+               vec!((11, 13, "[ro|or]".to_string()),
+                    (16, 18, "[fo|of]".to_string())));
+    assert_eq!(search.substitutions_on_line(1, "\\&\\n\\\\\\", true,
+                                            &buffer),
+               vec!((9, 11, "&\n\\\\".to_string())));
+    // ignoring case
+    let search = Search::new("THE", true).unwrap();
+    assert_eq!(search.matches_on_line(2, &buffer), vec!((2, 5)));
+    assert_eq!(Search::new("THE", false).unwrap().matches_on_line(2, &buffer),
+               vec!());
+  }
+
+  #[test]
+  fn substitutions() {
+    // carries out the substitutions on the first line of a buffer holding
+    // |text|, the way :s does
+    let substitute = |text: &str, pattern: &str, replacement: &str,
+                      global: bool| {
+      let mut buffer = Buffer::new();
+      buffer.insert_at_offset(text.to_string(), 0);
+      let search = Search::new(pattern, false).unwrap();
+      let chars: Vec<char> = text.chars().collect();
+      let mut result = String::new();
+      let mut column = 0;
+      for (start, end, replacement) in
+          search.substitutions_on_line(0, replacement, global, &buffer) {
+        result.extend(chars[column..start].iter());
+        result.push_str(&replacement);
+        column = end;
+      }
+      result.extend(chars[column..].iter());
+      result
+    };
+    // :s/a/aa/g doesn't match the replacements
+    assert_eq!(substitute("aaa", "a", "aa", true), "aaaaaa");
+    assert_eq!(substitute("aaa", "a", "aa", false), "aaaa");
+    // :s/x*/-/g matches between characters but not past the last of them
+    assert_eq!(substitute("abc", "x*", "-", true), "-a-b-c");
+    assert_eq!(substitute("xa", "x*", "-", true), "-a");
+    assert_eq!(substitute("ax", "x*", "-", true), "-a-");
+    assert_eq!(substitute("", "x*", "-", true), "-");
+    assert_eq!(substitute("abc", "x*", "-", false), "-abc");
+  }
+
+  #[test]
+  fn invalid_pattern() {
+    assert!(Search::new("(fox", false).is_err());
+  }
+}
@@ -0,0 +1,690 @@
+/*
+ * Copyright (c) 2015 Mathias Hällman
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+extern crate regex;
+
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::rc::Rc;
+use std::result;
+
+use rustc_serialize::json::Json;
+
+use self::regex::{Locations, Regex};
+
+use buffer::LineIterator;
+use highlight::Group;
+
+// regions nested deeper than this aren't entered
+const MAX_DEPTH: usize = 64;
+// empty tokens found in a row before a character is skipped
+const MAX_STALLS: usize = 4;
+
+// TextMate scopes and the groups they are highlighted in. A scope belongs to
+// the longest of these it starts with, or to none.
+const SCOPES: &'static [(&'static str, Group)] = &[
+  ("comment",                   Group::Comment),
+  ("constant",                  Group::Constant),
+  ("constant.character.escape", Group::Special),
+  ("constant.numeric",          Group::Number),
+  ("entity.name.function",      Group::Function),
+  ("entity.name.type",          Group::Type),
+  ("invalid",                   Group::Error),
+  ("keyword",                   Group::Keyword),
+  ("keyword.operator",          Group::Operator),
+  ("meta.preprocessor",         Group::PreProc),
+  ("storage",                   Group::Keyword),
+  ("storage.type",              Group::Type),
+  ("string",                    Group::String),
+  ("support.function",          Group::Function),
+  ("support.type",              Group::Type),
+  ("variable",                  Group::Identifier),
+];
+
+// The group of the first of the blank separated |scopes| belonging to one.
+fn scope_group(scopes: &str) -> Option<Group> {
+  scopes.split_whitespace().filter_map(|scope|
+    SCOPES.iter().
+    filter(|&&(prefix, _)| scope == prefix ||
+      (scope.starts_with(prefix) && scope[prefix.len()..].starts_with('.'))).
+    max_by_key(|&&(prefix, _)| prefix.len()).
+    map(|&(_, group)| group)).
+  next()
+}
+
+/*
+ * A rule of a grammar either matches text on a line, or begins a region of
+ * lines within which its own rules apply until its end is matched. The end of
+ * a region may refer back to the groups matched by its beginning, in which
+ * case the end pattern is completed when the region is entered.
+ * Other rules are referred to by index into the rules of the grammar.
+ */
+enum Rule {
+  Match {
+    regex: Regex,
+    group: Option<Group>,
+    captures: Vec<Option<Group>>,
+  },
+  Region {
+    begin: Regex,
+    end: Regex,  // with any backreferences matching the empty string
+    end_pattern: Option<String>,  // the end pattern if it has backreferences
+    group: Option<Group>,
+    content_group: Option<Group>,
+    begin_captures: Vec<Option<Group>>,
+    end_captures: Vec<Option<Group>>,
+    patterns: Vec<usize>,
+  },
+  Patterns(Vec<usize>),  // the top level, or an entry of the repository
+}
+
+/*
+ * A grammar tells how to highlight the lines of a language. Grammars are read
+ * from TextMate grammars in JSON, the top level patterns being rule 0 followed
+ * by the entries of the repository. Rules with patterns using what the regex
+ * crate lacks, such as look-around, are left out. Lines are matched without
+ * their newline.
+ */
+pub struct Grammar {
+  file_types: Vec<String>,
+  rules: Vec<Rule>,
+}
+
+impl Grammar {
+  pub fn load(path: &Path) -> Result<Grammar> {
+    let mut json = String::new();
+    try!(File::open(path).and_then(|mut file| file.read_to_string(&mut json)).
+         map_err(|io_err| Error::IoError(io_err)));
+    Grammar::parse(&json)
+  }
+
+  pub fn parse(json: &str) -> Result<Grammar> {
+    let json = try!(Json::from_str(json).map_err(|err|
+      Error::InvalidGrammar(format!("{}", err))));
+    let patterns = try!(json.find("patterns").ok_or(
+      Error::InvalidGrammar("Lacks patterns".to_string())));
+    let no_repository = BTreeMap::new();
+    let repository = json.find("repository").and_then(|r| r.as_object()).
+      unwrap_or(&no_repository);
+    let mut compiler = Compiler {
+      names: repository.keys().enumerate().
+             map(|(i, name)| (name.as_str(), i + 1)).collect(),
+      rules: (0..repository.len() + 1).map(|_| Rule::Patterns(Vec::new())).
+             collect(),
+    };
+    for (i, entry) in repository.values().enumerate() {
+      if let Some(rule) = compiler.rule(entry) { compiler.rules[i + 1] = rule; }
+    }
+    let top_level = compiler.patterns(patterns);
+    compiler.rules[0] = Rule::Patterns(top_level);
+    let file_types = json.find("fileTypes").and_then(|types| types.as_array()).
+      map(|types| types.iter().filter_map(|t| t.as_string()).
+                  map(|t| t.to_string()).collect()).
+      unwrap_or(Vec::new());
+    Ok(Grammar { file_types: file_types, rules: compiler.rules })
+  }
+
+  // Whether the grammar is for the file at |path|, told by the extension or
+  // name of the file being among the file types of the grammar.
+  #[cfg_attr(test, allow(dead_code))]
+  pub fn is_for(&self, path: &Path) -> bool {
+    let extension = path.extension().and_then(|e| e.to_str());
+    let name = path.file_name().and_then(|n| n.to_str());
+    self.file_types.iter().
+    any(|t| Some(t.as_str()) == extension || Some(t.as_str()) == name)
+  }
+
+  // Finds the group of each character on a line, given the state at the start
+  // of the line which is left as the state at its end.
+  pub fn highlight_line(&self, line: &str, state: &mut State)
+      -> Vec<Option<Group>> {
+    let mut groups = vec![None; line.len()];  // by byte offset
+    let paint = |groups: &mut Vec<Option<Group>>, start: usize, end: usize,
+                 group: Option<Group>| {
+      for g in &mut groups[start..end] { *g = group; }
+    };
+    let mut position = 0;
+    let mut stalls = 0;
+    while position <= line.len() {
+      let outer = self.state_group(state);
+      let (token, locations) = match self.next_token(line, position, state) {
+        Some(found) => found,
+        None        => break,
+      };
+      let (start, end) = locations.pos(0).expect("Token lacked a match.");
+      paint(&mut groups, position, start, outer);
+      let captures = match (token, &self.rules[token.rule(state)]) {
+        (Token::Match(_), &Rule::Match { group, ref captures, .. }) => {
+          paint(&mut groups, start, end, group.or(outer));
+          captures
+        }
+        (Token::Begin(rule),
+         &Rule::Region { group, ref end_pattern, ref begin_captures, .. }) => {
+          paint(&mut groups, start, end, group.or(outer));
+          let completed = end_pattern.as_ref().and_then(|pattern|
+            Regex::new(&replace_backreferences(pattern, |i|
+              locations.pos(i).map(|(s, e)| &line[s..e]).unwrap_or(""))).ok());
+          state.0.push(Frame { rule: rule, end: completed });
+          begin_captures
+        }
+        (Token::End, &Rule::Region { group, ref end_captures, .. }) => {
+          state.0.pop();
+          paint(&mut groups, start, end, group.or(self.state_group(state)));
+          end_captures
+        }
+        _ => unreachable!(),
+      };
+      for (i, &group) in captures.iter().enumerate() {
+        if let (Some(group), Some((start, end))) = (group, locations.pos(i)) {
+          paint(&mut groups, start, end, Some(group));
+        }
+      }
+      // an empty token doesn't move on, so when too many are found in a row,
+      // e.g. a region beginning and ending at once over and over, a character
+      // is skipped
+      if end > position { position = end; stalls = 0; }
+      else if stalls < MAX_STALLS { stalls += 1; }
+      else {
+        let skipped = line[position..].chars().next().
+          map(|c| c.len_utf8()).unwrap_or(1);
+        let skipped_end = cmp::min(position + skipped, line.len());
+        paint(&mut groups, position, skipped_end, self.state_group(state));
+        position += skipped;
+        stalls = 0;
+      }
+    }
+    if position < line.len() {
+      let outer = self.state_group(state);
+      paint(&mut groups, position, line.len(), outer);
+    }
+    line.char_indices().map(|(i, _)| groups[i]).collect()
+  }
+
+  // Finds the token starting first at or after |position|, along with where
+  // it and its groups are. The end of the current region wins a tie, and
+  // otherwise the rule coming first.
+  fn next_token(&self, line: &str, position: usize, state: &State)
+      -> Option<(Token, Locations)> {
+    let mut found: Option<(Token, Locations)> = None;
+    {
+      let mut consider = |token, regex: &Regex| {
+        let found_start = found.as_ref().and_then(|&(_, ref locations)|
+          locations.pos(0)).map(|(start, _)| start);
+        if found_start == Some(position) { return }
+        let mut locations = regex.locations();
+        if let Some((start, _)) =
+            regex.read_captures_at(&mut locations, line, position) {
+          if found_start.map(|found| start < found).unwrap_or(true) {
+            found = Some((token, locations));
+          }
+        }
+      };
+      let patterns = match state.0.last() {
+        Some(frame) => match self.rules[frame.rule] {
+          Rule::Region { ref end, ref patterns, .. } => {
+            consider(Token::End, frame.end.as_ref().unwrap_or(end));
+            patterns
+          }
+          _ => unreachable!(),
+        },
+        None        => match self.rules[0] {
+          Rule::Patterns(ref patterns) => patterns,
+          _                            => unreachable!(),
+        },
+      };
+      let mut expanded = Vec::new();
+      self.expand(patterns, &mut expanded, &mut Vec::new());
+      for rule in expanded {
+        match self.rules[rule] {
+          Rule::Match { ref regex, .. }                           =>
+            consider(Token::Match(rule), regex),
+          Rule::Region { ref begin, .. } if state.0.len() < MAX_DEPTH =>
+            consider(Token::Begin(rule), begin),
+          _                                                       => (),
+        }
+      }
+    }
+    found
+  }
+
+  // Gathers the rules matching text among |patterns|, following the included
+  // lists of patterns unless already visited.
+  fn expand(&self, patterns: &[usize], expanded: &mut Vec<usize>,
+            visited: &mut Vec<usize>) {
+    for &rule in patterns {
+      match self.rules[rule] {
+        Rule::Patterns(ref included) => if !visited.contains(&rule) {
+          visited.push(rule);
+          self.expand(included, expanded, visited);
+        },
+        _                            => expanded.push(rule),
+      }
+    }
+  }
+
+  // the group of text within the regions of |state|, the innermost first
+  fn state_group(&self, state: &State) -> Option<Group> {
+    state.0.iter().rev().filter_map(|frame| match self.rules[frame.rule] {
+      Rule::Region { group, content_group, .. } => content_group.or(group),
+      _                                         => None,
+    }).next()
+  }
+}
+
+/*
+ * The state of highlighting at the end of a line is the regions it's within,
+ * the innermost last.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct State(Vec<Frame>);
+
+impl State {
+  pub fn new() -> State {
+    State(Vec::new())
+  }
+}
+
+#[derive(Clone, Debug)]
+struct Frame {
+  rule: usize,
+  end: Option<Regex>,  // the end with its backreferences replaced, if any
+}
+
+impl Frame {
+  fn end_pattern(&self) -> Option<&str> {
+    self.end.as_ref().map(Regex::as_str)
+  }
+}
+
+// frames are equal if their ends are made from the same patterns
+impl PartialEq for Frame {
+  fn eq(&self, other: &Frame) -> bool {
+    self.rule == other.rule && self.end_pattern() == other.end_pattern()
+  }
+}
+
+#[derive(Clone, Copy)]
+enum Token {
+  Match(usize),
+  Begin(usize),
+  End,
+}
+
+impl Token {
+  // the rule of the token, an end being that of the innermost region
+  fn rule(&self, state: &State) -> usize {
+    match *self {
+      Token::Match(rule) | Token::Begin(rule) => rule,
+      Token::End => state.0.last().expect("Ended no region.").rule,
+    }
+  }
+}
+
+/*
+ * Turns the JSON of a grammar into rules, the entries of the repository being
+ * named for includes to find them.
+ */
+struct Compiler<'a> {
+  names: HashMap<&'a str, usize>,
+  rules: Vec<Rule>,
+}
+
+impl<'a> Compiler<'a> {
+  fn patterns(&mut self, patterns: &Json) -> Vec<usize> {
+    let mut rules = Vec::new();
+    for pattern in patterns.as_array().map(|p| p.as_slice()).unwrap_or(&[]) {
+      match pattern.find("include").and_then(|i| i.as_string()) {
+        Some("$self") | Some("$base")          => rules.push(0),
+        Some(name) if name.starts_with('#') =>
+          rules.extend(self.names.get(&name[1..]).cloned()),
+        Some(_)                                => (),  // other grammars
+        None                                   =>
+          if let Some(rule) = self.rule(pattern) {
+            self.rules.push(rule);
+            rules.push(self.rules.len() - 1);
+          },
+      }
+    }
+    rules
+  }
+
+  fn rule(&mut self, rule: &Json) -> Option<Rule> {
+    let string = |key| rule.find(key).and_then(|value| value.as_string());
+    let groups_of = |key| captures(rule.find(key).or(rule.find("captures")));
+    let group = string("name").and_then(scope_group);
+    if let Some(pattern) = string("match") {
+      Regex::new(pattern).ok().map(|regex| Rule::Match {
+        regex: regex, group: group, captures: groups_of("captures"),
+      })
+    }
+    else if let (Some(begin), Some(end_pattern)) =
+        (string("begin"), string("end")) {
+      let without_backreferences = replace_backreferences(end_pattern, |_| "");
+      let has_backreferences = without_backreferences != end_pattern;
+      match (Regex::new(begin), Regex::new(&without_backreferences)) {
+        (Ok(begin), Ok(end)) => Some(Rule::Region {
+          begin: begin,
+          end: end,
+          end_pattern: if has_backreferences { Some(end_pattern.to_string()) }
+                       else                  { None },
+          group: group,
+          content_group: string("contentName").and_then(scope_group),
+          begin_captures: groups_of("beginCaptures"),
+          end_captures: groups_of("endCaptures"),
+          patterns: rule.find("patterns").
+                    map(|patterns| self.patterns(patterns)).
+                    unwrap_or(Vec::new()),
+        }),
+        _                    => None,
+      }
+    }
+    else {
+      rule.find("patterns").
+      map(|patterns| Rule::Patterns(self.patterns(patterns)))
+    }
+  }
+}
+
+// the groups of captures by index, such as {"1": {"name": "keyword"}}
+fn captures(captures: Option<&Json>) -> Vec<Option<Group>> {
+  let mut groups = Vec::new();
+  for (index, capture) in captures.and_then(|c| c.as_object()).iter().
+                          flat_map(|c| c.iter()) {
+    if let Ok(index) = index.parse::<usize>() {
+      if groups.len() <= index { groups.resize(index + 1, None); }
+      groups[index] =
+        capture.find("name").and_then(|n| n.as_string()).and_then(scope_group);
+    }
+  }
+  groups
+}
+
+// Replaces the backreferences \1 through \9 of |pattern| by the text |group|
+// gives for each, escaped. Other escapes are left as they are.
+fn replace_backreferences<'t, F>(pattern: &str, group: F) -> String
+    where F: Fn(usize) -> &'t str {
+  let mut replaced = String::new();
+  let mut chars = pattern.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' { replaced.push(c); continue; }
+    match chars.next() {
+      Some(c) if c.is_digit(10) && c != '0' => {
+        let index = c.to_digit(10).unwrap() as usize;
+        replaced.push_str(&regex::escape(group(index)));
+      }
+      Some(c)                               => {
+        replaced.push('\\');
+        replaced.push(c);
+      }
+      None                                  => replaced.push('\\'),
+    }
+  }
+  replaced
+}
+
+/*
+ * Highlighting keeps the groups of each line of a buffer along with the state
+ * at the end of the line. An edit invalidates the lines it touches, which are
+ * highlighted again along with the lines following them until the state at the
+ * end of a line is the same as it was before the edit. Past that line nothing
+ * could have changed.
+ */
+pub struct Highlighting {
+  grammar: Rc<Grammar>,
+  lines: Vec<HighlightedLine>,
+  valid: usize,  // lines before this are up to date
+  edited: usize,  // lines before this are highlighted again whatever the state
+}
+
+struct HighlightedLine {
+  groups: Vec<Option<Group>>,
+  end: State,
+}
+
+impl Highlighting {
+  pub fn new(grammar: Rc<Grammar>) -> Highlighting {
+    Highlighting { grammar: grammar, lines: Vec::new(), valid: 0, edited: 0 }
+  }
+
+  // the group of each character on |line|, if highlighted
+  pub fn groups_on_line(&self, line: usize) -> &[Option<Group>] {
+    self.lines.get(line).map(|line| line.groups.as_slice()).unwrap_or(&[])
+  }
+
+  // Invalidates the lines of an edit at |line| replacing |removed| newlines by
+  // |added| ones.
+  pub fn edit(&mut self, line: usize, removed: usize, added: usize) {
+    // the state at the end of the last line edited is kept for the line it
+    // ends up as, the lines before it being replaced
+    let start = cmp::min(line, self.lines.len());
+    let end = cmp::min(line + removed, self.lines.len());
+    self.lines.splice(start..end, (0..added).map(|_|
+      HighlightedLine { groups: Vec::new(), end: State::new() }));
+    if self.edited > line + removed {
+      self.edited = self.edited + added - removed;
+    }
+    self.edited = cmp::max(self.edited, line + added + 1);
+    self.valid = cmp::min(self.valid, line);
+  }
+
+  // Highlights the lines invalidated since last time, returning how many lines
+  // that took.
+  pub fn update(&mut self, lines: LineIterator) -> usize {
+    let first = self.valid;
+    let mut state = if first == 0 { State::new() }
+                    else          { self.lines[first - 1].end.clone() };
+    let mut highlighted = 0;
+    let mut converged = false;
+    for (line, chars) in (first..).zip(lines.from(first)) {
+      let text: String = chars.take_while(|&c| c != '\n').collect();
+      let groups = self.grammar.highlight_line(&text, &mut state);
+      let highlighted_line =
+        HighlightedLine { groups: groups, end: state.clone() };
+      highlighted += 1;
+      if line < self.lines.len() {
+        converged = line + 1 >= self.edited && self.lines[line].end == state;
+        self.lines[line] = highlighted_line;
+        if converged { break; }
+      }
+      else { self.lines.push(highlighted_line); }
+    }
+    if !converged { self.lines.truncate(first + highlighted); }
+    self.valid = self.lines.len();
+    self.edited = 0;
+    highlighted
+  }
+}
+
+#[derive(Debug)]
+pub enum Error {
+  IoError(io::Error),
+  InvalidGrammar(String),
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Error::IoError(ref err)           => write!(f, "{}", err),
+      Error::InvalidGrammar(ref reason) =>
+        write!(f, "Invalid grammar: {}", reason),
+    }
+  }
+}
+
+impl error::Error for Error {
+  fn description(&self) -> &str {
+    match *self {
+      Error::IoError(_)        => "IO error",
+      Error::InvalidGrammar(_) => "Invalid grammar",
+    }
+  }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[cfg(test)]
+mod test {
+  use std::path::Path;
+  use std::rc::Rc;
+
+  use buffer::Buffer;
+
+  use super::*;
+
+  fn grammar() -> Grammar {
+    Grammar::load(&Path::new("tests/syntax/toy.json")).unwrap()
+  }
+
+  // the groups of a line written a character each, blank for none
+  fn sketch(groups: &[Option<Group>]) -> String {
+    groups.iter().map(|group| match *group {
+      Some(Group::Comment)  => 'c',
+      Some(Group::Function) => 'f',
+      Some(Group::Keyword)  => 'k',
+      Some(Group::Number)   => 'n',
+      Some(Group::Special)  => 'e',
+      Some(Group::String)   => 's',
+      Some(_)               => '?',
+      None                  => ' ',
+    }).collect()
+  }
+
+  fn highlight(grammar: &Grammar, line: &str, state: &mut State) -> String {
+    sketch(&grammar.highlight_line(line, state))
+  }
+
+  #[test]
+  fn scopes() {
+    assert_eq!(scope_group("keyword.operator.arithmetic"),
+               Some(Group::Operator));
+    assert_eq!(scope_group("keyword.control"), Some(Group::Keyword));
+    assert_eq!(scope_group("constant.numeric"), Some(Group::Number));
+    assert_eq!(scope_group("meta.block string.quoted"), Some(Group::String));
+    assert_eq!(scope_group("keywords"), None);
+    assert_eq!(scope_group("entity.name"), None);
+  }
+
+  #[test]
+  fn highlight_lines() {
+    let grammar = grammar();
+    let mut state = State::new();
+    assert_eq!(highlight(&grammar, "fn main 42 // x", &mut state),
+                                   "kk ffff nn cccc");
+    assert_eq!(highlight(&grammar, "let a = \"b\\n\"", &mut state),
+                                   "kkk     ssees");
+    // regions span lines
+    assert_eq!(highlight(&grammar, "1 /* 2 /* 3", &mut state),
+                                   "n ccccccccc");
+    assert!(state != State::new());
+    assert_eq!(highlight(&grammar, "4 */ 5 */ 6", &mut state),
+                                   "ccccccccc n");
+    assert_eq!(state, State::new());
+    // the end of a region may refer back to its beginning
+    assert_eq!(highlight(&grammar, "r##\"a\"#b\"## 7", &mut state),
+                                   "sssssssssss n");
+    // characters are columns rather than bytes
+    assert_eq!(highlight(&grammar, "\"åäö\" 8", &mut state), "sssss n");
+  }
+
+  #[test]
+  fn file_types() {
+    let grammar = grammar();
+    assert!(grammar.is_for(&Path::new("dir/file.toy")));
+    assert!(grammar.is_for(&Path::new("Toyfile")));
+    assert!(!grammar.is_for(&Path::new("file.rs")));
+  }
+
+  #[test]
+  fn invalid_grammar() {
+    assert!(Grammar::parse("{").is_err());
+    assert!(Grammar::parse("{\"name\": \"none\"}").is_err());
+    assert!(Grammar::load(&Path::new("tests/syntax/missing.json")).is_err());
+  }
+
+  #[test]
+  fn incremental() {
+    let mut buffer = Buffer::new();
+    buffer.insert_at_line_column("a 1\nb 2\nc 3\nd 4\n".to_string(), 0, 0).
+      unwrap();
+    let mut highlighting = Highlighting::new(Rc::new(grammar()));
+    assert_eq!(highlighting.update(buffer.line_iter()), 5);
+    assert_eq!(sketch(highlighting.groups_on_line(2)), "  n");
+    // an edit not changing the state only highlights its line
+    buffer.insert_at_line_column("5".to_string(), 1, 2).unwrap();
+    highlighting.edit(1, 0, 0);
+    assert_eq!(highlighting.update(buffer.line_iter()), 1);
+    assert_eq!(sketch(highlighting.groups_on_line(1)), "  nn");
+    // beginning a region highlights until its end
+    buffer.insert_at_line_column("/*".to_string(), 0, 0).unwrap();
+    highlighting.edit(0, 0, 0);
+    buffer.insert_at_line_column("*/".to_string(), 2, 0).unwrap();
+    highlighting.edit(2, 0, 0);
+    assert_eq!(highlighting.update(buffer.line_iter()), 3);
+    assert_eq!(sketch(highlighting.groups_on_line(1)), "cccc");
+    assert_eq!(sketch(highlighting.groups_on_line(2)), "cc  n");
+    assert_eq!(sketch(highlighting.groups_on_line(3)), "  n");
+    // added lines are highlighted and lines following them kept
+    buffer.insert_at_line_column("\n7\n".to_string(), 3, 0).unwrap();
+    highlighting.edit(3, 0, 2);
+    assert_eq!(highlighting.update(buffer.line_iter()), 3);
+    assert_eq!(sketch(highlighting.groups_on_line(4)), "n");
+    assert_eq!(sketch(highlighting.groups_on_line(5)), "  n");
+    // removed lines are forgotten, the line left ending as the last one did
+    buffer.delete_range(0, 0, 3, 0).unwrap();
+    highlighting.edit(0, 3, 0);
+    assert_eq!(highlighting.update(buffer.line_iter()), 1);
+    assert_eq!(sketch(highlighting.groups_on_line(0)), "");
+    assert_eq!(sketch(highlighting.groups_on_line(1)), "n");
+    assert_eq!(sketch(highlighting.groups_on_line(2)), "  n");
+    assert_eq!(sketch(highlighting.groups_on_line(4)), "");
+    // the state is compared with the one the last line edited ended in, lines
+    // being added after it
+    let mut buffer = Buffer::new();
+    buffer.insert_at_line_column("/* a\nb\n*/\n".to_string(), 0, 0).unwrap();
+    let mut highlighting = Highlighting::new(Rc::new(grammar()));
+    highlighting.update(buffer.line_iter());
+    assert_eq!(sketch(highlighting.groups_on_line(1)), "c");
+    buffer.insert_at_line_column("*/\n".to_string(), 0, 4).unwrap();
+    highlighting.edit(0, 0, 1);
+    assert_eq!(highlighting.update(buffer.line_iter()), 4);
+    assert_eq!(sketch(highlighting.groups_on_line(2)), " ");
+    // or removed from it
+    let mut buffer = Buffer::new();
+    buffer.insert_at_line_column("a\n/* b\nc\n*/ 1\n".to_string(), 0, 0).
+      unwrap();
+    let mut highlighting = Highlighting::new(Rc::new(grammar()));
+    highlighting.update(buffer.line_iter());
+    assert_eq!(sketch(highlighting.groups_on_line(2)), "c");
+    buffer.delete_range(0, 1, 1, 4).unwrap();
+    highlighting.edit(0, 1, 0);
+    assert_eq!(highlighting.update(buffer.line_iter()), 3);
+    assert_eq!(sketch(highlighting.groups_on_line(1)), " ");
+    assert_eq!(sketch(highlighting.groups_on_line(2)), "   n");
+  }
+
+  #[test]
+  fn buffer_highlighting() {
+    let mut buffer = Buffer::new();
+    buffer.insert_at_line_column("1\n2\n".to_string(), 0, 0).unwrap();
+    buffer.set_grammar(Some(Rc::new(grammar())));
+    assert_eq!(sketch(buffer.highlights_on_line(1)), "n");
+    buffer.commit_changes();
+    buffer.insert_at_line_column("/*".to_string(), 0, 0).unwrap();
+    assert_eq!(sketch(buffer.highlights_on_line(1)), "c");
+    buffer.undo();
+    assert_eq!(sketch(buffer.highlights_on_line(1)), "n");
+    buffer.set_grammar(None);
+    assert_eq!(sketch(buffer.highlights_on_line(1)), "");
+  }
+}
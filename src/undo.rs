@@ -0,0 +1,192 @@
+/*
+ * Copyright (c) 2015 Mathias Hällman
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+use std::mem;
+
+/*
+ * A change is a single modification of a buffer's content, located by
+ * character offset. Deletions keep the deleted string around so that any change
+ * can be inverted.
+ */
+#[derive(Clone, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum Change {
+  Insert(usize, String),
+  Delete(usize, String),
+}
+
+impl Change {
+  pub fn inverted(&self) -> Change {
+    match *self {
+      Change::Insert(offset, ref string) =>
+        Change::Delete(offset, string.clone()),
+      Change::Delete(offset, ref string) =>
+        Change::Insert(offset, string.clone()),
+    }
+  }
+}
+
+/*
+ * UndoTree records the changes made to a buffer. Changes are first recorded as
+ * pending and then committed in groups, each group making up a state in the
+ * tree. Undo and redo move between states one group at a time. Making changes
+ * after undoing doesn't throw the undone states away, but rather starts a new
+ * branch in the tree. Redo follows the branch most recently visited.
+ * The root state is the buffer as it was when the tree was created, it holds no
+ * changes.
+ */
+pub struct UndoTree {
+  states: Vec<UndoState>,
+  current: usize,
+  pending: Vec<Change>,
+}
+
+struct UndoState {
+  parent: usize,
+  children: Vec<usize>,  // the most recently visited child is kept last
+  changes: Vec<Change>,
+}
+
+impl UndoTree {
+  pub fn new() -> UndoTree {
+    let root =
+      UndoState { parent: 0, children: Vec::new(), changes: Vec::new() };
+    UndoTree { states: vec!(root), current: 0, pending: Vec::new() }
+  }
+
+  pub fn record(&mut self, change: Change) {
+    self.pending.push(change);
+  }
+
+  pub fn has_pending(&self) -> bool {
+    !self.pending.is_empty()
+  }
+
+  // identifies the current state, as of the last commit
+  pub fn state(&self) -> usize {
+    self.current
+  }
+
+  // gathers the pending changes into a new state, if there are any
+  pub fn commit(&mut self) {
+    if self.pending.is_empty() { return }
+    let id = self.states.len();
+    let changes = mem::replace(&mut self.pending, Vec::new());
+    let parent = self.current;
+    let state =
+      UndoState { parent: parent, children: Vec::new(), changes: changes };
+    self.states.push(state);
+    self.states[parent].children.push(id);
+    self.current = id;
+  }
+
+  // Moves to the parent state and returns the changes which, applied in order,
+  // takes the buffer there. Pending changes are committed first.
+  pub fn undo(&mut self) -> Option<Vec<Change>> {
+    self.commit();
+    if self.current == 0 { return None }
+    let id = self.current;
+    let parent = self.states[id].parent;
+    let changes =
+      self.states[id].changes.iter().rev().map(|change| change.inverted()).
+      collect();
+    // remember this branch as the most recently visited one
+    self.states[parent].children.retain(|&child| child != id);
+    self.states[parent].children.push(id);
+    self.current = parent;
+    Some(changes)
+  }
+
+  // Moves to the most recently visited child state and returns the changes
+  // which, applied in order, takes the buffer there.
+  pub fn redo(&mut self) -> Option<Vec<Change>> {
+    self.commit();
+    self.states[self.current].children.last().map(|&child| child).
+    map(|child| {
+      self.current = child;
+      self.states[child].changes.clone() })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn insert(offset: usize, string: &str) -> Change {
+    Change::Insert(offset, string.to_string())
+  }
+
+  fn delete(offset: usize, string: &str) -> Change {
+    Change::Delete(offset, string.to_string())
+  }
+
+  #[test]
+  fn undo_redo_groups() {
+    let mut tree = UndoTree::new();
+    tree.record(insert(0, "ab"));
+    tree.record(insert(2, "cd"));
+    tree.commit();
+    tree.record(delete(1, "bc"));
+    // pending changes are committed on undo
+    assert_eq!(tree.undo(), Some(vec!(insert(1, "bc"))));
+    assert_eq!(tree.undo(), Some(vec!(delete(2, "cd"), delete(0, "ab"))));
+    assert_eq!(tree.undo(), None);
+    assert_eq!(tree.redo(), Some(vec!(insert(0, "ab"), insert(2, "cd"))));
+    assert_eq!(tree.redo(), Some(vec!(delete(1, "bc"))));
+    assert_eq!(tree.redo(), None);
+  }
+
+  #[test]
+  fn empty_commit() {
+    let mut tree = UndoTree::new();
+    tree.commit();
+    assert_eq!(tree.undo(), None);
+    tree.record(insert(0, "a"));
+    tree.commit();
+    tree.commit();
+    assert_eq!(tree.undo(), Some(vec!(delete(0, "a"))));
+    assert_eq!(tree.undo(), None);
+  }
+
+  #[test]
+  fn states() {
+    let mut tree = UndoTree::new();
+    let root = tree.state();
+    tree.record(insert(0, "a"));
+    assert!(tree.has_pending());
+    assert_eq!(tree.state(), root);
+    tree.commit();
+    assert!(!tree.has_pending());
+    let first = tree.state();
+    assert!(first != root);
+    tree.undo();
+    assert_eq!(tree.state(), root);
+    tree.redo();
+    assert_eq!(tree.state(), first);
+  }
+
+  #[test]
+  fn branches() {
+    let mut tree = UndoTree::new();
+    tree.record(insert(0, "a"));
+    tree.commit();
+    tree.undo();
+    // a new change starts a new branch, redo follows it
+    tree.record(insert(0, "b"));
+    tree.commit();
+    tree.undo();
+    assert_eq!(tree.redo(), Some(vec!(insert(0, "b"))));
+    // the old branch is still around
+    tree.undo();
+    tree.states[0].children.reverse();
+    assert_eq!(tree.redo(), Some(vec!(insert(0, "a"))));
+    assert_eq!(tree.undo(), Some(vec!(delete(0, "a"))));
+    // undoing from a branch makes it the one redo follows
+    assert_eq!(tree.redo(), Some(vec!(insert(0, "a"))));
+  }
+}
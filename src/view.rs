@@ -10,24 +10,41 @@ extern crate unicode_width;
 
 use std::cmp;
 
+#[cfg(not(test))]
 use self::unicode_width::UnicodeWidthChar as CharWidth;
 
 use buffer::Buffer;
 use caret;
-use caret::Caret;
+use caret::{Caret, Wrapping};
+#[cfg(not(test))]
+use caret::Selection;
+#[cfg(not(test))]
+use highlight::{Group, Highlights};
+use options::{Opt, Options};
 use screen;
 #[cfg(not(test))]
 use screen::Screen;
+#[cfg(not(test))]
+use search::Search;
 
 const MIN_VIEW_SIZE: u16 = 1;
+const MIN_NUMBER_WIDTH: usize = 3;  // digits, not counting the space after
 
 /*
  * View handles the presentation of a buffer.
  * Everything is measured in screen cell coordinates.
+ * With the wrap option set, lines are displayed in as many rows as it takes
+ * rather than being scrolled horizontally. A row of a line is then referred to
+ * by the line along with the number of the row within it, the view possibly
+ * starting at a row other than the first of its first line.
+ * Left of the text is a gutter holding a column for signs, as wide as the
+ * signcolumn option asks for, followed by line numbers if the number or
+ * relativenumber option is set.
  */
 #[derive(Clone, Copy)]
 pub struct View {
   scroll_line: usize,
+  scroll_row: usize,  // the first row of the first line shown, when wrapping
   scroll_column: usize,
   size: screen::Size,
 }
@@ -36,6 +53,7 @@ impl View {
   pub fn new() -> View {
     View {
       scroll_line: 0,
+      scroll_row: 0,
       scroll_column: 0,
       size: screen::Size(MIN_VIEW_SIZE, MIN_VIEW_SIZE),
     }
@@ -50,44 +68,213 @@ impl View {
   }
 
   // assumes caret is in view
-  pub fn caret_position(&self, caret: Caret, buffer: &Buffer) -> screen::Cell {
-    let caret_row = (caret.line() - self.scroll_line) as u16;
-    let caret_column = caret::buffer_to_screen_column(
-      caret.line(), caret.column(), buffer) - self.scroll_column;
-    screen::Cell(caret_row, caret_column as u16)
+  pub fn caret_position(&self, caret: Caret, buffer: &Buffer,
+                        options: &Options) -> screen::Cell {
+    let starts = self.rows_of_caret_line(caret, buffer, options);
+    let (line, row) = self.caret_row(caret, buffer, options);
+    let caret_row = self.rows_between((self.scroll_line, self.scroll_row),
+                                      (line, row), buffer, options);
+    let caret_column = self.gutter_width(buffer, options) +
+      caret::buffer_to_screen_column(line, caret.column(), buffer) -
+      caret::buffer_to_screen_column(line, starts[row], buffer) -
+      self.scroll_column;
+    screen::Cell(caret_row as u16, caret_column as u16)
+  }
+
+  // the width of the gutter, which always leaves a column for the text
+  fn gutter_width(&self, buffer: &Buffer, options: &Options) -> usize {
+    let screen::Size(_, cols) = self.size;
+    let number_width = self.number_width(buffer, options).
+      map(|width| width + 1).unwrap_or(0);
+    cmp::min(options.number(Opt::SignColumn) + number_width, cols as usize - 1)
+  }
+
+  // the digits line numbers are given room for, if they are shown
+  fn number_width(&self, buffer: &Buffer, options: &Options) -> Option<usize> {
+    if !options.bool(Opt::Number) && !options.bool(Opt::RelativeNumber) {
+      return None;
+    }
+    Some(cmp::max(buffer.num_lines().to_string().len(), MIN_NUMBER_WIDTH))
+  }
+
+  // the number of columns the text is displayed in, right of the gutter
+  fn text_width(&self, buffer: &Buffer, options: &Options) -> usize {
+    let screen::Size(_, cols) = self.size;
+    cols as usize - self.gutter_width(buffer, options)
   }
 
   pub fn set_scroll(&mut self, line: usize, column: usize) {
     self.scroll_line = line;
+    self.scroll_row = 0;
     self.scroll_column = column;
   }
 
-  pub fn scroll_into_view(&mut self, caret: Caret, buffer: &Buffer) {
+  // how lines are wrapped in the view, if they are
+  pub fn wrapping(&self, buffer: &Buffer, options: &Options)
+      -> Option<Wrapping> {
+    if !options.bool(Opt::Wrap) { return None; }
+    Some(Wrapping {
+      width: self.text_width(buffer, options),
+      linebreak: options.bool(Opt::LineBreak),
+    })
+  }
+
+  // the buffer columns at which the rows |line| is displayed in start
+  fn rows_of(&self, line: usize, buffer: &Buffer, options: &Options)
+      -> Vec<usize> {
+    match self.wrapping(buffer, options) {
+      Some(wrapping) => caret::display_rows(line, buffer, wrapping),
+      None           => vec!(0),
+    }
+  }
+
+  // The rows the line of the caret is displayed in. A caret past the end of a
+  // line filling its last row, as when appending, wraps to a row of its own.
+  fn rows_of_caret_line(&self, caret: Caret, buffer: &Buffer,
+                        options: &Options) -> Vec<usize> {
+    let line = caret.line();
+    let mut starts = self.rows_of(line, buffer, options);
+    let line_len = buffer.line_length(line).unwrap_or(0);
+    let last_start = *starts.last().unwrap();
+    let filled = self.wrapping(buffer, options).map(|wrapping|
+      caret::buffer_to_screen_column(line, line_len, buffer) -
+      caret::buffer_to_screen_column(line, last_start, buffer) >=
+      wrapping.width).unwrap_or(false);
+    if filled && caret.column() >= line_len && line_len > last_start {
+      starts.push(line_len);
+    }
+    starts
+  }
+
+  // the row the caret is displayed on
+  fn caret_row(&self, caret: Caret, buffer: &Buffer, options: &Options)
+      -> (usize, usize) {
+    let starts = self.rows_of_caret_line(caret, buffer, options);
+    (caret.line(),
+     starts.iter().rposition(|&start| start <= caret.column()).unwrap_or(0))
+  }
+
+  // the number of rows from row |from| down to row |to|, the latter excluded
+  fn rows_between(&self, from: (usize, usize), to: (usize, usize),
+                  buffer: &Buffer, options: &Options) -> usize {
+    let rows_above: usize = (from.0..to.0).
+      map(|line| self.rows_of(line, buffer, options).len()).sum();
+    rows_above + to.1 - from.1
+  }
+
+  // the row |count| rows above row |from|, or the first row of the buffer
+  fn rows_up(&self, from: (usize, usize), count: usize, buffer: &Buffer,
+             options: &Options) -> (usize, usize) {
+    let (mut line, mut row) = from;
+    for _ in 0..count {
+      if row > 0 { row -= 1; }
+      else if line > 0 {
+        line -= 1;
+        row = self.rows_of(line, buffer, options).len() - 1;
+      }
+      else { break; }
+    }
+    (line, row)
+  }
+
+  // the row |count| rows below row |from|, or the last row of the buffer
+  fn rows_down(&self, from: (usize, usize), count: usize, buffer: &Buffer,
+               options: &Options) -> (usize, usize) {
+    let last_line = cmp::max(buffer.num_lines(), 1) - 1;
+    let (mut line, mut row) = from;
+    let mut num_rows = self.rows_of(line, buffer, options).len();
+    for _ in 0..count {
+      if row + 1 < num_rows { row += 1; }
+      else if line < last_line {
+        line += 1;
+        row = 0;
+        num_rows = self.rows_of(line, buffer, options).len();
+      }
+      else { break; }
+    }
+    (line, row)
+  }
+
+  // whether row |row|, which is assumed not to be above the view, is in it
+  fn is_row_in_view(&self, row: (usize, usize), buffer: &Buffer,
+                    options: &Options) -> bool {
+    let screen::Size(rows, _) = self.size;
+    // each line is displayed in at least one row
+    row.0 < self.scroll_line + rows as usize &&
+    self.rows_between((self.scroll_line, self.scroll_row), row, buffer,
+                      options) < rows as usize
+  }
+
+  // Scrolls the view such that the caret is in it, along with as many rows
+  // above and below it as the scrolloff option of the window asks for.
+  pub fn scroll_into_view(&mut self, caret: Caret, buffer: &Buffer,
+                          options: &Options) {
     let (line, column) = (caret.line(), caret.column());
-    let screen::Size(rows, cols) = self.size;
+    let screen::Size(rows, _) = self.size;
     let rows = rows as usize;
-    let cols = cols as usize;
+    let cols = self.text_width(buffer, options);
+
+    // the first line may have fewer rows than it had
+    let scroll_rows = self.rows_of(self.scroll_line, buffer, options).len();
+    self.scroll_row = cmp::min(self.scroll_row, scroll_rows - 1);
+
+    let scroll_off = self.scroll_off(options);
+    let caret_row = self.caret_row(caret, buffer, options);
+    let top = self.rows_up(caret_row, scroll_off, buffer, options);
+    let bottom = self.rows_down(caret_row, scroll_off, buffer, options);
+    let (scroll_line, scroll_row) =
+      if top < (self.scroll_line, self.scroll_row) { top }
+      else if !self.is_row_in_view(bottom, buffer, options) {
+        self.rows_up(bottom, rows - 1, buffer, options)
+      }
+      else { (self.scroll_line, self.scroll_row) };
+    self.scroll_line = scroll_line;
+    self.scroll_row = scroll_row;
 
-    self.scroll_line =
-      if line < self.scroll_line { line }
-      else if line >= self.scroll_line + rows { line - rows + 1 }
-      else { self.scroll_line };
+    // wrapped lines aren't scrolled horizontally
+    if self.wrapping(buffer, options).is_some() {
+      self.scroll_column = 0;
+      return;
+    }
 
-    // make sure wider characters are scrolled in entirely as well
+    // make sure wider characters, such as tabs, are scrolled in entirely
     let start = caret::buffer_to_screen_column(line, column, buffer);
-    let end = start + buffer.get_char_by_line_column(line, column).and_then(|c|
-      CharWidth::width(c)).unwrap_or(1) - 1;
+    let tab_stop = buffer.options().number(Opt::TabStop);
+    let width = buffer.get_char_by_line_column(line, column).map(|c|
+      caret::char_width(c, start, tab_stop)).unwrap_or(1);
+    let end = start + cmp::max(width, 1) - 1;
     self.scroll_column =
       if start < self.scroll_column { start }
       else if end >= self.scroll_column + cols { end - cols + 1 }
       else { self.scroll_column };
   }
 
-  pub fn line_clamped_to_view(&self, line: usize) -> usize {
+  // Clamps |line| to the lines starting in the view, keeping clear of the rows
+  // at its edges which the scrolloff option asks for, unless at the top or the
+  // bottom of the buffer.
+  pub fn line_clamped_to_view(&self, line: usize, buffer: &Buffer,
+                              options: &Options) -> usize {
     let screen::Size(rows, _) = self.size;
     assert!(rows >= MIN_VIEW_SIZE);
-    let last_line = self.scroll_line + rows as usize - 1;
-    cmp::min(cmp::max(line, self.scroll_line), last_line)
+    let scroll_off = self.scroll_off(options);
+    let top = (self.scroll_line, self.scroll_row);
+    let first = if top > (0, 0) {
+      self.rows_down(top, scroll_off, buffer, options)
+    } else { top };
+    let bottom = self.rows_down(top, rows as usize - 1, buffer, options);
+    let last = if self.rows_down(bottom, 1, buffer, options) != bottom {
+      self.rows_up(bottom, scroll_off, buffer, options)
+    } else { bottom };
+    // a line isn't in the view unless its first row is
+    let first_line = if first.1 > 0 && first.0 < last.0 { first.0 + 1 }
+                     else                              { first.0 };
+    cmp::min(cmp::max(line, first_line), last.0)
+  }
+
+  // the scrolloff option, limited to what fits in the view
+  fn scroll_off(&self, options: &Options) -> usize {
+    let screen::Size(rows, _) = self.size;
+    cmp::min(options.number(Opt::ScrollOff), (rows as usize - 1) / 2)
   }
 
   pub fn set_size(&mut self, size: screen::Size) {
@@ -96,59 +283,202 @@ impl View {
     self.size = size;
   }
 
+  // Draws the visible part of the buffer. The selection, if any, is
+  // highlighted, with a selected newline shown as a single highlighted cell.
+  // So are the matches of the search, if any.
   #[cfg(not(test))]
-  pub fn draw(&self, buffer: &Buffer, caret: Caret, focused: bool,
+  pub fn draw(&self, buffer: &Buffer, caret: Caret,
+              selection: Option<Selection>, search: Option<&Search>,
+              options: &Options, highlights: &Highlights, focused: bool,
               position: screen::Cell, screen: &mut Screen) {
     // calculate caret screen position if focused
-    let caret_cell =
-      if focused { Some(position + self.caret_position(caret, buffer)) }
-      else       { None };
+    let caret_cell = if focused {
+      Some(position + self.caret_position(caret, buffer, options))
+    } else { None };
+    let normal = highlights.style(if focused { Group::Normal }
+                                  else       { Group::NormalNC });
+    let cursor_line = if options.bool(Opt::CursorLine) {
+      highlights.style_over(Group::CursorLine, normal)
+    } else { normal };
 
-    // helper to put a character on the screen
-    let put = |character, cell: screen::Cell, screen: &mut Screen| {
-      use screen::Color::*;
+    // helper to put a character on the screen in the style of its highlight
+    // group, drawn on the style of its line
+    let put = |character, cell: screen::Cell, selected, matched,
+               group: Option<Group>, line_style: screen::Style,
+               screen: &mut Screen| {
       let highlight = caret_cell.map(|c| c != cell).unwrap_or(false);
-      let (fg, bg) = if highlight { (Black, White) } else { (White, Black) };
-      screen.put(cell, character, fg, bg);
+      let over = |group| highlights.style_over(group, line_style);
+      let style = if caret_cell == Some(cell)    { over(Group::Cursor) }
+                  else if highlight && selected { over(Group::Visual) }
+                  else if highlight && matched  { over(Group::Search) }
+                  else { group.map(&over).unwrap_or(line_style) };
+      screen.put(cell, character, style);
     };
 
     let screen::Size(rows, cols) = self.size;
-    // draw line by line
+    let tab_stop = buffer.options().number(Opt::TabStop);
+    let wrapping = self.wrapping(buffer, options).is_some();
+    let gutter = self.gutter_width(buffer, options) as u16;
+    let number_width = self.number_width(buffer, options);
+    let text_position = position + screen::Cell(0, gutter);
+    let cols = cols - gutter;
+
+    // helper to put the gutter of a row on the screen, with the number of
+    // |line| if it's the first row of the line
+    let put_gutter = |line: Option<usize>, row: u16, screen: &mut Screen| {
+      let number = match (line, number_width) {
+        (Some(line), Some(width)) => {
+          let relative = if line > caret.line() { line - caret.line() }
+                         else                   { caret.line() - line };
+          match (options.bool(Opt::Number),
+                 options.bool(Opt::RelativeNumber)) {
+            // the caret line is numbered absolutely when both are set
+            (true, true) if relative == 0 =>
+              format!("{:<1$} ", line + 1, width),
+            (_, true)                     =>
+              format!("{:>1$} ", relative, width),
+            _                             =>
+              format!("{:>1$} ", line + 1, width),
+          }
+        }
+        _                         => String::new(),
+      };
+      let signs = options.number(Opt::SignColumn);
+      let mut label = (0..signs).map(|_| ' ').chain(number.chars());
+      for col in 0..gutter {
+        screen.put(position + screen::Cell(row, col),
+                   label.next().unwrap_or(' '),
+                   highlights.style_over(Group::LineNr, normal));
+      }
+    };
+
+    // draw line by line, and each line row by row
     let mut row: u16 = 0;
-    for chars in buffer.line_iter().from(self.scroll_line).take(rows as usize) {
-      let line_offset = screen::Cell(row, 0) + position;
-      // draw character by character
-      let mut col = -(self.scroll_column as isize);
-      for character in chars {
-        if col >= cols as isize || character == '\n' { break }
-        let char_width = CharWidth::width(character).unwrap_or(0) as isize;
-        let end_col = col + char_width;
-        if (col < 0 && end_col >= 0) || end_col > cols as isize {
-          // blank out partially visible characters
-          for col in cmp::max(0, col)..cmp::min(end_col, cols as isize) {
-            put(' ', line_offset + screen::Cell(0, col as u16), screen);
+    let mut skipped_rows = self.scroll_row;
+    for (i, chars) in buffer.line_iter().from(self.scroll_line).enumerate() {
+      if row >= rows { break }
+      let line = self.scroll_line + i;
+      let chars: Vec<char> = chars.take_while(|&c| c != '\n').collect();
+      let starts = if line == caret.line() {
+        self.rows_of_caret_line(caret, buffer, options)
+      } else { self.rows_of(line, buffer, options) };
+      let selected_columns = selection.and_then(|selection|
+        selection.columns_on_line(line, buffer));
+      let is_selected = |column| selected_columns.map(|(start, end)|
+        column >= start && column < end).unwrap_or(false);
+      let matches = search.map(|search|
+        search.matches_on_line(line, buffer)).unwrap_or(Vec::new());
+      let is_matched = |column| matches.iter().any(|&(start, end)|
+        column >= start && column < end);
+      let groups = buffer.highlights_on_line(line);
+      let group_at = |column| groups.get(column).cloned().unwrap_or(None);
+      let line_style = if line == caret.line() { cursor_line } else { normal };
+      let mut screen_col = 0;  // of the character drawn next
+      for (line_row, &start) in starts.iter().enumerate() {
+        if row >= rows { break }
+        let end = starts.get(line_row + 1).cloned().unwrap_or(chars.len());
+        // rows above the view are only measured
+        if skipped_rows > 0 {
+          skipped_rows -= 1;
+          screen_col = chars[start..end].iter().fold(screen_col, |col, &c|
+            col + caret::char_width(c, col, tab_stop));
+          continue;
+        }
+        put_gutter(if line_row == 0 { Some(line) } else { None }, row, screen);
+        let line_offset = screen::Cell(row, 0) + text_position;
+        // draw character by character
+        let mut col = if wrapping { 0 } else { -(self.scroll_column as isize) };
+        for column in start..end {
+          if col >= cols as isize { break }
+          let character = chars[column];
+          let char_width =
+            caret::char_width(character, screen_col, tab_stop) as isize;
+          let end_col = col + char_width;
+          let selected = is_selected(column);
+          let matched = is_matched(column);
+          let group = group_at(column);
+          if (col < 0 && end_col >= 0) || end_col > cols as isize ||
+             character == '\t' {
+            // blank out tabs and partially visible characters
+            for col in cmp::max(0, col)..cmp::min(end_col, cols as isize) {
+              put(' ', line_offset + screen::Cell(0, col as u16), selected,
+                  matched, group, line_style, screen);
+            }
+          }
+          else if col >= 0 {
+            put(character, line_offset + screen::Cell(0, col as u16),
+                selected, matched, group, line_style, screen);
           }
+          col += char_width;
+          screen_col += char_width as usize;
         }
-        else if col >= 0 {
-          put(character, line_offset + screen::Cell(0, col as u16), screen);
+        // blank out the rest of the row if the line didn't fill it, the first
+        // cell of the last row being where the newline is
+        let is_last_row = line_row + 1 == starts.len();
+        let newline_selected = is_last_row && col >= 0 && is_selected(end);
+        let newline_col = cmp::max(0, col) as u16;
+        for col in newline_col..cols {
+          put(' ', line_offset + screen::Cell(0, col),
+              newline_selected && col == newline_col, false, None, line_style,
+              screen);
         }
-        col += char_width;
+        row += 1;
       }
-      // blank out the rest of the row if the line didn't fill it
-      for col in cmp::max(0, col) as u16..cols {
-        put(' ', line_offset + screen::Cell(0, col), screen);
-      }
-      row += 1;
     }
     // fill in the rest of the view below the buffer content
     for row in row..rows {
       let line_offset = screen::Cell(row, 0) + position;
-      put(if self.scroll_column == 0 { '~' } else { ' ' }, line_offset, screen);
-      for col in 1..cols {
-        put(' ', line_offset + screen::Cell(0, col), screen);
+      put(if self.scroll_column == 0 { '~' } else { ' ' }, line_offset, false,
+          false, Some(Group::NonText), normal, screen);
+      for col in 1..cols + gutter {
+        put(' ', line_offset + screen::Cell(0, col), false, false, None,
+            normal, screen);
       }
     }
   }
+
+  // Draws a status line on the row right below the view. It shows the path of
+  // the buffer and whether it's modified on the left, and the mode name along
+  // with the caret location on the right.
+  #[cfg(not(test))]
+  pub fn draw_status(&self, buffer: &Buffer, caret: Caret, mode: &str,
+                     highlights: &Highlights, focused: bool,
+                     position: screen::Cell, screen: &mut Screen) {
+    let style = highlights.style(if focused { Group::StatusLine }
+                                 else       { Group::StatusLineNC });
+    let screen::Size(rows, cols) = self.size;
+    let cols = cols as usize;
+    let row_offset = position + screen::Cell(rows, 0);
+
+    let name = buffer.path().map(|path| path.to_string_lossy().into_owned()).
+      unwrap_or("[No Name]".to_string());
+    let modified = if buffer.is_modified() { " [+]" } else { "" };
+    let left = format!(" {}{}", name, modified);
+    let right =
+      format!("{}  {}:{} ", mode, caret.line() + 1, caret.column() + 1);
+
+    // the right part is kept whole, the left part is cut if it doesn't fit
+    let width = |string: &str| string.chars().
+      map(|c| CharWidth::width(c).unwrap_or(0)).fold(0, |a, b| a + b);
+    let right_start = cols.saturating_sub(width(&right));
+    let mut col = 0;
+    for character in left.chars() {
+      let char_width = CharWidth::width(character).unwrap_or(0);
+      if col + char_width >= right_start { break }
+      screen.put(row_offset + screen::Cell(0, col as u16), character, style);
+      col += char_width;
+    }
+    for col in col..right_start {
+      screen.put(row_offset + screen::Cell(0, col as u16), ' ', style);
+    }
+    col = right_start;
+    for character in right.chars() {
+      let char_width = CharWidth::width(character).unwrap_or(0);
+      if col + char_width > cols { break }
+      screen.put(row_offset + screen::Cell(0, col as u16), character, style);
+      col += char_width;
+    }
+  }
 }
 
 #[cfg(test)]
@@ -158,6 +488,7 @@ mod test {
   use buffer::Buffer;
   use caret;
   use caret::Caret;
+  use options::{Opt, Options, Value};
   use screen;
 
   use super::*;
@@ -168,19 +499,20 @@ mod test {
     let buffer = Buffer::open(
       &Path::new("tests/view/scroll_into_view_double_width.txt")).unwrap();
     let mut view = View::new();
+    let options = Options::new();
     view.set_size(screen::Size(1, 15));
     assert_eq!(view.scroll_line(), 0); assert_eq!(view.scroll_column(), 0);
     caret.adjust(caret::Adjustment::Set(0, 12), &buffer);
-    view.scroll_into_view(caret, &buffer);
+    view.scroll_into_view(caret, &buffer, &options);
     assert_eq!(view.scroll_line(), 0); assert_eq!(view.scroll_column(), 3);
     caret.adjust(caret::Adjustment::Set(0, 16), &buffer);
-    view.scroll_into_view(caret, &buffer);
+    view.scroll_into_view(caret, &buffer, &options);
     assert_eq!(view.scroll_line(), 0); assert_eq!(view.scroll_column(), 9);
     caret.adjust(caret::Adjustment::Set(0, 3), &buffer);
-    view.scroll_into_view(caret, &buffer);
+    view.scroll_into_view(caret, &buffer, &options);
     assert_eq!(view.scroll_line(), 0); assert_eq!(view.scroll_column(), 6);
     caret.adjust(caret::Adjustment::Set(3, 10), &buffer);
-    view.scroll_into_view(caret, &buffer);
+    view.scroll_into_view(caret, &buffer, &options);
     assert_eq!(view.scroll_line(), 3); assert_eq!(view.scroll_column(), 6);
   }
 
@@ -190,20 +522,186 @@ mod test {
     let buffer = Buffer::open(
       &Path::new("tests/view/caret_position.txt")).unwrap();
     let mut view = View::new();
+    let options = Options::new();
     view.set_scroll(1, 1);
     caret.adjust(caret::Adjustment::Set(1, 1), &buffer);
-    assert_eq!(view.caret_position(caret, &buffer), screen::Cell(0, 1));
+    assert_eq!(view.caret_position(caret, &buffer, &options),
+               screen::Cell(0, 1));
     caret.adjust(caret::Adjustment::Set(2, 1), &buffer);
-    assert_eq!(view.caret_position(caret, &buffer), screen::Cell(1, 0));
+    assert_eq!(view.caret_position(caret, &buffer, &options),
+               screen::Cell(1, 0));
+  }
+
+  fn wrapped_buffer() -> Buffer {
+    let mut buffer = Buffer::new();
+    buffer.insert_at_line_column(
+      "aaaa bbbb cccc\nx\ndddddddddd\ny\ny\n".to_string(), 0, 0).unwrap();
+    buffer
+  }
+
+  #[test]
+  fn wrapped_caret_position_and_scrolling() {
+    let mut caret = Caret::new();
+    let buffer = wrapped_buffer();
+    let mut view = View::new();
+    let mut options = Options::new();
+    options.set(Opt::Wrap, Value::Bool(true));
+    view.set_size(screen::Size(3, 6));
+    caret.adjust(caret::Adjustment::Set(0, 8), &buffer);
+    assert_eq!(view.caret_position(caret, &buffer, &options),
+               screen::Cell(1, 2));
+    caret.adjust(caret::Adjustment::Set(0, 13), &buffer);
+    assert_eq!(view.caret_position(caret, &buffer, &options),
+               screen::Cell(2, 1));
+    // scrolling is by row
+    caret.adjust(caret::Adjustment::Set(2, 7), &buffer);
+    view.scroll_into_view(caret, &buffer, &options);
+    assert_eq!(view.scroll_line(), 1);
+    assert_eq!(view.caret_position(caret, &buffer, &options),
+               screen::Cell(2, 1));
+    caret.adjust(caret::Adjustment::Set(0, 2), &buffer);
+    view.scroll_into_view(caret, &buffer, &options);
+    assert_eq!(view.scroll_line(), 0);
+    // the view may start in the middle of a line
+    options.set(Opt::ScrollOff, Value::Number(1));
+    caret.adjust(caret::Adjustment::Set(0, 13), &buffer);
+    view.scroll_into_view(caret, &buffer, &options);
+    assert_eq!(view.scroll_line(), 0);
+    assert_eq!(view.caret_position(caret, &buffer, &options),
+               screen::Cell(1, 1));
+    // a line isn't in the view unless its first row is
+    options.set(Opt::ScrollOff, Value::Number(0));
+    assert_eq!(view.line_clamped_to_view(0, &buffer, &options), 1);
+    assert_eq!(view.line_clamped_to_view(4, &buffer, &options), 1);
+  }
+
+  #[test]
+  fn wrapped_caret_past_filled_row() {
+    let mut caret = Caret::new();
+    let buffer = wrapped_buffer();
+    let mut view = View::new();
+    let mut options = Options::new();
+    options.set(Opt::Wrap, Value::Bool(true));
+    view.set_size(screen::Size(3, 5));
+    view.set_scroll(2, 0);
+    caret.adjust(caret::Adjustment::Set(2, 9), &buffer);
+    assert_eq!(view.caret_position(caret, &buffer, &options),
+               screen::Cell(1, 4));
+    // past the end of the line, the caret wraps to the start of the next row
+    caret.adjust(caret::Adjustment::Set(2, 10), &buffer);
+    assert_eq!(view.caret_position(caret, &buffer, &options),
+               screen::Cell(2, 0));
+    // and is scrolled into view there
+    view.set_size(screen::Size(2, 5));
+    view.scroll_into_view(caret, &buffer, &options);
+    assert_eq!(view.caret_position(caret, &buffer, &options),
+               screen::Cell(1, 0));
+    // a line not filling its last row has the caret at its end
+    caret.adjust(caret::Adjustment::Set(0, 14), &buffer);
+    view.scroll_into_view(caret, &buffer, &options);
+    assert_eq!(view.caret_position(caret, &buffer, &options),
+               screen::Cell(0, 4));
+  }
+
+  #[test]
+  fn scroll_into_view_tabs() {
+    let mut caret = Caret::new();
+    let mut buffer = Buffer::new();
+    buffer.insert_at_line_column("\tab\tc".to_string(), 0, 0).unwrap();
+    buffer.options_mut().set(Opt::TabStop, Value::Number(4));
+    let mut view = View::new();
+    let options = Options::new();
+    view.set_size(screen::Size(1, 5));
+    // tabs are scrolled in entirely
+    caret.adjust(caret::Adjustment::Set(0, 3), &buffer);
+    view.scroll_into_view(caret, &buffer, &options);
+    assert_eq!(view.scroll_column(), 3);
+    caret.adjust(caret::Adjustment::Set(0, 0), &buffer);
+    view.scroll_into_view(caret, &buffer, &options);
+    assert_eq!(view.scroll_column(), 0);
+    caret.adjust(caret::Adjustment::Set(0, 4), &buffer);
+    view.scroll_into_view(caret, &buffer, &options);
+    assert_eq!(view.scroll_column(), 4);
+  }
+
+  #[test]
+  fn scroll_into_view_scroll_off() {
+    let mut caret = Caret::new();
+    let mut buffer = Buffer::new();
+    buffer.insert_at_line_column(
+      ::std::iter::repeat("line\n").take(19).collect(), 0, 0).unwrap();
+    let mut view = View::new();
+    let mut options = Options::new();
+    options.set(Opt::ScrollOff, Value::Number(2));
+    view.set_size(screen::Size(5, 5));
+    caret.adjust(caret::Adjustment::Set(3, 0), &buffer);
+    view.scroll_into_view(caret, &buffer, &options);
+    assert_eq!(view.scroll_line(), 1);
+    caret.adjust(caret::Adjustment::Set(10, 0), &buffer);
+    view.scroll_into_view(caret, &buffer, &options);
+    assert_eq!(view.scroll_line(), 8);
+    caret.adjust(caret::Adjustment::Set(9, 0), &buffer);
+    view.scroll_into_view(caret, &buffer, &options);
+    assert_eq!(view.scroll_line(), 7);
+    // no lines are kept below the last one of the buffer
+    caret.adjust(caret::Adjustment::Set(19, 0), &buffer);
+    view.scroll_into_view(caret, &buffer, &options);
+    assert_eq!(view.scroll_line(), 15);
+    // scrolloff is limited to what fits in the view
+    options.set(Opt::ScrollOff, Value::Number(10));
+    caret.adjust(caret::Adjustment::Set(5, 0), &buffer);
+    view.scroll_into_view(caret, &buffer, &options);
+    assert_eq!(view.scroll_line(), 3);
+  }
+
+  #[test]
+  fn gutter() {
+    let mut caret = Caret::new();
+    let mut buffer = Buffer::new();
+    buffer.insert_at_line_column(
+      ::std::iter::repeat("line\n").take(19).collect(), 0, 0).unwrap();
+    let mut view = View::new();
+    let mut options = Options::new();
+    view.set_size(screen::Size(5, 8));
+    assert_eq!(view.gutter_width(&buffer, &options), 0);
+    options.set(Opt::RelativeNumber, Value::Bool(true));
+    assert_eq!(view.gutter_width(&buffer, &options), 4);
+    options.set(Opt::SignColumn, Value::Number(2));
+    assert_eq!(view.gutter_width(&buffer, &options), 6);
+    // the caret is right of the gutter, and the text scrolled in what's left
+    caret.adjust(caret::Adjustment::Set(0, 1), &buffer);
+    assert_eq!(view.caret_position(caret, &buffer, &options),
+               screen::Cell(0, 7));
+    caret.adjust(caret::Adjustment::Set(0, 3), &buffer);
+    view.scroll_into_view(caret, &buffer, &options);
+    assert_eq!(view.scroll_column(), 2);
+    // numbers are given room as the buffer grows, leaving a column for text
+    buffer.insert_at_line_column(
+      ::std::iter::repeat("line\n").take(1000).collect(), 0, 0).unwrap();
+    options.set(Opt::SignColumn, Value::Number(0));
+    assert_eq!(view.gutter_width(&buffer, &options), 5);
+    view.set_size(screen::Size(5, 4));
+    assert_eq!(view.gutter_width(&buffer, &options), 3);
   }
 
   #[test]
   fn line_clamped_to_view() {
+    let mut buffer = Buffer::new();
+    buffer.insert_at_line_column(
+      ::std::iter::repeat("line\n").take(19).collect(), 0, 0).unwrap();
     let mut view = View::new();
+    let mut options = Options::new();
     view.set_size(screen::Size(5, 5));
     view.set_scroll(5, 5);
-    assert_eq!(view.line_clamped_to_view(1), 5);
-    assert_eq!(view.line_clamped_to_view(7), 7);
-    assert_eq!(view.line_clamped_to_view(10), 9);
+    assert_eq!(view.line_clamped_to_view(1, &buffer, &options), 5);
+    assert_eq!(view.line_clamped_to_view(7, &buffer, &options), 7);
+    assert_eq!(view.line_clamped_to_view(10, &buffer, &options), 9);
+    options.set(Opt::ScrollOff, Value::Number(1));
+    assert_eq!(view.line_clamped_to_view(1, &buffer, &options), 6);
+    assert_eq!(view.line_clamped_to_view(10, &buffer, &options), 8);
+    view.set_scroll(0, 0);
+    assert_eq!(view.line_clamped_to_view(0, &buffer, &options), 0);
+    view.set_scroll(16, 0);
+    assert_eq!(view.line_clamped_to_view(19, &buffer, &options), 19);
   }
 }
@@ -0,0 +1,12 @@
+#!/bin/sh
+#
+# A fake clipboard provider for the tests, keeping each selection in a file.
+#
+# Usage: fake-clipboard.sh copy|paste <selection>
+
+file="tests/clipboard/$2-result.txt"
+case "$1" in
+  copy)  cat > "$file" ;;
+  paste) cat "$file" ;;
+  *)     exit 1 ;;
+esac